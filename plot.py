@@ -17,19 +17,37 @@ colors = {
     'large.dat': 'red',
 }
 
+linestyles = {
+    'file': '-',
+    'mmap': '--',
+}
+
+markers = {
+    'btree': 'o',
+    'hash': 's',
+}
+
 for method_col, method_label in methods:
     plt.figure(figsize=(10,6))
 
     for file in df['file'].unique():
-        subset = df[df['file'] == file]
-        plt.plot(subset['t'], subset[method_col], marker='o', label=f'{file}', color=colors.get(file, None))
+        for backend in df['backend'].unique():
+            for index in df['index'].unique():
+                subset = df[(df['file'] == file) & (df['backend'] == backend) & (df['index'] == index)]
+                if subset.empty:
+                    continue
+                plt.plot(subset['page_size'], subset[method_col], marker=markers.get(index, 'o'),
+                         label=f'{file} ({backend}, {index})', color=colors.get(file, None),
+                         linestyle=linestyles.get(backend, '-'))
 
     plt.title(f'Benchmark - {method_label}')
-    plt.xlabel('t (degree of B-Tree)')
+    plt.xlabel('Page size (bytes)')
+    plt.xscale('log', base=2)
     plt.ylabel('Time (seconds)')
     plt.grid(True)
     plt.legend()
-    plt.xticks(subset['t'])
+    page_sizes = sorted(df['page_size'].unique())
+    plt.xticks(page_sizes, page_sizes)
     plt.savefig(f'static/{method_col}_benchmark.png')
     plt.show()
 
@@ -0,0 +1,269 @@
+use rustdb::{Backend, Durability, Error, IndexKind, Result, TableOptions};
+use std::fs::{OpenOptions, remove_file};
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+
+struct Dataset {
+    file: &'static str,
+    recordsize: u16,
+    keysize: u16,
+    sample_key: &'static [u8],
+    add_key: &'static [u8],
+    add_record: &'static [u8],
+    update_record: &'static [u8],
+    // Whether to run the hash index against the B-tree too.
+    compare_hash: bool,
+}
+
+struct BenchmarkResult {
+    file: String,
+    backend: Backend,
+    index: IndexKind,
+    page_size: u32,
+    t: u32,
+    load_time: f64,
+    search_time: f64,
+    add_time: f64,
+    update_time: f64,
+    search_after_update_time: f64,
+}
+
+fn backend_name(backend: Backend) -> &'static str {
+    match backend {
+        Backend::File => "file",
+        Backend::Mmap => "mmap",
+    }
+}
+
+fn benchmark_table(dataset: &Dataset, page_size: u32, backend: Backend, index: IndexKind) -> Result<BenchmarkResult> {
+    let Dataset {
+        file: datafile,
+        recordsize,
+        keysize,
+        sample_key,
+        add_key,
+        add_record,
+        update_record,
+        ..
+    } = *dataset;
+    let static_path = format!("static/{}", datafile);
+
+    println!(
+        "Benchmarking file: {} with page size = {} ({} backend, {} index)\n",
+        static_path,
+        page_size,
+        backend_name(backend),
+        index
+    );
+
+    let indexfile = format!("static/{}.p{}.{}.{}.ndx", datafile, page_size, backend_name(backend), index);
+    if Path::new(&indexfile).exists() {
+        remove_file(&indexfile)?;
+    }
+
+    let tmp_datafile = format!("static/{}.p{}.{}.{}.dat", datafile, page_size, backend_name(backend), index);
+    if Path::new(&tmp_datafile).exists() {
+        remove_file(&tmp_datafile)?;
+    }
+
+    let mut options = TableOptions::new();
+    options
+        .record_size(recordsize)
+        .key_size(keysize)
+        .page_size(page_size)
+        .index_kind(index)
+        .backend(backend)
+        .index_path(&indexfile);
+
+    // The static datasets are bare key + record entries. Load them through a table once, then
+    // drop its index so the timed open below rebuilds the index from the datafile.
+    {
+        let mut table = options.clone().durability(Durability::None).open(&tmp_datafile)?;
+        let records = std::fs::read(&static_path)?;
+        for entry in records.chunks_exact(keysize as usize + recordsize as usize) {
+            let (key, record) = entry.split_at(keysize as usize);
+            match table.add_record(key, record) {
+                Ok(()) | Err(Error::DuplicateKey) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    remove_file(&indexfile)?;
+
+    let start = Instant::now();
+    let mut table = options.open(&tmp_datafile)?;
+    let load_duration = start.elapsed();
+    let t = table.stats()?.t;
+    match table.hash_index() {
+        Some(hash) => println!(
+            "Load/Create Table (global depth = {}): {:.4?}",
+            hash.global_depth(),
+            load_duration
+        ),
+        None => println!("Load/Create Table (t = {}): {:.4?}", t, load_duration),
+    }
+
+    let start = Instant::now();
+    match table.search_record(sample_key)? {
+        Some(_) => println!("Search for key {:?}: Found", sample_key),
+        None => println!("Search for key {:?}: Not found", sample_key),
+    }
+    let search_duration = start.elapsed();
+    println!("Search Time: {:.4?}", search_duration);
+
+    let start = Instant::now();
+    table.add_record(add_key, add_record)?;
+    let add_duration = start.elapsed();
+    println!("Add Record Time: {:.4?}", add_duration);
+
+    let start = Instant::now();
+    table.update_record(add_key, update_record)?;
+    let update_duration = start.elapsed();
+    println!("Record for key updated successfully.");
+    println!("Update Record Time: {:.4?}", update_duration);
+
+    let start = Instant::now();
+    let found = table.search_record(add_key)?;
+    let search_after_update_duration = start.elapsed();
+
+    if let Some(val) = found {
+        let trimmed_val = &val[..update_record.len()];
+        if trimmed_val != update_record {
+            panic!(
+                "Error: Updated record does not match expected!\nExpected: \n{:?} got\n{:?}",
+                update_record, trimmed_val
+            );
+        }
+    } else {
+        panic!("Error: Updated key not found!");
+    }
+
+    println!("Search after update Time: {:.4?}", search_after_update_duration);
+    println!("----------------------------------------\n");
+
+    drop(table);
+    remove_file(&tmp_datafile)?;
+    remove_file(&indexfile)?;
+
+    Ok(BenchmarkResult {
+        file: datafile.to_string(),
+        backend,
+        index,
+        page_size,
+        t,
+        load_time: load_duration.as_secs_f64(),
+        search_time: search_duration.as_secs_f64(),
+        add_time: add_duration.as_secs_f64(),
+        update_time: update_duration.as_secs_f64(),
+        search_after_update_time: search_after_update_duration.as_secs_f64(),
+    })
+}
+
+fn main() -> Result<()> {
+    let mut results_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open("static/results.csv")?;
+
+    writeln!(
+        results_file,
+        "file,backend,index,page_size,t,load_time,search_time,add_time,update_time,search_after_update_time"
+    )?;
+
+    let datasets = [
+        Dataset {
+            file: "small.dat",
+            recordsize: 31,
+            keysize: 1,
+            sample_key: b"G",
+            add_key: b"Z",
+            add_record: b"new small record ............",
+            update_record: b"updated small record ........",
+            compare_hash: true,
+        },
+        Dataset {
+            file: "medium.dat",
+            recordsize: 80,
+            keysize: 4,
+            sample_key: b"2499",
+            add_key: b"9999",
+            add_record: b"new medium record ....................data here...................",
+            update_record: b"updated medium record .................new data here...............",
+            compare_hash: true,
+        },
+        Dataset {
+            file: "large.dat",
+            recordsize: 102,
+            keysize: 7,
+            sample_key: b"1000942",
+            add_key: b"9999999",
+            add_record: b"new large record .....................................................extra data here..........",
+            update_record: b"updated large record ..................................................updated extra data.......",
+            compare_hash: false,
+        },
+    ];
+
+    let page_sizes = [256, 1024, 4096, 16384];
+    let backends = [Backend::File, Backend::Mmap];
+
+    let mut results = Vec::new();
+
+    for dataset in &datasets {
+        if !Path::new(&format!("static/{}", dataset.file)).exists() {
+            eprintln!("warning: skipping {}: file not found in static/, no rows written for it", dataset.file);
+            continue;
+        }
+
+        let indexes: &[IndexKind] =
+            if dataset.compare_hash { &[IndexKind::BTree, IndexKind::Hash] } else { &[IndexKind::BTree] };
+        for &index in indexes {
+            for &backend in &backends {
+                for &page_size in &page_sizes {
+                    results.push(benchmark_table(dataset, page_size, backend, index)?);
+                }
+            }
+        }
+    }
+
+    for r in results.iter() {
+        writeln!(
+            results_file,
+            "{},{},{},{},{},{:.6},{:.6},{:.6},{:.6},{:.6}",
+            r.file,
+            backend_name(r.backend),
+            r.index,
+            r.page_size,
+            r.t,
+            r.load_time,
+            r.search_time,
+            r.add_time,
+            r.update_time,
+            r.search_after_update_time
+        )?;
+    }
+
+    println!("Benchmark finished. Results saved to static/results.csv!");
+
+    println!("\nSummary:");
+    println!("{:<12} {:<8} {:<6} {:<6} {:<4} {:<10} {:<10} {:<10} {:<10} {:<10}",
+             "File", "Backend", "Index", "Page", "t", "Load(s)", "Search(s)", "Add(s)", "Update(s)", "Search2(s)");
+
+    for r in &results {
+        println!("{:<12} {:<8} {:<6} {:<6} {:<4} {:<10.6} {:<10.6} {:<10.6} {:<10.6} {:<10.6}",
+                 r.file,
+                 backend_name(r.backend),
+                 r.index.to_string(),
+                 r.page_size,
+                 r.t,
+                 r.load_time,
+                 r.search_time,
+                 r.add_time,
+                 r.update_time,
+                 r.search_after_update_time,
+        );
+    }
+
+    Ok(())
+}
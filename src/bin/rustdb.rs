@@ -0,0 +1,370 @@
+use rustdb::{Compression, Engine, Error, IndexKind, Table, TableOptions};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: rustdb [--hex]                                   start an interactive shell
+       rustdb [--hex] shell <path>                      start a shell on an existing table
+       rustdb [--hex] create <path> <keysize> <recordsize> [page_size] [none|lz4] [btree|hash|lsm]
+       rustdb [--hex] <command> <path> [args...]        run one command against a table";
+
+const HELP: &str = "\
+commands:
+  create <path> <keysize> <recordsize> [page_size] [none|lz4] [btree|hash|lsm]
+                                                      create a table and open it
+  open <path>                                         open an existing table
+  close                                               close the open table
+  get <key>                                           print the record stored under key
+  put <key> <record>                                  add a new record
+  update <key> <record>                               overwrite an existing record
+  delete <key>                                        delete a record
+  scan [limit]                                        print records in key order
+  count                                               print the number of records
+  min                                                 print the smallest key
+  max                                                 print the largest key
+  stats                                               print sizes and counters
+  verify                                              check the index against the datafile
+  mode [utf8|hex]                                     show or set how keys and records are printed
+  help                                                show this help
+  quit                                                leave the shell
+
+Keys and records are taken as UTF-8 text, or as hex when prefixed with 0x.
+Quote arguments that contain spaces.";
+
+// Mistakes in what the user typed are reported as-is; everything else comes from the table.
+#[derive(Debug)]
+enum CliError {
+    Usage(String),
+    Db(Error),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(msg) => write!(f, "{}", msg),
+            CliError::Db(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<Error> for CliError {
+    fn from(err: Error) -> Self {
+        CliError::Db(err)
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(err: io::Error) -> Self {
+        CliError::Db(Error::Io(err))
+    }
+}
+
+type Result<T> = std::result::Result<T, CliError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Utf8,
+    Hex,
+}
+
+struct Shell {
+    table: Option<Table>,
+    mode: Mode,
+}
+
+impl Shell {
+    fn table(&mut self) -> Result<&mut Table> {
+        self.table
+            .as_mut()
+            .ok_or_else(|| CliError::Usage("no table is open; use `open <path>` first".to_string()))
+    }
+
+    // Returns false once the shell should exit.
+    fn execute(&mut self, args: &[String]) -> Result<bool> {
+        let Some((command, args)) = args.split_first() else {
+            return Ok(true);
+        };
+
+        match (command.as_str(), args) {
+            ("create", [path, keysize, recordsize, rest @ ..]) if rest.len() <= 3 => {
+                let mut options = TableOptions::new();
+                options.key_size(parse_number(keysize)?).record_size(parse_number(recordsize)?);
+                if let Some(page_size) = rest.first() {
+                    options.page_size(parse_number(page_size)?);
+                }
+                if let Some(compression) = rest.get(1) {
+                    options.compression(compression.parse::<Compression>()?);
+                }
+                match rest.get(2).map(String::as_str) {
+                    Some("lsm") => {
+                        options.engine(Engine::Lsm);
+                    }
+                    Some(index_kind) => {
+                        options.index_kind(index_kind.parse::<IndexKind>()?);
+                    }
+                    None => {}
+                }
+                if std::path::Path::new(path).exists() {
+                    return Err(Error::TableExists(path.clone()).into());
+                }
+                self.table = None;
+                self.table = Some(options.open(path)?);
+            }
+            ("open", [path]) => {
+                self.table = None;
+                self.table = Some(Table::open(path)?);
+            }
+            ("close", []) => self.table = None,
+            ("get", [key]) => {
+                let key = parse_bytes(key)?;
+                let mode = self.mode;
+                match self.table()?.search_record(&key)? {
+                    Some(record) => println!("{}", display(&record, mode)),
+                    None => return Err(Error::KeyNotFound.into()),
+                }
+            }
+            ("put", [key, record]) => {
+                let (key, record) = (parse_bytes(key)?, parse_bytes(record)?);
+                self.table()?.add_record(&key, &record)?;
+            }
+            ("update", [key, record]) => {
+                let (key, record) = (parse_bytes(key)?, parse_bytes(record)?);
+                self.table()?.update_record(&key, &record)?;
+            }
+            ("delete", [key]) => {
+                let key = parse_bytes(key)?;
+                self.table()?.delete_record(&key)?;
+            }
+            ("scan", rest) if rest.len() <= 1 => {
+                let limit = match rest.first() {
+                    Some(limit) => parse_number(limit)?,
+                    None => usize::MAX,
+                };
+                let mode = self.mode;
+                let mut shown = 0;
+                self.table()?.for_each_record(|key, record| {
+                    if shown < limit {
+                        println!("{}\t{}", display(key, mode), display(record, mode));
+                        shown += 1;
+                    }
+                    Ok(())
+                })?;
+            }
+            ("count", []) => println!("{}", self.table()?.count()),
+            ("min" | "max", []) => {
+                let mode = self.mode;
+                let table = self.table()?;
+                let key = if command == "min" { table.min_key()? } else { table.max_key()? };
+                match key {
+                    Some(key) => println!("{}", display(&key, mode)),
+                    None => return Err(Error::KeyNotFound.into()),
+                }
+            }
+            ("stats", []) => {
+                let stats = self.table()?.stats()?;
+                println!("key size:       {}", stats.keysize);
+                println!("record size:    {}", stats.recordsize);
+                println!("engine:         {}", stats.engine);
+                if stats.engine == Engine::Lsm {
+                    let runs: Vec<String> = stats.runs.iter().map(usize::to_string).collect();
+                    println!("runs per level: {}", runs.join(" "));
+                } else {
+                    println!("index:          {}", stats.index_kind);
+                }
+                println!("page size:      {}", stats.page_size);
+                println!("t:              {}", stats.t);
+                println!("records:        {}", stats.records);
+                println!("deleted:        {}", stats.deleted);
+                println!("compression:    {}", stats.compression);
+                println!("encrypted:      {}", if stats.encrypted { "yes" } else { "no" });
+                println!("datafile bytes: {}", stats.datafile_bytes);
+                println!("index pages:    {}", stats.index_pages);
+                match stats.filter {
+                    Some(filter) => println!(
+                        "bloom filter:   {} bits, {} hashes, {:.2}% false positives",
+                        filter.bits,
+                        filter.hashes,
+                        filter.false_positive_rate() * 100.0
+                    ),
+                    None => println!("bloom filter:   off"),
+                }
+            }
+            ("verify", []) => {
+                self.table()?.verify()?;
+                println!("ok");
+            }
+            ("mode", []) => println!("{}", if self.mode == Mode::Hex { "hex" } else { "utf8" }),
+            ("mode", [mode]) => {
+                self.mode = match mode.as_str() {
+                    "utf8" => Mode::Utf8,
+                    "hex" => Mode::Hex,
+                    _ => return Err(CliError::Usage(format!("unknown mode {}", mode))),
+                }
+            }
+            ("help", []) => println!("{}", HELP),
+            ("quit" | "exit", []) => return Ok(false),
+            _ => {
+                return Err(CliError::Usage(format!(
+                    "bad command `{}`; type `help` for a list",
+                    args_to_line(command, args)
+                )));
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn interactive(&mut self) -> Result<()> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+
+        loop {
+            print!("rustdb> ");
+            io::stdout().flush()?;
+
+            let Some(line) = lines.next() else {
+                println!();
+                return Ok(());
+            };
+
+            let result = split_line(&line?).and_then(|args| self.execute(&args));
+            match result {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(err) => println!("error: {}", err),
+            }
+        }
+    }
+}
+
+fn args_to_line(command: &str, args: &[String]) -> String {
+    std::iter::once(command)
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Splits a shell line on whitespace, keeping double-quoted runs together.
+fn split_line(line: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    args.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if quoted {
+        return Err(CliError::Usage("unterminated quote".to_string()));
+    }
+    if in_word {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str) -> Result<T> {
+    arg.parse()
+        .map_err(|_| CliError::Usage(format!("{} is not a valid number", arg)))
+}
+
+fn parse_bytes(arg: &str) -> Result<Vec<u8>> {
+    let Some(hex) = arg.strip_prefix("0x") else {
+        return Ok(arg.as_bytes().to_vec());
+    };
+
+    if hex.len() % 2 != 0 {
+        return Err(CliError::Usage(format!("{} has an odd number of hex digits", arg)));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| CliError::Usage(format!("{} is not valid hex", arg)))
+        })
+        .collect()
+}
+
+// Hex shows the stored bytes as they are; UTF-8 drops the zero padding and escapes anything
+// that is not printable.
+fn display(bytes: &[u8], mode: Mode) -> String {
+    match mode {
+        Mode::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        Mode::Utf8 => {
+            let end = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+            let mut text = String::new();
+            for chunk in bytes[..end].utf8_chunks() {
+                for c in chunk.valid().chars() {
+                    match c {
+                        '\\' => text.push_str("\\\\"),
+                        c if c.is_control() => text.push_str(&format!("\\x{:02x}", c as u32)),
+                        c => text.push(c),
+                    }
+                }
+                for b in chunk.invalid() {
+                    text.push_str(&format!("\\x{:02x}", b));
+                }
+            }
+            text
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<()> {
+    let mut args = args;
+    let mut shell = Shell {
+        table: None,
+        mode: Mode::Utf8,
+    };
+    if args.first().map(String::as_str) == Some("--hex") {
+        shell.mode = Mode::Hex;
+        args.remove(0);
+    }
+
+    match args.as_slice() {
+        [] => shell.interactive(),
+        [help] if help == "help" || help == "--help" => {
+            println!("{}\n\n{}", USAGE, HELP);
+            Ok(())
+        }
+        [shell_command, path] if shell_command == "shell" => {
+            shell.execute(&["open".to_string(), path.clone()])?;
+            shell.interactive()
+        }
+        [command, ..] if command == "create" => shell.execute(&args).map(|_| ()),
+        [command, path, rest @ ..] => {
+            shell.execute(&["open".to_string(), path.clone()])?;
+            let mut line = vec![command.clone()];
+            line.extend_from_slice(rest);
+            shell.execute(&line).map(|_| ())
+        }
+        _ => Err(CliError::Usage(USAGE.to_string())),
+    }
+}
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
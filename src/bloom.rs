@@ -0,0 +1,242 @@
+use crate::checksum::fnv1a;
+use crate::encryption::Cipher;
+use crate::error::{Error, Result};
+use crate::hash::mix;
+use crate::storage::Storage;
+
+const MAGIC: &[u8; 4] = b"RDBF";
+const VERSION: u16 = 1;
+
+// magic (4) + version (2) + clean flag (1) + hashes (1) + bits per key (4) + capacity (8)
+// + keys added (8) + deletions (8) + the table's entry and deleted counts it was saved at (16).
+const HEADER_SIZE: usize = 52;
+const CLEAN_OFFSET: u64 = 6;
+
+// Filters are never sized for fewer keys than this, so a small table does not rebuild its
+// filter every few inserts.
+const MIN_CAPACITY: u64 = 1024;
+
+/// What `Table::stats` reports about a table's Bloom filter. Lookups are counted since the table
+/// was opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterStats {
+    pub bits: u64,
+    pub hashes: u32,
+    /// Lookups of missing keys that the filter answered without reading the index.
+    pub negatives: u64,
+    /// Lookups of missing keys that the filter let through to the index.
+    pub false_positives: u64,
+}
+
+impl FilterStats {
+    /// The share of lookups of missing keys that still went to the index, or 0 before there
+    /// were any.
+    pub fn false_positive_rate(&self) -> f64 {
+        let misses = self.negatives + self.false_positives;
+        if misses == 0 { 0.0 } else { self.false_positives as f64 / misses as f64 }
+    }
+}
+
+// A Bloom filter over the keys of a table. Deleted keys cannot be taken out again, so it is
+// rebuilt from the index once as many keys were deleted as are left, and once more keys were
+// added than it was sized for.
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    bits_per_key: u32,
+    capacity: u64,
+    keys: u64,
+    removed: u64,
+    negatives: u64,
+    false_positives: u64,
+    // The table's counts when the filter was last saved, and whether it changed since.
+    saved: Option<(u64, u64)>,
+    changed: bool,
+}
+
+impl BloomFilter {
+    pub(crate) fn new(bits_per_key: u32, capacity: u64) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let words = (capacity * bits_per_key as u64).div_ceil(64);
+        // k = ln 2 * m / n minimises the false-positive rate.
+        let hashes = ((bits_per_key as f64 * std::f64::consts::LN_2).round() as u32).clamp(1, 16);
+        BloomFilter {
+            bits: vec![0; words as usize],
+            hashes,
+            bits_per_key,
+            capacity,
+            keys: 0,
+            removed: 0,
+            negatives: 0,
+            false_positives: 0,
+            saved: None,
+            changed: true,
+        }
+    }
+
+    pub(crate) fn bits_per_key(&self) -> u32 {
+        self.bits_per_key
+    }
+
+    pub(crate) fn insert(&mut self, key: &[u8]) {
+        for bit in self.positions(key) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.keys += 1;
+        self.changed = true;
+    }
+
+    pub(crate) fn may_contain(&self, key: &[u8]) -> bool {
+        self.positions(key).all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub(crate) fn remove(&mut self) {
+        self.removed += 1;
+        self.changed = true;
+    }
+
+    // Counts a lookup of a key the index did not have; `passed` if the filter did not catch it.
+    pub(crate) fn count_miss(&mut self, passed: bool) {
+        if passed {
+            self.false_positives += 1;
+        } else {
+            self.negatives += 1;
+        }
+    }
+
+    // Whether the filter should be rebuilt for a table of `live` keys.
+    pub(crate) fn is_stale(&self, live: u64) -> bool {
+        self.keys > self.capacity || (self.removed > 0 && self.removed >= live)
+    }
+
+    // Keeps the lookup counters of the filter this one replaces.
+    pub(crate) fn take_counters(&mut self, old: &BloomFilter) {
+        self.negatives = old.negatives;
+        self.false_positives = old.false_positives;
+    }
+
+    pub(crate) fn stats(&self) -> FilterStats {
+        FilterStats {
+            bits: self.bits.len() as u64 * 64,
+            hashes: self.hashes,
+            negatives: self.negatives,
+            false_positives: self.false_positives,
+        }
+    }
+
+    // Double hashing: bit i is h1 + i * h2, from the key's FNV-1a hash and a mix of it. Both are
+    // fixed functions, so a saved filter stays valid across builds.
+    fn positions(&self, key: &[u8]) -> impl Iterator<Item = u64> + use<> {
+        let h1 = fnv1a(key);
+        let h2 = mix(h1) | 1;
+
+        let len = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % len)
+    }
+
+    // Writes the filter if it changed since it was last saved, stamped with the table's entry
+    // and deleted counts. The file is marked dirty first and clean last, so a crash in between
+    // leaves a filter that `load` refuses.
+    pub(crate) fn save(
+        &mut self,
+        storage: &mut dyn Storage,
+        cipher: Option<&Cipher>,
+        stamp: (u64, u64),
+    ) -> Result<()> {
+        if !self.changed && self.saved == Some(stamp) {
+            return Ok(());
+        }
+
+        if storage.len()? > CLEAN_OFFSET {
+            storage.write_at(CLEAN_OFFSET, &[0])?;
+            storage.sync_data()?;
+        }
+        let header = self.header(stamp);
+        let mut bitmap = Vec::with_capacity(self.bits.len() * 8);
+        for word in &self.bits {
+            bitmap.extend_from_slice(&word.to_le_bytes());
+        }
+        if let Some(cipher) = cipher {
+            bitmap = cipher.seal(&bitmap, &header);
+        }
+        let mut file = header;
+        file.extend_from_slice(&bitmap);
+        storage.truncate(file.len() as u64)?;
+        storage.write_at(0, &file)?;
+        storage.sync_data()?;
+        storage.write_at(CLEAN_OFFSET, &[1])?;
+        storage.sync()?;
+
+        self.saved = Some(stamp);
+        self.changed = false;
+        Ok(())
+    }
+
+    // The filter saved in `storage`, if there is one with `bits_per_key` that was saved cleanly
+    // at `stamp`. Anything else is rebuilt by the caller, so a damaged file is not an error.
+    pub(crate) fn load(
+        storage: &mut dyn Storage,
+        cipher: Option<&Cipher>,
+        bits_per_key: u32,
+        stamp: (u64, u64),
+    ) -> Result<Option<Self>> {
+        let len = storage.len()? as usize;
+        if len < HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut file = vec![0u8; len];
+        storage.read_at(0, &mut file)?;
+        let bitmap = file.split_off(HEADER_SIZE);
+        let mut header = file;
+
+        let u64_at = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+        if &header[0..4] != MAGIC
+            || u16::from_le_bytes(header[4..6].try_into().unwrap()) != VERSION
+            || header[CLEAN_OFFSET as usize] != 1
+            || u32::from_le_bytes(header[8..12].try_into().unwrap()) != bits_per_key
+            || (u64_at(36), u64_at(44)) != stamp
+        {
+            return Ok(None);
+        }
+        let capacity = u64_at(12);
+        let (keys, removed) = (u64_at(20), u64_at(28));
+
+        header[CLEAN_OFFSET as usize] = 0;
+        let bitmap = match cipher {
+            None => bitmap,
+            Some(cipher) => match cipher.open(&bitmap, &header) {
+                Ok(bitmap) => bitmap,
+                Err(Error::Corruption(_)) => return Ok(None),
+                Err(err) => return Err(err),
+            },
+        };
+        let mut filter = BloomFilter::new(bits_per_key, capacity);
+        if bitmap.len() != filter.bits.len() * 8 || header[7] as u32 != filter.hashes {
+            return Ok(None);
+        }
+        for (word, bytes) in filter.bits.iter_mut().zip(bitmap.chunks_exact(8)) {
+            *word = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        filter.keys = keys;
+        filter.removed = removed;
+        filter.saved = Some(stamp);
+        filter.changed = false;
+        Ok(Some(filter))
+    }
+
+    // With the clean flag cleared, which is also how it is sealed.
+    fn header(&self, stamp: (u64, u64)) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.push(0);
+        header.push(self.hashes as u8);
+        header.extend_from_slice(&self.bits_per_key.to_le_bytes());
+        header.extend_from_slice(&self.capacity.to_le_bytes());
+        header.extend_from_slice(&self.keys.to_le_bytes());
+        header.extend_from_slice(&self.removed.to_le_bytes());
+        header.extend_from_slice(&stamp.0.to_le_bytes());
+        header.extend_from_slice(&stamp.1.to_le_bytes());
+        header
+    }
+}
@@ -1,185 +1,641 @@
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use crate::encryption::{Cipher, SEAL_SIZE};
+use crate::error::{Error, Result};
+use crate::storage::{Backend, Durability, MemStorage, Storage};
+use std::fs::OpenOptions;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+pub const DEFAULT_PAGE_SIZE: u32 = 4096;
+
+const MAGIC: &[u8; 4] = b"RDBI";
+const VERSION: u16 = 3;
+
+// magic (4) + version (2) + page_size (4) + t (4) + keysize (2) + root_page (8) + page_count (8)
+// + len (8) + dirty (1) + counted (1) + encrypted (1) + key check (SEAL_SIZE). The header page is
+// never encrypted; the key check seals the fields before root_page.
+const HEADER_SIZE: usize = 43 + SEAL_SIZE;
+const KEY_CHECK_OFFSET: usize = 43;
+
+// Page 0 always holds the header, so it doubles as the "no child" marker.
+const NULL_PAGE: u64 = 0;
+
+// Node page: n (4) + leaf flag (1) + prefix length (2) + the prefix shared by all its keys, then
+// n * (suffix length (1, or 2 for keys over 255 bytes) + suffix + value (8)) and, in interior
+// nodes only, n + 1 children (8) and, if counted, n + 1 subtree counts (8). Suffixes leave out
+// the zero padding at the end of a key. In an encrypted index the node takes up all but
+// SEAL_SIZE bytes of the page, and is sealed with its page number as associated data.
+const NODE_HEADER_SIZE: usize = 7;
 
 #[derive(Debug)]
 struct Node {
     n: u32,
     keys: Vec<Vec<u8>>,
     values: Vec<u64>,
-    children: Vec<i64>,
+    children: Vec<u64>,
+    // Keys in the subtree under each child. Kept alongside `children` in memory either way,
+    // but only stored, and only meaningful, in a counted index.
+    counts: Vec<u64>,
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.children[0] == NULL_PAGE
+    }
+
+    fn subtree_len(&self) -> u64 {
+        let n = self.n as usize;
+        if self.is_leaf() { n as u64 } else { n as u64 + self.counts[..=n].iter().sum::<u64>() }
+    }
 }
 
 pub struct Index {
-    file: File,
+    storage: Box<dyn Storage>,
+    page_size: u32,
     t: u32,
     keysize: u16,
-    pub(crate) root_offset: u64,
+    pub(crate) root_page: u64,
+    page_count: u64,
+    len: u64,
+    dirty: bool,
+    counted: bool,
+    cipher: Option<Cipher>,
+    // Pages read since the index was opened, for EXPLAIN and benchmarks.
+    reads: u64,
+}
+
+struct Header {
+    page_size: u32,
+    t: u32,
+    keysize: u16,
+    root_page: u64,
+    page_count: u64,
+    len: u64,
+    dirty: bool,
+    counted: bool,
+    encrypted: bool,
+    key_check: Vec<u8>,
+}
+
+impl Header {
+    fn read(storage: &mut dyn Storage) -> Result<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        storage.read_at(0, &mut header)?;
+
+        if &header[0..4] != MAGIC {
+            return Err(Error::Corruption("not an index file".to_string()));
+        }
+        let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::VersionMismatch {
+                found: version,
+                expected: VERSION,
+            });
+        }
+
+        Ok(Header {
+            page_size: u32::from_le_bytes(header[6..10].try_into().unwrap()),
+            t: u32::from_le_bytes(header[10..14].try_into().unwrap()),
+            keysize: u16::from_le_bytes(header[14..16].try_into().unwrap()),
+            root_page: u64::from_le_bytes(header[16..24].try_into().unwrap()),
+            page_count: u64::from_le_bytes(header[24..32].try_into().unwrap()),
+            len: u64::from_le_bytes(header[32..40].try_into().unwrap()),
+            dirty: header[40] != 0,
+            counted: header[41] != 0,
+            encrypted: header[42] != 0,
+            key_check: header[KEY_CHECK_OFFSET..HEADER_SIZE].to_vec(),
+        })
+    }
 }
 
 impl Index {
-    pub fn create(path: &str, t: u32, keysize: u16) -> io::Result<Self> {
-        let mut file = OpenOptions::new()
+    // An interior node of 2t - 1 keys has to fit in one page even when its keys share no
+    // prefix and have no padding: the node header + (2t - 1) * (suffix length + key + value)
+    // + 2t * child. Leaves have no children, so they hold at least as many keys, and more when
+    // their keys are short or share a prefix.
+    pub fn t_for(page_size: u32, keysize: u16) -> u32 {
+        Self::order(page_size, keysize, false)
+    }
+
+    // Counted nodes also store a subtree count (8) next to each child.
+    fn order(page_size: u32, keysize: u16, counted: bool) -> u32 {
+        let entry = (Self::length_size(keysize) + keysize as usize + 8) as u32;
+        let child = if counted { 16 } else { 8 };
+        (page_size + entry).saturating_sub(NODE_HEADER_SIZE as u32) / (2 * entry + 2 * child)
+    }
+
+    pub fn create(path: impl AsRef<Path>, keysize: u16, page_size: u32) -> Result<Self> {
+        Self::create_with(path, keysize, page_size, Backend::File)
+    }
+
+    pub fn create_with(path: impl AsRef<Path>, keysize: u16, page_size: u32, backend: Backend) -> Result<Self> {
+        let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(true)
             .open(path)?;
 
-        // t (4) + root_offset (8) + keysize (2) = 14 reserved for header
-        file.write_all(&[0u8; 14])?;
+        Self::create_with_storage(backend.storage(file)?, keysize, page_size)
+    }
+
+    pub fn create_with_storage(storage: Box<dyn Storage>, keysize: u16, page_size: u32) -> Result<Self> {
+        Self::create_with_counts(storage, keysize, page_size, false)
+    }
+
+    /// Creates an index whose nodes also keep the key count of every subtree, which makes
+    /// `rank` and `select` O(log n) at the cost of a lower order `t`.
+    pub fn create_counted(storage: Box<dyn Storage>, keysize: u16, page_size: u32) -> Result<Self> {
+        Self::create_with_counts(storage, keysize, page_size, true)
+    }
+
+    fn create_with_counts(storage: Box<dyn Storage>, keysize: u16, page_size: u32, counted: bool) -> Result<Self> {
+        Self::create_sealed(storage, keysize, page_size, counted, None)
+    }
+
+    // An index whose node pages are encrypted with `cipher`, if given. Sealing takes room from
+    // every page, so the order is that of a smaller page.
+    pub(crate) fn create_sealed(
+        storage: Box<dyn Storage>,
+        keysize: u16,
+        page_size: u32,
+        counted: bool,
+        cipher: Option<Cipher>,
+    ) -> Result<Self> {
+        let overhead = if cipher.is_some() { SEAL_SIZE as u32 } else { 0 };
+        let t = Self::order(page_size.saturating_sub(overhead), keysize, counted);
+        if t < 2 || (page_size as usize) < HEADER_SIZE {
+            return Err(Error::PageTooSmall { page_size, keysize });
+        }
 
         let mut index = Index {
-            file,
+            storage,
+            page_size,
             t,
             keysize,
-            root_offset: 0,
-        };
-
-        let root = Node {
-            n: 0,
-            keys: vec![],
-            values: vec![],
-            children: vec![-1; (2 * t) as usize],
+            root_page: NULL_PAGE,
+            page_count: 1,
+            len: 0,
+            dirty: true,
+            counted,
+            cipher,
+            reads: 0,
         };
 
-        index.root_offset = index.write_node(&root)?;
+        let root = index.empty_node();
+        index.root_page = index.write_node(&root)?;
         index.write_header()?;
 
         Ok(index)
     }
 
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(path, Backend::File)
+    }
 
-    pub fn open(path: &str) -> io::Result<Self> {
-        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    pub fn open_with(path: impl AsRef<Path>, backend: Backend) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Self::open_with_storage(backend.storage(file)?)
+    }
 
-        let mut buf4 = [0u8; 4];
-        let mut buf8 = [0u8; 8];
-        let mut buf2 = [0u8; 2];
+    pub fn open_with_storage(storage: Box<dyn Storage>) -> Result<Self> {
+        Self::open_sealed(storage, None)
+    }
 
-        file.read_exact(&mut buf4)?;
-        let t = u32::from_le_bytes(buf4);
+    pub(crate) fn open_sealed(mut storage: Box<dyn Storage>, cipher: Option<Cipher>) -> Result<Self> {
+        let header = Header::read(storage.as_mut())?;
+        match (&cipher, header.encrypted) {
+            (None, true) => return Err(Error::InvalidOptions("the index is encrypted; a key is required".to_string())),
+            (Some(_), false) => return Err(Error::InvalidOptions("the index is not encrypted".to_string())),
+            (Some(cipher), true) => {
+                let mut fields = [0u8; 16];
+                storage.read_at(0, &mut fields)?;
+                cipher.verify_key(&header.key_check, &fields)?;
+            }
+            (None, false) => {}
+        }
 
-        file.read_exact(&mut buf8)?;
-        let root_offset = u64::from_le_bytes(buf8);
+        Ok(Index {
+            storage,
+            page_size: header.page_size,
+            t: header.t,
+            keysize: header.keysize,
+            root_page: header.root_page,
+            page_count: header.page_count,
+            len: header.len,
+            dirty: header.dirty,
+            counted: header.counted,
+            cipher,
+            reads: 0,
+        })
+    }
+
+    // Key count of an index that was synced and not written to since, or None if the file is
+    // empty, unreadable, from another format version or was left dirty by a crash and has to
+    // be rebuilt.
+    pub fn clean_len(storage: &mut dyn Storage) -> Result<Option<u64>> {
+        match Header::read(storage) {
+            Ok(header) if !header.dirty => Ok(Some(header.len)),
+            Ok(_) | Err(Error::Corruption(_)) | Err(Error::VersionMismatch { .. }) => Ok(None),
+            Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
 
-        file.read_exact(&mut buf2)?;
-        let keysize = u16::from_le_bytes(buf2);
+    pub fn sync(&mut self) -> Result<()> {
+        self.storage.sync()?;
+        if self.dirty {
+            self.dirty = false;
+            self.write_header()?;
+            self.storage.sync()?;
+        }
+        Ok(())
+    }
 
-        Ok(Index { file, t, keysize, root_offset })
+    // Makes the pages written so far as durable as `durability` asks, without marking the
+    // index clean; only `sync` does that.
+    pub fn commit(&mut self, durability: Durability) -> Result<()> {
+        Ok(self.storage.commit(durability)?)
     }
 
-    fn write_header(&mut self) -> io::Result<()> {
-        self.file.seek(SeekFrom::Start(0))?;
-        self.file.write_all(&self.t.to_le_bytes())?;
-        self.file.write_all(&self.root_offset.to_le_bytes())?;
-        self.file.write_all(&self.keysize.to_le_bytes())?;
+    // The dirty flag has to be durable before the first page is touched, otherwise a crash
+    // could leave half-written pages behind a header that still claims to be clean. This
+    // barrier is kept whatever the durability mode, since losing it means silent corruption
+    // rather than lost writes.
+    fn begin_write(&mut self) -> Result<()> {
+        if !self.dirty {
+            self.dirty = true;
+            self.write_header()?;
+            self.storage.sync_data()?;
+        }
         Ok(())
     }
 
-    fn serialize_node(&mut self, node: &Node) -> io::Result<()> {
-        self.file.write_all(&node.n.to_le_bytes())?;
+    pub fn len(&self) -> u64 {
+        self.len
+    }
 
-        let max_keys = (2 * self.t - 1) as usize;
-        for i in 0..max_keys {
-            if i < node.keys.len() {
-                let key = &node.keys[i];
-                let mut fixed_key = vec![0u8; self.keysize as usize];
-                let len = key.len().min(self.keysize as usize);
-                fixed_key[..len].copy_from_slice(&key[..len]);
-                self.file.write_all(&fixed_key)?;
-                self.file.write_all(&node.values[i].to_le_bytes())?;
-            } else {
-                self.file.write_all(&vec![0u8; self.keysize as usize])?;
-                self.file.write_all(&0u64.to_le_bytes())?;
-            }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn t(&self) -> u32 {
+        self.t
+    }
+
+    pub fn keysize(&self) -> u16 {
+        self.keysize
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    pub fn node_reads(&self) -> u64 {
+        self.reads
+    }
+
+    pub fn is_counted(&self) -> bool {
+        self.counted
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    // Hands the storage over to a table that starts the index over, leaving this one with none.
+    pub(crate) fn take_storage(&mut self) -> Box<dyn Storage> {
+        std::mem::replace(&mut self.storage, Box::new(MemStorage::new()))
+    }
+
+    // Bytes of a page a node can take up.
+    fn node_size(&self) -> usize {
+        self.page_size as usize - if self.cipher.is_some() { SEAL_SIZE } else { 0 }
+    }
+
+    fn max_keys(&self) -> usize {
+        (2 * self.t - 1) as usize
+    }
+
+    fn max_children(&self) -> usize {
+        (2 * self.t) as usize
+    }
+
+    fn empty_node(&self) -> Node {
+        Node {
+            n: 0,
+            keys: vec![],
+            values: vec![],
+            children: vec![NULL_PAGE; self.max_children()],
+            counts: vec![0; self.max_children()],
         }
+    }
 
-        let max_children = (2 * self.t) as usize;
-        for i in 0..max_children {
-            if i < node.children.len() {
-                self.file.write_all(&node.children[i].to_le_bytes())?;
-            } else {
-                self.file.write_all(&(-1i64).to_le_bytes())?;
-            }
+    fn write_header(&mut self) -> Result<()> {
+        let mut header = vec![0u8; self.page_size as usize];
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6..10].copy_from_slice(&self.page_size.to_le_bytes());
+        header[10..14].copy_from_slice(&self.t.to_le_bytes());
+        header[14..16].copy_from_slice(&self.keysize.to_le_bytes());
+        header[16..24].copy_from_slice(&self.root_page.to_le_bytes());
+        header[24..32].copy_from_slice(&self.page_count.to_le_bytes());
+        header[32..40].copy_from_slice(&self.len.to_le_bytes());
+        header[40] = self.dirty as u8;
+        header[41] = self.counted as u8;
+        if let Some(cipher) = &self.cipher {
+            header[42] = 1;
+            let check = cipher.key_check(&header[0..16]);
+            header[KEY_CHECK_OFFSET..HEADER_SIZE].copy_from_slice(&check);
         }
 
-        Ok(())
+        self.write_page(0, &header)
     }
 
-    fn write_node(&mut self, node: &Node) -> io::Result<u64> {
-        let offset = self.file.seek(SeekFrom::End(0))?;
-        self.serialize_node(node)?;
-        Ok(offset)
+    fn write_page(&mut self, page: u64, buf: &[u8]) -> Result<()> {
+        Ok(self.storage.write_at(page * self.page_size as u64, buf)?)
     }
 
-    fn write_node_at(&mut self, offset: u64, node: &Node) -> io::Result<()> {
-        self.file.seek(SeekFrom::Start(offset))?;
-        self.serialize_node(node)
+    fn length_size(keysize: u16) -> usize {
+        if keysize < 256 { 1 } else { 2 }
     }
 
+    // Length of `key` without its zero padding.
+    fn trimmed_len(key: &[u8]) -> usize {
+        key.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1)
+    }
+
+    // The prefix stored once for a node whose smallest and largest keys are given. It never
+    // ends in padding, so every key of the node is at least as long as it once trimmed.
+    fn node_prefix(first: &[u8], last: &[u8]) -> usize {
+        let shared = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+        Self::trimmed_len(&first[..shared])
+    }
 
-    fn read_node(&mut self, offset: u64) -> io::Result<Node> {
-        self.file.seek(SeekFrom::Start(offset))?;
+    // Encoded size of a leaf holding `keys`, plus `extra` if given.
+    fn leaf_size(&self, keys: &[Vec<u8>], extra: Option<&[u8]>) -> usize {
+        let all = || keys.iter().map(Vec::as_slice).chain(extra);
+        let (Some(first), Some(last)) = (all().min(), all().max()) else {
+            return NODE_HEADER_SIZE;
+        };
+        let prefix = Self::node_prefix(first, last);
+        let entry = Self::length_size(self.keysize) + 8;
+        NODE_HEADER_SIZE + prefix + all().map(|key| entry + Self::trimmed_len(key) - prefix).sum::<usize>()
+    }
 
-        let mut buf4 = [0u8; 4];
-        self.file.read_exact(&mut buf4).expect("failed read");
-        let n = u32::from_le_bytes(buf4);
+    fn leaf_fits(&self, keys: &[Vec<u8>]) -> bool {
+        self.leaf_size(keys, None) <= self.node_size()
+    }
 
-        let max_keys = (2 * self.t - 1) as usize;
-        let mut keys = Vec::new();
-        let mut values = Vec::new();
+    // Whether `leaf` still fits in its page with `key` added.
+    fn leaf_fits_with(&self, leaf: &Node, key: &[u8]) -> bool {
+        self.leaf_size(&leaf.keys[..leaf.n as usize], Some(key)) <= self.node_size()
+    }
 
-        for _ in 0..max_keys {
-            let mut key_buf = vec![0u8; self.keysize as usize];
-            self.file.read_exact(&mut key_buf)?;
-            keys.push(key_buf);
+    fn encode_node(&self, node: &Node) -> Vec<u8> {
+        let n = node.n as usize;
+        let leaf = node.is_leaf();
+        let wide = Self::length_size(self.keysize) == 2;
+        let prefix = if n == 0 { 0 } else { Self::node_prefix(&node.keys[0], &node.keys[n - 1]) };
+
+        let mut page = vec![0u8; self.node_size()];
+        page[0..4].copy_from_slice(&node.n.to_le_bytes());
+        page[4] = leaf as u8;
+        page[5..7].copy_from_slice(&(prefix as u16).to_le_bytes());
+        let mut pos = NODE_HEADER_SIZE;
+        if n > 0 {
+            page[pos..pos + prefix].copy_from_slice(&node.keys[0][..prefix]);
+            pos += prefix;
+        }
 
-            let mut value_buf = [0u8; 8];
-            self.file.read_exact(&mut value_buf)?;
-            values.push(u64::from_le_bytes(value_buf));
+        for i in 0..n {
+            let suffix = &node.keys[i][prefix..Self::trimmed_len(&node.keys[i])];
+            if wide {
+                page[pos..pos + 2].copy_from_slice(&(suffix.len() as u16).to_le_bytes());
+                pos += 2;
+            } else {
+                page[pos] = suffix.len() as u8;
+                pos += 1;
+            }
+            page[pos..pos + suffix.len()].copy_from_slice(suffix);
+            pos += suffix.len();
+            page[pos..pos + 8].copy_from_slice(&node.values[i].to_le_bytes());
+            pos += 8;
         }
 
-        let max_children = (2 * self.t) as usize;
-        let mut children = Vec::new();
-        for _ in 0..max_children {
-            let mut child_buf = [0u8; 8];
-            self.file.read_exact(&mut child_buf)?;
-            children.push(i64::from_le_bytes(child_buf));
+        if !leaf {
+            for i in 0..=n {
+                page[pos..pos + 8].copy_from_slice(&node.children[i].to_le_bytes());
+                pos += 8;
+            }
+            if self.counted {
+                for i in 0..=n {
+                    page[pos..pos + 8].copy_from_slice(&node.counts[i].to_le_bytes());
+                    pos += 8;
+                }
+            }
         }
 
-        Ok(Node { n, keys, values, children })
+        page
     }
 
-    pub fn insert(&mut self, key: Vec<u8>, value: u64) -> io::Result<()> {
-        let root = self.read_node(self.root_offset)?;
-        if root.n as usize == (2 * self.t - 1) as usize {
-            let mut new_root = Node {
-                n: 0,
-                keys: vec![],
-                values: vec![],
-                children: vec![-1; (2 * self.t) as usize],
+    fn decode_node(&self, page_no: u64, page: &[u8]) -> Result<Node> {
+        let keysize = self.keysize as usize;
+        let wide = Self::length_size(self.keysize) == 2;
+        let n = u32::from_le_bytes(page[0..4].try_into().unwrap()) as usize;
+        let leaf = page[4] != 0;
+        let prefix = u16::from_le_bytes(page[5..7].try_into().unwrap()) as usize;
+        let overflow = || Error::Corruption(format!("page {} holds {} keys, more than fit in a page", page_no, n));
+
+        // A page can hold at most one key per 9 bytes, which bounds `n` before anything is
+        // allocated for it.
+        if prefix > keysize || (!leaf && n > self.max_keys()) || n > page.len() / 9 {
+            return Err(overflow());
+        }
+        let prefix_bytes = page.get(NODE_HEADER_SIZE..NODE_HEADER_SIZE + prefix).ok_or_else(overflow)?;
+
+        let mut keys = Vec::with_capacity(n);
+        let mut values = Vec::with_capacity(n);
+        let mut pos = NODE_HEADER_SIZE + prefix;
+        for _ in 0..n {
+            let len = if wide {
+                let bytes = page.get(pos..pos + 2).ok_or_else(overflow)?;
+                pos += 2;
+                u16::from_le_bytes(bytes.try_into().unwrap()) as usize
+            } else {
+                let byte = *page.get(pos).ok_or_else(overflow)?;
+                pos += 1;
+                byte as usize
             };
+            if prefix + len > keysize {
+                return Err(Error::Corruption(format!("page {} holds a key longer than the key size", page_no)));
+            }
+            let suffix = page.get(pos..pos + len).ok_or_else(overflow)?;
+            let value = page.get(pos + len..pos + len + 8).ok_or_else(overflow)?;
+            pos += len + 8;
+
+            let mut key = vec![0u8; keysize];
+            key[..prefix].copy_from_slice(prefix_bytes);
+            key[prefix..prefix + len].copy_from_slice(suffix);
+            keys.push(key);
+            values.push(u64::from_le_bytes(value.try_into().unwrap()));
+        }
+
+        // Leaves can hold more keys than interior nodes, and the code walking them indexes
+        // `children` up to n.
+        let slots = self.max_children().max(n + 1);
+        let mut children = vec![NULL_PAGE; slots];
+        let mut counts = vec![0; slots];
+        if !leaf {
+            let child_size = if self.counted { 16 } else { 8 };
+            if pos + (n + 1) * child_size > page.len() {
+                return Err(overflow());
+            }
+            for child in children.iter_mut().take(n + 1) {
+                *child = u64::from_le_bytes(page[pos..pos + 8].try_into().unwrap());
+                if *child == NULL_PAGE || *child >= self.page_count {
+                    return Err(Error::Corruption(format!(
+                        "page {} points past the end of the index",
+                        page_no
+                    )));
+                }
+                pos += 8;
+            }
+            if self.counted {
+                for count in counts.iter_mut().take(n + 1) {
+                    *count = u64::from_le_bytes(page[pos..pos + 8].try_into().unwrap());
+                    pos += 8;
+                }
+            }
+        }
+
+        Ok(Node {
+            n: n as u32,
+            keys,
+            values,
+            children,
+            counts,
+        })
+    }
+
+    fn write_node(&mut self, node: &Node) -> Result<u64> {
+        let page = self.page_count;
+        self.page_count += 1;
+        self.write_node_at(page, node)?;
+        self.write_header()?;
+        Ok(page)
+    }
+
+    fn write_node_at(&mut self, page: u64, node: &Node) -> Result<()> {
+        let buf = self.encode_node(node);
+        match &self.cipher {
+            Some(cipher) => {
+                let sealed = cipher.seal(&buf, &page.to_le_bytes());
+                self.write_page(page, &sealed)
+            }
+            None => self.write_page(page, &buf),
+        }
+    }
+
+    fn read_node(&mut self, page: u64) -> Result<Node> {
+        if page == NULL_PAGE || page >= self.page_count {
+            return Err(Error::Corruption(format!("page {} is not a node", page)));
+        }
+
+        self.reads += 1;
+        let offset = page * self.page_size as u64;
+        if self.cipher.is_none()
+            && let Some(bytes) = self.storage.mapped(offset, self.page_size as usize)
+        {
+            return self.decode_node(page, bytes);
+        }
+
+        let mut buf = vec![0u8; self.page_size as usize];
+        self.storage.read_at(offset, &mut buf)?;
+        if let Some(cipher) = &self.cipher {
+            buf = cipher.open(&buf, &page.to_le_bytes())?;
+        }
+        self.decode_node(page, &buf)
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: u64) -> Result<()> {
+        self.insert_entry(key, value, false).map(|_| ())
+    }
+
+    /// Inserts `key` unless it is already present, in which case nothing changes and the value
+    /// stored under it is returned. Takes a single descent from the root either way.
+    pub fn insert_if_absent(&mut self, key: Vec<u8>, value: u64) -> Result<Option<u64>> {
+        self.insert_entry(key, value, true)
+    }
+
+    fn insert_entry(&mut self, key: Vec<u8>, value: u64, unique: bool) -> Result<Option<u64>> {
+        let key = self.fixed_key(&key);
+        self.begin_write()?;
+
+        let mut root = self.read_node(self.root_page)?;
+        if root.is_leaf() && !self.leaf_fits_with(&root, &key) {
+            let i = Self::lower_bound(&root, &key);
+            if unique && i < root.n as usize && root.keys[i] == key {
+                return Ok(Some(root.values[i]));
+            }
+
+            let mut new_root = self.empty_node();
+            let old_root_page = self.root_page;
+            let new_root_page = self.write_node(&new_root)?;
+            self.root_page = new_root_page;
+            self.write_header()?;
+
+            new_root.children[0] = old_root_page;
+            self.split_leaf(&mut new_root, 0, old_root_page, root, key, value)?;
+            self.write_node_at(new_root_page, &new_root)?;
+            self.len += 1;
+            return Ok(None);
+        }
+        if !root.is_leaf() && root.n as usize == self.max_keys() {
+            let mut new_root = self.empty_node();
 
-            let old_root_offset = self.root_offset;
-            let new_root_offset = self.write_node(&new_root)?;
-            self.root_offset = new_root_offset;
+            let old_root_page = self.root_page;
+            let new_root_page = self.write_node(&new_root)?;
+            self.root_page = new_root_page;
             self.write_header()?;
 
-            new_root.children[0] = old_root_offset as i64;
-            self.split_child(&mut new_root, 0, old_root_offset)?;
+            new_root.children[0] = old_root_page;
+            self.split_child(&mut new_root, 0, old_root_page)?;
 
-            self.write_node_at(new_root_offset, &new_root)?;
+            self.write_node_at(new_root_page, &new_root)?;
+            root = new_root;
+        }
 
-            self.insert_non_full(self.root_offset, key, value)
-        } else {
-            self.insert_non_full(self.root_offset, key, value)
+        let existing = self.insert_non_full(self.root_page, root, key, value, unique)?;
+        if existing.is_none() {
+            self.len += 1;
         }
+        Ok(existing)
     }
 
-    fn insert_non_full(&mut self, offset: u64, key: Vec<u8>, value: u64) -> io::Result<()> {
-        let mut node = self.read_node(offset)?;
+    // With `unique`, stops at an existing copy of `key` and returns its value. Nodes split on
+    // the way down stay split, which leaves a valid tree either way. `node` is the node at
+    // `page`, already read by the caller, so each level costs one read unless it splits.
+    fn insert_non_full(
+        &mut self,
+        page: u64,
+        mut node: Node,
+        key: Vec<u8>,
+        value: u64,
+        unique: bool,
+    ) -> Result<Option<u64>> {
+        if unique {
+            let i = Self::lower_bound(&node, &key);
+            if i < node.n as usize && node.keys[i] == key {
+                return Ok(Some(node.values[i]));
+            }
+        }
 
         let mut i = node.n as isize - 1;
-        if node.children[0] == -1 {
+        if node.is_leaf() {
             while i >= 0 && node.keys[i as usize] > key {
                 i -= 1;
             }
@@ -187,127 +643,892 @@ impl Index {
             node.values.insert((i + 1) as usize, value);
             node.n += 1;
 
-            self.write_node_at(offset, &node)?;
-            Ok(())
+            self.write_node_at(page, &node)?;
+            Ok(None)
         } else {
             while i >= 0 && node.keys[i as usize] > key {
                 i -= 1;
             }
             i += 1;
-            let child_offset = node.children[i as usize] as u64;
-            let child = self.read_node(child_offset)?;
+            let child_page = node.children[i as usize];
+            let mut child = self.read_node(child_page)?;
+
+            if child.is_leaf() && !self.leaf_fits_with(&child, &key) {
+                let j = Self::lower_bound(&child, &key);
+                if unique && j < child.n as usize && child.keys[j] == key {
+                    return Ok(Some(child.values[j]));
+                }
+                self.split_leaf(&mut node, i as usize, child_page, child, key, value)?;
+                self.write_node_at(page, &node)?;
+                return Ok(None);
+            }
 
-            if child.n as usize == (2 * self.t - 1) as usize {
-                self.split_child(&mut node, i as usize, child_offset)?;
+            let split = !child.is_leaf() && child.n as usize == self.max_keys();
+            if split {
+                self.split_child(&mut node, i as usize, child_page)?;
 
+                if unique && node.keys[i as usize] == key {
+                    self.write_node_at(page, &node)?;
+                    return Ok(Some(node.values[i as usize]));
+                }
                 if node.keys[i as usize] < key {
                     i += 1;
                 }
+                child = self.read_node(node.children[i as usize])?;
             }
 
-            self.write_node_at(offset, &node)?;
+            // Without `unique` the key is sure to be added, so the count can go up right away;
+            // otherwise only once the descent below has added it.
+            if !unique {
+                node.counts[i as usize] += 1;
+            }
+            if split || (!unique && self.counted) {
+                self.write_node_at(page, &node)?;
+            }
 
-            self.insert_non_full(node.children[i as usize] as u64, key, value)
+            let existing = self.insert_non_full(node.children[i as usize], child, key, value, unique)?;
+            if unique && existing.is_none() && self.counted {
+                node.counts[i as usize] += 1;
+                self.write_node_at(page, &node)?;
+            }
+            Ok(existing)
         }
     }
 
-    fn split_child(&mut self, parent: &mut Node, i: usize, child_offset: u64) -> io::Result<()> {
-        let mut y = self.read_node(child_offset)?;
+    // Adds `key` to a leaf that has no room for it and splits the result in two, moving the
+    // middle key up into `parent` as child i's separator. Leaves of prefix-compressed keys can
+    // hold many more than 2t - 1 keys, and a new key without their prefix can make either half
+    // too big; splitting next to the new key instead always works, since the half that gets it
+    // then holds only t - 1 old keys, or the new key itself moves up.
+    fn split_leaf(
+        &mut self,
+        parent: &mut Node,
+        i: usize,
+        page: u64,
+        mut leaf: Node,
+        key: Vec<u8>,
+        value: u64,
+    ) -> Result<()> {
+        let p = Self::lower_bound(&leaf, &key);
+        leaf.keys.insert(p, key);
+        leaf.values.insert(p, value);
+        let m = leaf.keys.len();
+        let t = self.t as usize;
+
+        let mut s = m / 2;
+        if !self.leaf_fits(&leaf.keys[..s]) || !self.leaf_fits(&leaf.keys[s + 1..]) {
+            s = p.clamp(t - 1, m - t);
+        }
 
-        let mut z = Node {
-            n: self.t - 1,
-            keys: Vec::new(),
-            values: Vec::new(),
-            children: vec![-1; (2 * self.t) as usize],
-        };
+        let mut right = self.empty_node();
+        right.keys = leaf.keys.split_off(s + 1);
+        right.values = leaf.values.split_off(s + 1);
+        right.n = right.keys.len() as u32;
+        let median_key = leaf.keys.pop().unwrap();
+        let median_value = leaf.values.pop().unwrap();
+        leaf.n = leaf.keys.len() as u32;
+
+        let right_page = self.write_node(&right)?;
+        self.write_node_at(page, &leaf)?;
+
+        parent.keys.insert(i, median_key);
+        parent.values.insert(i, median_value);
+        parent.children.insert(i + 1, right_page);
+        parent.counts[i] = leaf.n as u64;
+        parent.counts.insert(i + 1, right.n as u64);
+        parent.n += 1;
+        Ok(())
+    }
+
+    fn split_child(&mut self, parent: &mut Node, i: usize, child_page: u64) -> Result<()> {
+        let mut y = self.read_node(child_page)?;
+
+        let mut z = self.empty_node();
+        z.n = self.t - 1;
 
         for _ in 0..(self.t - 1) {
             z.keys.push(y.keys.remove(self.t as usize));
             z.values.push(y.values.remove(self.t as usize));
         }
 
-        if y.children[0] != -1 {
+        if !y.is_leaf() {
             for j in 0..self.t as usize {
                 z.children[j] = y.children.remove(self.t as usize);
+                z.counts[j] = y.counts.remove(self.t as usize);
             }
         }
 
         y.n = self.t - 1;
 
-        let z_offset = self.write_node(&z)?;
+        let z_page = self.write_node(&z)?;
 
         parent.keys.insert(i, y.keys.remove((self.t - 1) as usize));
         parent.values.insert(i, y.values.remove((self.t - 1) as usize));
-        parent.children.insert(i + 1, z_offset as i64);
+        parent.children.insert(i + 1, z_page);
+        parent.counts[i] = y.subtree_len();
+        parent.counts.insert(i + 1, z.subtree_len());
         parent.n += 1;
 
-        self.write_node_at(child_offset, &y)?;
+        self.write_node_at(child_page, &y)?;
 
         Ok(())
     }
 
-
-    pub fn traverse_inorder_from<F>(&mut self, offset: u64, mut visit: F) -> io::Result<()>
+    pub fn traverse_inorder_from<F>(&mut self, page: u64, mut visit: F) -> Result<()>
     where
-        F: FnMut(&[u8], u64) -> io::Result<()>,
+        F: FnMut(&[u8], u64) -> Result<()>,
     {
-        let node = self.read_node(offset)?;
+        self.visit_inorder(page, &mut visit)
+    }
+
+    fn visit_inorder(&mut self, page: u64, visit: &mut dyn FnMut(&[u8], u64) -> Result<()>) -> Result<()> {
+        let node = self.read_node(page)?;
 
         for i in 0..(node.n as usize) {
-            if node.children[i] != -1 {
-                self.traverse_inorder_from(node.children[i] as u64, &mut visit)?;
+            if node.children[i] != NULL_PAGE {
+                self.visit_inorder(node.children[i], visit)?;
             }
 
             visit(node.keys[i].as_slice(), node.values[i])?;
         }
 
-        if node.children[node.n as usize] != -1 {
-            self.traverse_inorder_from(node.children[node.n as usize] as u64, &mut visit)?;
+        if node.children[node.n as usize] != NULL_PAGE {
+            self.visit_inorder(node.children[node.n as usize], visit)?;
         }
 
         Ok(())
     }
 
-    pub fn traverse_inorder<F>(&mut self, mut visit: F) -> io::Result<()>
+    pub fn traverse_inorder<F>(&mut self, visit: F) -> Result<()>
     where
-        F: FnMut(&[u8], u64) -> io::Result<()>,
+        F: FnMut(&[u8], u64) -> Result<()>,
     {
-        self.traverse_inorder_from(self.root_offset, visit)
+        self.traverse_inorder_from(self.root_page, visit)
     }
 
-    pub fn search(&mut self, key: &[u8]) -> io::Result<Option<u64>> {
-        self.search_in_node(self.root_offset, key)
+    pub fn search(&mut self, key: &[u8]) -> Result<Option<u64>> {
+        self.search_in_node(self.root_page, key)
+    }
+
+    /// Points `key` at `value`, returning the value it replaces, or None if `key` is missing.
+    pub fn update(&mut self, key: &[u8], value: u64) -> Result<Option<u64>> {
+        let key = self.fixed_key(key);
+        let mut page = self.root_page;
+        loop {
+            let mut node = self.read_node(page)?;
+            let i = Self::lower_bound(&node, &key);
+            if i < node.n as usize && node.keys[i] == key {
+                self.begin_write()?;
+                let old = std::mem::replace(&mut node.values[i], value);
+                self.write_node_at(page, &node)?;
+                return Ok(Some(old));
+            }
+            if node.is_leaf() {
+                return Ok(None);
+            }
+            page = node.children[i];
+        }
+    }
+
+    /// Looks up keys given in ascending order. Each node is read at most once, so neighbouring
+    /// keys share the reads of the path they have in common.
+    pub fn search_sorted(&mut self, keys: &[Vec<u8>]) -> Result<Vec<Option<u64>>> {
+        let keys: Vec<Vec<u8>> = keys.iter().map(|key| self.fixed_key(key)).collect();
+        let mut values = vec![None; keys.len()];
+        if !keys.is_empty() {
+            self.search_sorted_in(self.root_page, &keys, &mut values)?;
+        }
+        Ok(values)
     }
 
-    fn search_in_node(&mut self, offset: u64, key: &[u8]) -> io::Result<Option<u64>> {
-        let node = self.read_node(offset)?;
+    fn search_sorted_in(&mut self, page: u64, keys: &[Vec<u8>], values: &mut [Option<u64>]) -> Result<()> {
+        let node = self.read_node(page)?;
 
+        let mut start = 0;
+        while start < keys.len() {
+            let low = Self::lower_bound(&node, &keys[start]);
+            if low < node.n as usize && node.keys[low] == keys[start] {
+                values[start] = Some(node.values[low]);
+                start += 1;
+                continue;
+            }
+
+            // Every following key below the separator goes down the same child.
+            let end = if low < node.n as usize {
+                start + keys[start..].partition_point(|key| *key < node.keys[low])
+            } else {
+                keys.len()
+            };
+            if node.children[low] != NULL_PAGE {
+                self.search_sorted_in(node.children[low], &keys[start..end], &mut values[start..end])?;
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    fn fixed_key(&self, key: &[u8]) -> Vec<u8> {
         let mut fixed_key = vec![0u8; self.keysize as usize];
         let len = key.len().min(self.keysize as usize);
         fixed_key[..len].copy_from_slice(&key[..len]);
+        fixed_key
+    }
 
+    // Index of the first key in `node` that is not less than `key`.
+    fn lower_bound(node: &Node, key: &[u8]) -> usize {
         let mut low = 0;
         let mut high = node.n as usize;
 
         while low < high {
             let mid = (low + high) / 2;
-            if node.keys[mid].as_slice() < fixed_key.as_slice() {
+            if node.keys[mid].as_slice() < key {
                 low = mid + 1;
             } else {
                 high = mid;
             }
         }
 
+        low
+    }
+
+    // Index of the first key in `node` that is greater than `key`.
+    fn upper_bound(node: &Node, key: &[u8]) -> usize {
+        let i = Self::lower_bound(node, key);
+        if i < node.n as usize && node.keys[i] == key { i + 1 } else { i }
+    }
+
+    /// Iterates over the keys in `range` in order, with the value stored under each. The
+    /// iterator is double-ended, and each end only reads pages once it is first advanced.
+    pub fn range<'k>(&mut self, range: impl RangeBounds<&'k [u8]>) -> Result<Range<'_>> {
+        let start = range.start_bound().map(|key| self.fixed_key(key));
+        let end = range.end_bound().map(|key| self.fixed_key(key));
+
+        Ok(Range {
+            index: self,
+            start,
+            end,
+            front: None,
+            back: None,
+            front_key: None,
+            back_key: None,
+        })
+    }
+
+    /// Iterates over the keys that start with `prefix`. Stored keys are zero-padded, so a
+    /// prefix longer than the key size only matches if the extra bytes are zeros.
+    pub fn prefix_scan(&mut self, prefix: &[u8]) -> Result<Range<'_>> {
+        let (start, end) = prefix_range(prefix, self.keysize as usize);
+        self.range((start.as_ref().map(Vec::as_slice), end.as_ref().map(Vec::as_slice)))
+    }
+
+    pub fn iter(&mut self) -> Result<Range<'_>> {
+        self.range((Bound::Unbounded, Bound::<&[u8]>::Unbounded))
+    }
+
+    /// Iterates over all keys in descending order.
+    pub fn iter_rev(&mut self) -> Result<std::iter::Rev<Range<'_>>> {
+        Ok(self.iter()?.rev())
+    }
+
+    fn search_in_node(&mut self, page: u64, key: &[u8]) -> Result<Option<u64>> {
+        let node = self.read_node(page)?;
+
+        let fixed_key = self.fixed_key(key);
+        let low = Self::lower_bound(&node, &fixed_key);
+
         if low < node.n as usize && node.keys[low].as_slice() == fixed_key.as_slice() {
             return Ok(Some(node.values[low]));
         }
 
-        if node.children[low] == -1 {
+        if node.children[low] == NULL_PAGE {
             Ok(None)
         } else {
-            self.search_in_node(node.children[low] as u64, key)
+            self.search_in_node(node.children[low], key)
+        }
+    }
+}
+
+impl Index {
+    // CLRS deletion: every node the descent enters is first topped up to at least t keys, by
+    // borrowing from a sibling or merging with one, so removing a key never underflows it.
+    // Pages emptied by a merge are not reused; rebuilding the index reclaims them.
+    pub fn delete(&mut self, key: &[u8]) -> Result<Option<u64>> {
+        let key = self.fixed_key(key);
+        if self.search(&key)?.is_none() {
+            return Ok(None);
+        }
+
+        self.begin_write()?;
+        let value = self.delete_from(self.root_page, &key)?;
+        self.len -= 1;
+
+        let root = self.read_node(self.root_page)?;
+        if root.n == 0 && !root.is_leaf() {
+            self.root_page = root.children[0];
+            self.write_header()?;
+        }
+
+        Ok(Some(value))
+    }
+
+    fn delete_from(&mut self, page: u64, key: &[u8]) -> Result<u64> {
+        let mut node = self.read_node(page)?;
+        let i = Self::lower_bound(&node, key);
+
+        if i < node.n as usize && node.keys[i] == key {
+            let value = node.values[i];
+
+            if node.is_leaf() {
+                node.keys.remove(i);
+                node.values.remove(i);
+                node.n -= 1;
+                self.write_node_at(page, &node)?;
+                return Ok(value);
+            }
+
+            let left = node.children[i];
+            let right = node.children[i + 1];
+
+            if self.read_node(left)?.n >= self.t {
+                let (pred_key, pred_value) = self.last_entry(left)?;
+                node.keys[i] = pred_key.clone();
+                node.values[i] = pred_value;
+                self.count_removal(&mut node, i);
+                self.write_node_at(page, &node)?;
+                self.delete_from(left, &pred_key)?;
+            } else if self.read_node(right)?.n >= self.t {
+                let (succ_key, succ_value) = self.first_entry(right)?;
+                node.keys[i] = succ_key.clone();
+                node.values[i] = succ_value;
+                self.count_removal(&mut node, i + 1);
+                self.write_node_at(page, &node)?;
+                self.delete_from(right, &succ_key)?;
+            } else {
+                self.merge_children(&mut node, i)?;
+                self.count_removal(&mut node, i);
+                self.write_node_at(page, &node)?;
+                self.delete_from(left, key)?;
+            }
+
+            return Ok(value);
+        }
+
+        if node.is_leaf() {
+            return Err(Error::KeyNotFound);
+        }
+
+        let mut i = i;
+        let filled = self.read_node(node.children[i])?.n < self.t;
+        if filled {
+            i = self.fill_child(&mut node, i)?;
+        }
+        self.count_removal(&mut node, i);
+        if filled || self.counted {
+            self.write_node_at(page, &node)?;
+        }
+
+        self.delete_from(node.children[i], key)
+    }
+
+    // Records that the subtree under child i of `node` is about to lose a key.
+    fn count_removal(&self, node: &mut Node, i: usize) {
+        if self.counted {
+            node.counts[i] -= 1;
+        }
+    }
+
+    // Gives child i of `parent` at least t keys. Returns the index the child ends up at, which
+    // moves left when it is merged into its left sibling.
+    fn fill_child(&mut self, parent: &mut Node, i: usize) -> Result<usize> {
+        let child_page = parent.children[i];
+
+        if i > 0 {
+            let left_page = parent.children[i - 1];
+            let mut left = self.read_node(left_page)?;
+            if left.n >= self.t {
+                let mut child = self.read_node(child_page)?;
+                let last = left.n as usize - 1;
+
+                child.keys.insert(0, std::mem::replace(&mut parent.keys[i - 1], left.keys.remove(last)));
+                child.values.insert(0, std::mem::replace(&mut parent.values[i - 1], left.values.remove(last)));
+                if !child.is_leaf() {
+                    child.children.insert(0, left.children[last + 1]);
+                    child.counts.insert(0, left.counts[last + 1]);
+                    left.children[last + 1] = NULL_PAGE;
+                    left.counts[last + 1] = 0;
+                }
+                left.n -= 1;
+                child.n += 1;
+                parent.counts[i - 1] = left.subtree_len();
+                parent.counts[i] = child.subtree_len();
+
+                self.write_node_at(left_page, &left)?;
+                self.write_node_at(child_page, &child)?;
+                return Ok(i);
+            }
+        }
+
+        if i < parent.n as usize {
+            let right_page = parent.children[i + 1];
+            let mut right = self.read_node(right_page)?;
+            if right.n >= self.t {
+                let mut child = self.read_node(child_page)?;
+                let end = child.n as usize;
+
+                child.keys.insert(end, std::mem::replace(&mut parent.keys[i], right.keys.remove(0)));
+                child.values.insert(end, std::mem::replace(&mut parent.values[i], right.values.remove(0)));
+                if !child.is_leaf() {
+                    child.children.insert(end + 1, right.children.remove(0));
+                    child.counts.insert(end + 1, right.counts.remove(0));
+                }
+                right.n -= 1;
+                child.n += 1;
+                parent.counts[i] = child.subtree_len();
+                parent.counts[i + 1] = right.subtree_len();
+
+                self.write_node_at(right_page, &right)?;
+                self.write_node_at(child_page, &child)?;
+                return Ok(i);
+            }
+
+            self.merge_children(parent, i)?;
+            return Ok(i);
+        }
+
+        self.merge_children(parent, i - 1)?;
+        Ok(i - 1)
+    }
+
+    // Pulls key i of `parent` down into child i and appends child i + 1 after it.
+    fn merge_children(&mut self, parent: &mut Node, i: usize) -> Result<()> {
+        let left_page = parent.children[i];
+        let mut left = self.read_node(left_page)?;
+        let right = self.read_node(parent.children[i + 1])?;
+
+        let n = left.n as usize;
+        let right_n = right.n as usize;
+        left.keys.truncate(n);
+        left.values.truncate(n);
+        left.children.truncate(n + 1);
+        left.counts.truncate(n + 1);
+
+        left.keys.push(parent.keys.remove(i));
+        left.values.push(parent.values.remove(i));
+        left.keys.extend_from_slice(&right.keys[..right_n]);
+        left.values.extend_from_slice(&right.values[..right_n]);
+        left.children.extend_from_slice(&right.children[..right_n + 1]);
+        left.counts.extend_from_slice(&right.counts[..right_n + 1]);
+        left.n += 1 + right.n;
+
+        parent.children.remove(i + 1);
+        parent.counts.remove(i + 1);
+        parent.counts[i] = left.subtree_len();
+        parent.n -= 1;
+
+        self.write_node_at(left_page, &left)
+    }
+
+    /// The smallest key, found by following the leftmost path down from the root.
+    pub fn min_key(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.first_entry(self.root_page)?.0))
+    }
+
+    /// The largest key, found by following the rightmost path down from the root.
+    pub fn max_key(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.last_entry(self.root_page)?.0))
+    }
+
+    /// The number of keys less than `key`, whether or not `key` itself is present.
+    pub fn rank(&mut self, key: &[u8]) -> Result<u64> {
+        self.check_counted()?;
+        let key = self.fixed_key(key);
+
+        let mut rank = 0;
+        let mut page = self.root_page;
+        loop {
+            let node = self.read_node(page)?;
+            let i = Self::lower_bound(&node, &key);
+            rank += i as u64;
+            if node.is_leaf() {
+                return Ok(rank);
+            }
+
+            rank += node.counts[..i].iter().sum::<u64>();
+            if i < node.n as usize && node.keys[i] == key {
+                return Ok(rank + node.counts[i]);
+            }
+            page = node.children[i];
+        }
+    }
+
+    /// The entry at `position` in key order, counting from 0.
+    pub fn select(&mut self, position: u64) -> Result<Option<(Vec<u8>, u64)>> {
+        self.check_counted()?;
+        if position >= self.len {
+            return Ok(None);
+        }
+
+        let mut position = position;
+        let mut page = self.root_page;
+        'descend: loop {
+            let mut node = self.read_node(page)?;
+            if node.is_leaf() {
+                let i = position as usize;
+                if i >= node.n as usize {
+                    break;
+                }
+                return Ok(Some((std::mem::take(&mut node.keys[i]), node.values[i])));
+            }
+
+            for i in 0..=node.n as usize {
+                if position < node.counts[i] {
+                    page = node.children[i];
+                    continue 'descend;
+                }
+                position -= node.counts[i];
+                if i < node.n as usize {
+                    if position == 0 {
+                        return Ok(Some((std::mem::take(&mut node.keys[i]), node.values[i])));
+                    }
+                    position -= 1;
+                }
+            }
+            break;
         }
+        Err(Error::Corruption("subtree counts do not add up to the index length".to_string()))
     }
 
+    fn check_counted(&self) -> Result<()> {
+        if !self.counted {
+            return Err(Error::InvalidOptions(
+                "rank and select need an index created with subtree counts".to_string(),
+            ));
+        }
+        Ok(())
+    }
 
+    fn first_entry(&mut self, mut page: u64) -> Result<(Vec<u8>, u64)> {
+        loop {
+            let node = self.read_node(page)?;
+            if node.is_leaf() {
+                return Ok((node.keys[0].clone(), node.values[0]));
+            }
+            page = node.children[0];
+        }
+    }
+
+    fn last_entry(&mut self, mut page: u64) -> Result<(Vec<u8>, u64)> {
+        loop {
+            let node = self.read_node(page)?;
+            let n = node.n as usize;
+            if node.is_leaf() {
+                return Ok((node.keys[n - 1].clone(), node.values[n - 1]));
+            }
+            page = node.children[n];
+        }
+    }
+
+    /// Checks the B-tree invariants: key order, node occupancy, uniform leaf depth, subtree
+    /// counts if the index keeps them, and the entry count in the header.
+    pub fn verify(&mut self) -> Result<()> {
+        let mut leaf_depth = None;
+        let count = self.verify_node(self.root_page, 0, None, None, &mut leaf_depth)?;
+        if count != self.len {
+            return Err(Error::Corruption(format!(
+                "index holds {} keys but its header counts {}",
+                count, self.len
+            )));
+        }
+        Ok(())
+    }
+
+    fn verify_node(
+        &mut self,
+        page: u64,
+        depth: u32,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+        leaf_depth: &mut Option<u32>,
+    ) -> Result<u64> {
+        let node = self.read_node(page)?;
+        let n = node.n as usize;
+
+        if page != self.root_page && node.n < self.t - 1 {
+            return Err(Error::Corruption(format!("page {} holds only {} keys", page, n)));
+        }
+
+        for i in 0..n {
+            let key = node.keys[i].as_slice();
+            let after_lower = lower.is_none_or(|lower| lower < key);
+            let before_upper = upper.is_none_or(|upper| key < upper);
+            let sorted = i == 0 || node.keys[i - 1].as_slice() < key;
+            if !(after_lower && before_upper && sorted) {
+                return Err(Error::Corruption(format!("page {} has keys out of order", page)));
+            }
+        }
+
+        if node.is_leaf() {
+            if *leaf_depth.get_or_insert(depth) != depth {
+                return Err(Error::Corruption(format!("leaf page {} is at the wrong depth", page)));
+            }
+            return Ok(n as u64);
+        }
+
+        let mut count = n as u64;
+        for i in 0..=n {
+            if node.children[i] == NULL_PAGE {
+                return Err(Error::Corruption(format!("page {} is missing child {}", page, i)));
+            }
+            let child_lower = if i == 0 { lower } else { Some(node.keys[i - 1].as_slice()) };
+            let child_upper = if i == n { upper } else { Some(node.keys[i].as_slice()) };
+            let child_count = self.verify_node(node.children[i], depth + 1, child_lower, child_upper, leaf_depth)?;
+            if self.counted && node.counts[i] != child_count {
+                return Err(Error::Corruption(format!(
+                    "page {} counts {} keys under child {}, found {}",
+                    page, node.counts[i], i, child_count
+                )));
+            }
+            count += child_count;
+        }
+        Ok(count)
+    }
+}
+
+// The range of keys, zero-padded to `keysize`, that start with `prefix`. Its end is the first
+// key past the prefix: trailing 0xff bytes dropped and the last one left incremented. A prefix
+// longer than the key size only matches if the extra bytes are zeros.
+pub(crate) fn prefix_range(prefix: &[u8], keysize: usize) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    if prefix.len() > keysize {
+        let (head, tail) = prefix.split_at(keysize);
+        if tail.iter().any(|&b| b != 0) {
+            return (Bound::Included(head.to_vec()), Bound::Excluded(head.to_vec()));
+        }
+        return prefix_range(head, keysize);
+    }
+
+    let mut end = prefix.to_vec();
+    while end.last() == Some(&0xff) {
+        end.pop();
+    }
+    match end.last_mut() {
+        Some(last) => {
+            *last += 1;
+            (Bound::Included(prefix.to_vec()), Bound::Excluded(end))
+        }
+        None => (Bound::Included(prefix.to_vec()), Bound::Unbounded),
+    }
+}
+
+/// In-order iterator over a key range, returned by `Index::range`.
+///
+/// Each end holds the path from the root to its next key, and each step reads at most the
+/// pages on the way down to the following leaf. The ends remember the last key they returned,
+/// so they stop when they meet.
+pub struct Range<'a> {
+    index: &'a mut Index,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    // Front entries point at the next key to return; back entries count the keys left to
+    // return from that node, so the next one is at `i - 1`.
+    front: Option<Vec<(Node, usize)>>,
+    back: Option<Vec<(Node, usize)>>,
+    front_key: Option<Vec<u8>>,
+    back_key: Option<Vec<u8>>,
+}
+
+impl Range<'_> {
+    fn seek_front(&mut self) -> Result<Vec<(Node, usize)>> {
+        let mut stack = Vec::new();
+        let mut page = self.index.root_page;
+        loop {
+            let node = self.index.read_node(page)?;
+            let i = match &self.start {
+                Bound::Included(key) => Index::lower_bound(&node, key),
+                Bound::Excluded(key) => Index::upper_bound(&node, key),
+                Bound::Unbounded => 0,
+            };
+            let child = node.children[i];
+            let leaf = node.is_leaf();
+            stack.push((node, i));
+            if leaf {
+                return Ok(stack);
+            }
+            page = child;
+        }
+    }
+
+    fn seek_back(&mut self) -> Result<Vec<(Node, usize)>> {
+        let mut stack = Vec::new();
+        let mut page = self.index.root_page;
+        loop {
+            let node = self.index.read_node(page)?;
+            let i = match &self.end {
+                Bound::Included(key) => Index::upper_bound(&node, key),
+                Bound::Excluded(key) => Index::lower_bound(&node, key),
+                Bound::Unbounded => node.n as usize,
+            };
+            let child = node.children[i];
+            let leaf = node.is_leaf();
+            stack.push((node, i));
+            if leaf {
+                return Ok(stack);
+            }
+            page = child;
+        }
+    }
+
+    fn descend_leftmost(index: &mut Index, stack: &mut Vec<(Node, usize)>, mut page: u64) -> Result<()> {
+        loop {
+            let node = index.read_node(page)?;
+            let child = node.children[0];
+            let leaf = node.is_leaf();
+            stack.push((node, 0));
+            if leaf {
+                return Ok(());
+            }
+            page = child;
+        }
+    }
+
+    fn descend_rightmost(index: &mut Index, stack: &mut Vec<(Node, usize)>, mut page: u64) -> Result<()> {
+        loop {
+            let node = index.read_node(page)?;
+            let n = node.n as usize;
+            let child = node.children[n];
+            let leaf = node.is_leaf();
+            stack.push((node, n));
+            if leaf {
+                return Ok(());
+            }
+            page = child;
+        }
+    }
+
+    fn past_end(&self, key: &[u8]) -> bool {
+        let past_bound = match &self.end {
+            Bound::Included(end) => key > end.as_slice(),
+            Bound::Excluded(end) => key >= end.as_slice(),
+            Bound::Unbounded => false,
+        };
+        past_bound || self.back_key.as_ref().is_some_and(|back| key >= back.as_slice())
+    }
+
+    fn before_start(&self, key: &[u8]) -> bool {
+        let before_bound = match &self.start {
+            Bound::Included(start) => key < start.as_slice(),
+            Bound::Excluded(start) => key <= start.as_slice(),
+            Bound::Unbounded => false,
+        };
+        before_bound || self.front_key.as_ref().is_some_and(|front| key <= front.as_slice())
+    }
+
+    fn finish(&mut self) {
+        self.front = Some(Vec::new());
+        self.back = Some(Vec::new());
+    }
+
+    // Remembers the last key handed out from one end, reusing the buffer.
+    fn remember(slot: &mut Option<Vec<u8>>, key: &[u8]) {
+        let last = slot.get_or_insert_with(Vec::new);
+        last.clear();
+        last.extend_from_slice(key);
+    }
+}
+
+impl Iterator for Range<'_> {
+    type Item = Result<(Vec<u8>, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front.is_none() {
+            match self.seek_front() {
+                Ok(stack) => self.front = Some(stack),
+                Err(err) => {
+                    self.finish();
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        loop {
+            let stack = self.front.as_mut().unwrap();
+            let (node, i) = stack.last_mut()?;
+            if *i >= node.n as usize {
+                stack.pop();
+                continue;
+            }
+
+            let key = std::mem::take(&mut node.keys[*i]);
+            let value = node.values[*i];
+            *i += 1;
+            let child = if node.is_leaf() { None } else { Some(node.children[*i]) };
+
+            if self.past_end(&key) {
+                self.finish();
+                return None;
+            }
+            if let Some(page) = child
+                && let Err(err) = Self::descend_leftmost(self.index, self.front.as_mut().unwrap(), page)
+            {
+                self.finish();
+                return Some(Err(err));
+            }
+            Self::remember(&mut self.front_key, &key);
+            return Some(Ok((key, value)));
+        }
+    }
+}
+
+impl DoubleEndedIterator for Range<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.back.is_none() {
+            match self.seek_back() {
+                Ok(stack) => self.back = Some(stack),
+                Err(err) => {
+                    self.finish();
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        loop {
+            let stack = self.back.as_mut().unwrap();
+            let (node, i) = stack.last_mut()?;
+            if *i == 0 {
+                stack.pop();
+                continue;
+            }
+
+            *i -= 1;
+            let key = std::mem::take(&mut node.keys[*i]);
+            let value = node.values[*i];
+            let child = if node.is_leaf() { None } else { Some(node.children[*i]) };
+
+            if self.before_start(&key) {
+                self.finish();
+                return None;
+            }
+            if let Some(page) = child
+                && let Err(err) = Self::descend_rightmost(self.index, self.back.as_mut().unwrap(), page)
+            {
+                self.finish();
+                return Some(Err(err));
+            }
+            Self::remember(&mut self.back_key, &key);
+            return Some(Ok((key, value)));
+        }
+    }
+}
+
+impl Drop for Index {
+    fn drop(&mut self) {
+        if self.dirty {
+            let _ = self.sync();
+        }
+    }
 }
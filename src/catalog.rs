@@ -0,0 +1,91 @@
+use crate::error::{Error, Result};
+use crate::schema::Schema;
+use crate::storage::sync_parent_dir;
+use bincode::{Decode, Encode};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RDBC";
+const VERSION: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct TableInfo {
+    pub name: String,
+    pub keysize: u16,
+    pub recordsize: u16,
+    pub page_size: u32,
+    pub t: u32,
+    pub datafile: String,
+    pub indexfile: String,
+    // Only tables created through SQL have typed columns, and only those can have secondary
+    // indexes.
+    pub schema: Option<Schema>,
+    pub indexes: Vec<IndexInfo>,
+}
+
+/// A secondary index on one column, kept up to date by SQL statements.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct IndexInfo {
+    pub name: String,
+    pub column: String,
+    pub file: String,
+}
+
+#[derive(Debug, Default, Encode, Decode)]
+pub(crate) struct Catalog {
+    pub(crate) tables: Vec<TableInfo>,
+}
+
+impl Catalog {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Catalog::default());
+        }
+
+        let bytes = fs::read(path)?;
+        if bytes.len() < 6 || &bytes[0..4] != MAGIC {
+            return Err(Error::Corruption("not a catalog file".to_string()));
+        }
+        let version = u16::from_le_bytes(bytes[4..6].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::VersionMismatch {
+                found: version,
+                expected: VERSION,
+            });
+        }
+
+        let (catalog, _) = bincode::decode_from_slice(&bytes[6..], bincode::config::standard())
+            .map_err(|err| Error::Corruption(format!("unreadable catalog: {}", err)))?;
+        Ok(catalog)
+    }
+
+    // Written to a temporary file and renamed over the old one, so a crash leaves either
+    // the previous catalog or the new one.
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend(
+            bincode::encode_to_vec(self, bincode::config::standard())
+                .map_err(|err| Error::Corruption(format!("cannot encode catalog: {}", err)))?,
+        );
+
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        // Until the directory is synced a crash can still bring back the old catalog, and with it
+        // a dropped table, or lose a new one.
+        sync_parent_dir(path)?;
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&TableInfo> {
+        self.tables.iter().find(|info| info.name == name)
+    }
+
+    pub(crate) fn get_mut(&mut self, name: &str) -> Option<&mut TableInfo> {
+        self.tables.iter_mut().find(|info| info.name == name)
+    }
+}
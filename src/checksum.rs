@@ -0,0 +1,11 @@
+// FNV-1a, a fixed function so that whatever hashes keys or checksums data on disk stays valid
+// across builds. It hashes keys for the hash index and the Bloom filter, and checksums the LSM
+// manifest and WAL batches.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
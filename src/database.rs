@@ -0,0 +1,222 @@
+use crate::catalog::{Catalog, IndexInfo, TableInfo};
+use crate::error::{Error, Result};
+use crate::schema::Schema;
+use crate::table::{Table, TableOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CATALOG_FILE: &str = "catalog";
+
+/// A directory of named tables, with a catalog remembering how each one was created.
+pub struct Database {
+    dir: PathBuf,
+    catalog: Catalog,
+}
+
+impl Database {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let catalog = Catalog::load(&dir.join(CATALOG_FILE))?;
+        Ok(Database { dir, catalog })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn tables(&self) -> &[TableInfo] {
+        &self.catalog.tables
+    }
+
+    pub fn table_info(&self, name: &str) -> Option<&TableInfo> {
+        self.catalog.get(name)
+    }
+
+    pub fn create_table(&mut self, name: &str, options: &TableOptions) -> Result<Table> {
+        self.create_table_with_schema(name, options, None)
+    }
+
+    pub(crate) fn create_table_with_schema(
+        &mut self,
+        name: &str,
+        options: &TableOptions,
+        schema: Option<Schema>,
+    ) -> Result<Table> {
+        check_name(name)?;
+        if self.catalog.get(name).is_some() {
+            return Err(Error::TableExists(name.to_string()));
+        }
+
+        let datafile = format!("{}.dat", name);
+        let indexfile = format!("{}.ndx", name);
+        let mut options = options.clone();
+        options.index_path(self.dir.join(&indexfile));
+        if !options.files(self.dir.join(&datafile))?.is_empty() {
+            return Err(Error::TableExists(name.to_string()));
+        }
+
+        let table = options.open(self.dir.join(&datafile))?;
+        let stats = table.stats()?;
+
+        self.catalog.tables.push(TableInfo {
+            name: name.to_string(),
+            keysize: table.keysize(),
+            recordsize: table.recordsize(),
+            page_size: stats.page_size,
+            t: stats.t,
+            datafile,
+            indexfile,
+            schema,
+            indexes: Vec::new(),
+        });
+        self.save()?;
+
+        Ok(table)
+    }
+
+    pub fn open_table(&self, name: &str) -> Result<Table> {
+        self.open_table_with(name, &TableOptions::new())
+    }
+
+    // Sizes always come from the catalog; only the runtime settings (backend, durability) are
+    // taken from `options`.
+    pub fn open_table_with(&self, name: &str, options: &TableOptions) -> Result<Table> {
+        let info = self
+            .catalog
+            .get(name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+
+        options
+            .clone()
+            .key_size(info.keysize)
+            .record_size(info.recordsize)
+            .page_size(info.page_size)
+            .index_path(self.dir.join(&info.indexfile))
+            .open(self.dir.join(&info.datafile))
+    }
+
+    // Every file of the table, whatever its engine and options keep next to the datafile.
+    fn table_files(&self, info: &TableInfo) -> Result<Vec<PathBuf>> {
+        TableOptions::new()
+            .index_path(self.dir.join(&info.indexfile))
+            .files(self.dir.join(&info.datafile))
+    }
+
+    pub fn rename_table(&mut self, name: &str, new_name: &str) -> Result<()> {
+        check_name(new_name)?;
+        if self.catalog.get(new_name).is_some() {
+            return Err(Error::TableExists(new_name.to_string()));
+        }
+        let info = self
+            .catalog
+            .get(name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?
+            .clone();
+
+        // The index file is named by the catalog; every other file is named after the datafile.
+        let datafile = format!("{}.dat", new_name);
+        let indexfile = format!("{}.ndx", new_name);
+        let mut renames = Vec::new();
+        for file in self.table_files(&info)? {
+            let target = if file == self.dir.join(&info.indexfile) {
+                self.dir.join(&indexfile)
+            } else {
+                let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+                match file_name.strip_prefix(&info.datafile) {
+                    Some(rest) => self.dir.join(format!("{}{}", datafile, rest)),
+                    None => continue,
+                }
+            };
+            if target.exists() {
+                return Err(Error::TableExists(new_name.to_string()));
+            }
+            renames.push((file, target));
+        }
+        for (file, target) in renames {
+            fs::rename(file, target)?;
+        }
+
+        let mut index_files = Vec::new();
+        for index in &info.indexes {
+            let file = index_file(new_name, &index.name);
+            if self.dir.join(&index.file).exists() {
+                fs::rename(self.dir.join(&index.file), self.dir.join(&file))?;
+            }
+            index_files.push(file);
+        }
+
+        let entry = self.catalog.get_mut(name).unwrap();
+        entry.name = new_name.to_string();
+        entry.datafile = datafile;
+        entry.indexfile = indexfile;
+        for (index, file) in entry.indexes.iter_mut().zip(index_files) {
+            index.file = file;
+        }
+        self.save()
+    }
+
+    pub fn drop_table(&mut self, name: &str) -> Result<()> {
+        let position = self
+            .catalog
+            .tables
+            .iter()
+            .position(|info| info.name == name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+
+        let info = self.catalog.tables.remove(position);
+        self.save()?;
+
+        let index_files = info.indexes.iter().map(|index| self.dir.join(&index.file));
+        for path in self.table_files(&info)?.into_iter().chain(index_files) {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Registers a secondary index; building its contents is up to the caller.
+    pub(crate) fn add_index(&mut self, table: &str, name: &str, column: &str) -> Result<IndexInfo> {
+        check_name(name)?;
+        let info = self
+            .catalog
+            .get_mut(table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        if info.indexes.iter().any(|index| index.name == name) {
+            return Err(Error::TableExists(format!("{}.{}", table, name)));
+        }
+
+        let index = IndexInfo {
+            name: name.to_string(),
+            column: column.to_string(),
+            file: index_file(table, name),
+        };
+        info.indexes.push(index.clone());
+        self.save()?;
+        Ok(index)
+    }
+
+    pub(crate) fn file_path(&self, file: &str) -> PathBuf {
+        self.dir.join(file)
+    }
+
+    fn save(&self) -> Result<()> {
+        self.catalog.save(&self.dir.join(CATALOG_FILE))
+    }
+}
+
+fn index_file(table: &str, index: &str) -> String {
+    format!("{}.{}.idx", table, index)
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(Error::InvalidTableName(name.to_string()));
+    }
+    Ok(())
+}
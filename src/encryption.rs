@@ -0,0 +1,77 @@
+use crate::error::{Error, Result};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::fmt;
+
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+// What sealing adds to a plaintext: the nonce in front of it and the tag behind it.
+pub(crate) const SEAL_SIZE: usize = NONCE_SIZE + TAG_SIZE;
+
+/// A 256-bit key for encrypting a table at rest. It is never stored; the files only keep a
+/// check that tells a wrong key apart from a damaged file.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        EncryptionKey(key)
+    }
+}
+
+impl From<[u8; 32]> for EncryptionKey {
+    fn from(key: [u8; 32]) -> Self {
+        EncryptionKey(key)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+// XChaCha20-Poly1305 with a random nonce per sealed value. Its 192-bit nonces can be drawn at
+// random for as many writes as a table will ever see.
+#[derive(Clone)]
+pub(crate) struct Cipher(XChaCha20Poly1305);
+
+impl Cipher {
+    pub(crate) fn new(key: &EncryptionKey) -> Self {
+        Cipher(XChaCha20Poly1305::new(&key.0.into()))
+    }
+
+    // nonce + ciphertext + tag. `aad` is authenticated along with the plaintext, so a sealed
+    // value only opens where it was written.
+    pub(crate) fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = self
+            .0
+            .encrypt(&nonce, Payload { msg: plaintext, aad })
+            .expect("plaintext fits in one XChaCha20-Poly1305 message");
+
+        let mut out = Vec::with_capacity(SEAL_SIZE + plaintext.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        out
+    }
+
+    pub(crate) fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < SEAL_SIZE {
+            return Err(Error::Corruption("sealed value is shorter than its nonce and tag".to_string()));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        self.0
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| Error::Corruption("sealed value fails authentication".to_string()))
+    }
+
+    // Seals nothing, so that only the key that wrote it opens it again.
+    pub(crate) fn key_check(&self, aad: &[u8]) -> Vec<u8> {
+        self.seal(&[], aad)
+    }
+
+    pub(crate) fn verify_key(&self, check: &[u8], aad: &[u8]) -> Result<()> {
+        self.open(check, aad).map(|_| ()).map_err(|_| Error::WrongKey)
+    }
+}
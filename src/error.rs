@@ -0,0 +1,64 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    DuplicateKey,
+    KeyNotFound,
+    KeyTooLarge { len: usize, max: usize },
+    RecordTooLarge { len: usize, max: usize },
+    PageTooSmall { page_size: u32, keysize: u16 },
+    InvalidOptions(String),
+    InvalidTableName(String),
+    TableExists(String),
+    TableNotFound(String),
+    Corruption(String),
+    VersionMismatch { found: u16, expected: u16 },
+    WrongKey,
+    Unsupported(String),
+    Sql(String),
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DuplicateKey => write!(f, "key already exists"),
+            Error::KeyNotFound => write!(f, "key not found"),
+            Error::KeyTooLarge { len, max } => write!(f, "key is {} bytes, at most {} allowed", len, max),
+            Error::RecordTooLarge { len, max } => write!(f, "record is {} bytes, at most {} allowed", len, max),
+            Error::PageTooSmall { page_size, keysize } => {
+                write!(f, "page size {} is too small for {}-byte keys", page_size, keysize)
+            }
+            Error::InvalidOptions(msg) => write!(f, "invalid options: {}", msg),
+            Error::InvalidTableName(name) => write!(f, "invalid table name {:?}", name),
+            Error::TableExists(name) => write!(f, "table {:?} already exists", name),
+            Error::TableNotFound(name) => write!(f, "table {:?} not found", name),
+            Error::Corruption(msg) => write!(f, "corruption: {}", msg),
+            Error::VersionMismatch { found, expected } => {
+                write!(f, "format version {} is not supported, expected {}", found, expected)
+            }
+            Error::WrongKey => write!(f, "wrong encryption key"),
+            Error::Unsupported(msg) => write!(f, "not supported: {}", msg),
+            Error::Sql(msg) => write!(f, "SQL error: {}", msg),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
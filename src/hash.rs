@@ -0,0 +1,676 @@
+use crate::checksum::fnv1a;
+use crate::encryption::{Cipher, SEAL_SIZE};
+use crate::error::{Error, Result};
+use crate::storage::{Backend, Durability, MemStorage, Storage};
+use std::fs::OpenOptions;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RDBH";
+const VERSION: u16 = 1;
+
+// magic (4) + version (2) + page_size (4) + keysize (2) + bucket capacity (4) + global depth (1)
+// + dirty (1) + encrypted (1) + directory page (8) + directory pages (8) + page_count (8) + len (8)
+// + key check (SEAL_SIZE). As in the B-tree, the header page is never encrypted; the key check
+// seals the fields before the global depth.
+const HEADER_SIZE: usize = 51 + SEAL_SIZE;
+const KEY_CHECK_OFFSET: usize = 51;
+
+// Bucket page: local depth (1) + n (2), then n * (key + value (8)), in no particular order. In
+// an encrypted index buckets and directory pages are sealed with their page number, like nodes.
+const BUCKET_HEADER_SIZE: usize = 3;
+
+// Each doubling of the directory takes one more bit of the hash and doubles the directory kept in
+// memory, so it stops at 2^24 slots (128 MiB). Well-spread keys get there only with millions of
+// full buckets; a skewed key set that needs more fails its insert instead of allocating without
+// bound.
+const MAX_DEPTH: u8 = 24;
+
+// The SplitMix64 finalizer, which spreads every input bit over the low bits the directory uses.
+pub(crate) fn mix(mut hash: u64) -> u64 {
+    hash = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+struct Bucket {
+    depth: u8,
+    keys: Vec<Vec<u8>>,
+    values: Vec<u64>,
+}
+
+/// An on-disk extendible hash index for exact-key lookups: a directory of 2^depth bucket pages
+/// addressed by the low bits of each key's hash. A lookup reads one bucket; a full bucket splits
+/// in two and only doubles the directory when it is already as deep as the directory. It has
+/// the point operations of `Index`, but no key order.
+pub struct HashIndex {
+    storage: Box<dyn Storage>,
+    page_size: u32,
+    keysize: u16,
+    capacity: u32,
+    depth: u8,
+    // Bucket page of every hash suffix. Kept in memory and written at `sync`; until then the
+    // header says the index is dirty, and a table rebuilds it.
+    directory: Vec<u64>,
+    directory_page: u64,
+    directory_pages: u64,
+    directory_changed: bool,
+    page_count: u64,
+    len: u64,
+    dirty: bool,
+    cipher: Option<Cipher>,
+    // Pages read since the index was opened, as `Index::node_reads`.
+    reads: u64,
+}
+
+struct Header {
+    page_size: u32,
+    keysize: u16,
+    capacity: u32,
+    depth: u8,
+    dirty: bool,
+    encrypted: bool,
+    directory_page: u64,
+    directory_pages: u64,
+    page_count: u64,
+    len: u64,
+    key_check: Vec<u8>,
+}
+
+impl Header {
+    fn read(storage: &mut dyn Storage) -> Result<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        storage.read_at(0, &mut header)?;
+
+        if &header[0..4] != MAGIC {
+            return Err(Error::Corruption("not a hash index file".to_string()));
+        }
+        let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::VersionMismatch {
+                found: version,
+                expected: VERSION,
+            });
+        }
+
+        Ok(Header {
+            page_size: u32::from_le_bytes(header[6..10].try_into().unwrap()),
+            keysize: u16::from_le_bytes(header[10..12].try_into().unwrap()),
+            capacity: u32::from_le_bytes(header[12..16].try_into().unwrap()),
+            depth: header[16],
+            dirty: header[17] != 0,
+            encrypted: header[18] != 0,
+            directory_page: u64::from_le_bytes(header[19..27].try_into().unwrap()),
+            directory_pages: u64::from_le_bytes(header[27..35].try_into().unwrap()),
+            page_count: u64::from_le_bytes(header[35..43].try_into().unwrap()),
+            len: u64::from_le_bytes(header[43..51].try_into().unwrap()),
+            key_check: header[KEY_CHECK_OFFSET..HEADER_SIZE].to_vec(),
+        })
+    }
+}
+
+impl HashIndex {
+    // Entries a bucket page holds.
+    fn capacity_for(page_size: u32, keysize: u16, cipher: Option<&Cipher>) -> u32 {
+        let overhead = if cipher.is_some() { SEAL_SIZE } else { 0 };
+        let room = (page_size as usize).saturating_sub(overhead + BUCKET_HEADER_SIZE);
+        (room / (keysize as usize + 8)) as u32
+    }
+
+    pub fn create(path: impl AsRef<Path>, keysize: u16, page_size: u32) -> Result<Self> {
+        Self::create_with(path, keysize, page_size, Backend::File)
+    }
+
+    pub fn create_with(path: impl AsRef<Path>, keysize: u16, page_size: u32, backend: Backend) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Self::create_with_storage(backend.storage(file)?, keysize, page_size)
+    }
+
+    pub fn create_with_storage(storage: Box<dyn Storage>, keysize: u16, page_size: u32) -> Result<Self> {
+        Self::create_sealed(storage, keysize, page_size, None)
+    }
+
+    // A hash index whose buckets and directory are encrypted with `cipher`, if given.
+    pub(crate) fn create_sealed(
+        storage: Box<dyn Storage>,
+        keysize: u16,
+        page_size: u32,
+        cipher: Option<Cipher>,
+    ) -> Result<Self> {
+        let capacity = Self::capacity_for(page_size, keysize, cipher.as_ref());
+        // A directory page has to hold at least one entry, which any page with room for two
+        // keys does.
+        if capacity < 2 || (page_size as usize) < HEADER_SIZE {
+            return Err(Error::PageTooSmall { page_size, keysize });
+        }
+
+        let mut index = HashIndex {
+            storage,
+            page_size,
+            keysize,
+            capacity,
+            depth: 0,
+            directory: vec![1],
+            directory_page: 0,
+            directory_pages: 0,
+            directory_changed: true,
+            page_count: 2,
+            len: 0,
+            dirty: true,
+            cipher,
+            reads: 0,
+        };
+        let bucket = Bucket {
+            depth: 0,
+            keys: Vec::new(),
+            values: Vec::new(),
+        };
+        index.write_bucket(1, &bucket)?;
+        index.write_directory()?;
+
+        Ok(index)
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(path, Backend::File)
+    }
+
+    pub fn open_with(path: impl AsRef<Path>, backend: Backend) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Self::open_with_storage(backend.storage(file)?)
+    }
+
+    pub fn open_with_storage(storage: Box<dyn Storage>) -> Result<Self> {
+        Self::open_sealed(storage, None)
+    }
+
+    pub(crate) fn open_sealed(mut storage: Box<dyn Storage>, cipher: Option<Cipher>) -> Result<Self> {
+        let header = Header::read(storage.as_mut())?;
+        match (&cipher, header.encrypted) {
+            (None, true) => return Err(Error::InvalidOptions("the index is encrypted; a key is required".to_string())),
+            (Some(_), false) => return Err(Error::InvalidOptions("the index is not encrypted".to_string())),
+            (Some(cipher), true) => {
+                let mut fields = [0u8; 16];
+                storage.read_at(0, &mut fields)?;
+                cipher.verify_key(&header.key_check, &fields)?;
+            }
+            (None, false) => {}
+        }
+        if header.depth > MAX_DEPTH
+            || header.capacity != Self::capacity_for(header.page_size, header.keysize, cipher.as_ref())
+            || header.directory_pages == 0
+            || header.directory_page + header.directory_pages > header.page_count
+        {
+            return Err(Error::Corruption("hash index header is inconsistent".to_string()));
+        }
+
+        let mut index = HashIndex {
+            storage,
+            page_size: header.page_size,
+            keysize: header.keysize,
+            capacity: header.capacity,
+            depth: header.depth,
+            directory: Vec::new(),
+            directory_page: header.directory_page,
+            directory_pages: header.directory_pages,
+            directory_changed: false,
+            page_count: header.page_count,
+            len: header.len,
+            dirty: header.dirty,
+            cipher,
+            reads: 0,
+        };
+        index.read_directory()?;
+        Ok(index)
+    }
+
+    // Key count of an index that was synced and not written to since, as `Index::clean_len`.
+    pub fn clean_len(storage: &mut dyn Storage) -> Result<Option<u64>> {
+        match Header::read(storage) {
+            Ok(header) if !header.dirty => Ok(Some(header.len)),
+            Ok(_) | Err(Error::Corruption(_)) | Err(Error::VersionMismatch { .. }) => Ok(None),
+            Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        if self.directory_changed {
+            self.write_directory()?;
+        }
+        self.storage.sync()?;
+        if self.dirty {
+            self.dirty = false;
+            self.write_header()?;
+            self.storage.sync()?;
+        }
+        Ok(())
+    }
+
+    // Makes the buckets written so far as durable as `durability` asks. The directory is only
+    // written by `sync`, which is also the only thing that marks the index clean.
+    pub fn commit(&mut self, durability: Durability) -> Result<()> {
+        Ok(self.storage.commit(durability)?)
+    }
+
+    // The same barrier as `Index::begin_write`.
+    fn begin_write(&mut self) -> Result<()> {
+        if !self.dirty {
+            self.dirty = true;
+            self.write_header()?;
+            self.storage.sync_data()?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn keysize(&self) -> u16 {
+        self.keysize
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    /// Entries per bucket page.
+    pub fn bucket_capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// The number of hash bits the directory is addressed by.
+    pub fn global_depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn bucket_reads(&self) -> u64 {
+        self.reads
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    // Hands the storage over to a table that starts the index over, leaving this one with none.
+    pub(crate) fn take_storage(&mut self) -> Box<dyn Storage> {
+        std::mem::replace(&mut self.storage, Box::new(MemStorage::new()))
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let mut header = vec![0u8; self.page_size as usize];
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6..10].copy_from_slice(&self.page_size.to_le_bytes());
+        header[10..12].copy_from_slice(&self.keysize.to_le_bytes());
+        header[12..16].copy_from_slice(&self.capacity.to_le_bytes());
+        header[16] = self.depth;
+        header[17] = self.dirty as u8;
+        header[19..27].copy_from_slice(&self.directory_page.to_le_bytes());
+        header[27..35].copy_from_slice(&self.directory_pages.to_le_bytes());
+        header[35..43].copy_from_slice(&self.page_count.to_le_bytes());
+        header[43..51].copy_from_slice(&self.len.to_le_bytes());
+        if let Some(cipher) = &self.cipher {
+            header[18] = 1;
+            let check = cipher.key_check(&header[0..16]);
+            header[KEY_CHECK_OFFSET..HEADER_SIZE].copy_from_slice(&check);
+        }
+
+        Ok(self.storage.write_at(0, &header)?)
+    }
+
+    // Bytes of a page its contents can take up.
+    fn content_size(&self) -> usize {
+        self.page_size as usize - if self.cipher.is_some() { SEAL_SIZE } else { 0 }
+    }
+
+    fn write_page(&mut self, page: u64, buf: Vec<u8>) -> Result<()> {
+        let buf = match &self.cipher {
+            Some(cipher) => cipher.seal(&buf, &page.to_le_bytes()),
+            None => buf,
+        };
+        Ok(self.storage.write_at(page * self.page_size as u64, &buf)?)
+    }
+
+    fn read_page(&mut self, page: u64) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; self.page_size as usize];
+        self.storage.read_at(page * self.page_size as u64, &mut buf)?;
+        match &self.cipher {
+            Some(cipher) => cipher.open(&buf, &page.to_le_bytes()),
+            None => Ok(buf),
+        }
+    }
+
+    fn entries_per_directory_page(&self) -> usize {
+        self.content_size() / 8
+    }
+
+    // Rewrites the directory in place, or in a new run of pages at the end once it outgrew its
+    // old one. The old run is not reused; rebuilding the index reclaims it.
+    fn write_directory(&mut self) -> Result<()> {
+        let per_page = self.entries_per_directory_page();
+        let pages = self.directory.len().div_ceil(per_page) as u64;
+        if pages > self.directory_pages {
+            self.directory_page = self.page_count;
+            self.directory_pages = pages;
+            self.page_count += pages;
+        }
+        for i in 0..pages {
+            let mut buf = vec![0u8; self.content_size()];
+            let start = i as usize * per_page;
+            let entries = &self.directory[start..self.directory.len().min(start + per_page)];
+            for (slot, page) in buf.chunks_exact_mut(8).zip(entries) {
+                slot.copy_from_slice(&page.to_le_bytes());
+            }
+            self.write_page(self.directory_page + i, buf)?;
+        }
+        self.write_header()?;
+        self.directory_changed = false;
+        Ok(())
+    }
+
+    fn read_directory(&mut self) -> Result<()> {
+        let len = 1usize << self.depth;
+        let per_page = self.entries_per_directory_page();
+        if (len.div_ceil(per_page) as u64) > self.directory_pages {
+            return Err(Error::Corruption("hash directory is larger than its pages".to_string()));
+        }
+
+        let mut directory = Vec::with_capacity(len);
+        for i in 0..self.directory_pages {
+            let buf = self.read_page(self.directory_page + i)?;
+            for slot in buf.chunks_exact(8).take(len - directory.len()) {
+                let page = u64::from_le_bytes(slot.try_into().unwrap());
+                if page == 0 || page >= self.page_count {
+                    return Err(Error::Corruption(format!(
+                        "hash directory points at page {}, past the end of the index",
+                        page
+                    )));
+                }
+                directory.push(page);
+            }
+        }
+        self.directory = directory;
+        Ok(())
+    }
+
+    fn encode_bucket(&self, bucket: &Bucket) -> Vec<u8> {
+        let mut page = vec![0u8; self.content_size()];
+        page[0] = bucket.depth;
+        page[1..3].copy_from_slice(&(bucket.keys.len() as u16).to_le_bytes());
+        let mut pos = BUCKET_HEADER_SIZE;
+        for (key, value) in bucket.keys.iter().zip(&bucket.values) {
+            page[pos..pos + key.len()].copy_from_slice(key);
+            pos += key.len();
+            page[pos..pos + 8].copy_from_slice(&value.to_le_bytes());
+            pos += 8;
+        }
+        page
+    }
+
+    fn decode_bucket(&self, page_no: u64, page: &[u8]) -> Result<Bucket> {
+        let keysize = self.keysize as usize;
+        let depth = page[0];
+        let n = u16::from_le_bytes(page[1..3].try_into().unwrap()) as usize;
+        if n > self.capacity as usize || depth > self.depth {
+            return Err(Error::Corruption(format!("bucket page {} has a bad header", page_no)));
+        }
+
+        let mut keys = Vec::with_capacity(n);
+        let mut values = Vec::with_capacity(n);
+        for entry in page[BUCKET_HEADER_SIZE..].chunks_exact(keysize + 8).take(n) {
+            keys.push(entry[..keysize].to_vec());
+            values.push(u64::from_le_bytes(entry[keysize..].try_into().unwrap()));
+        }
+        Ok(Bucket { depth, keys, values })
+    }
+
+    fn write_bucket(&mut self, page: u64, bucket: &Bucket) -> Result<()> {
+        let buf = self.encode_bucket(bucket);
+        self.write_page(page, buf)
+    }
+
+    fn read_bucket(&mut self, page: u64) -> Result<Bucket> {
+        self.reads += 1;
+        let offset = page * self.page_size as u64;
+        if self.cipher.is_none()
+            && let Some(bytes) = self.storage.mapped(offset, self.page_size as usize)
+        {
+            return self.decode_bucket(page, bytes);
+        }
+        let buf = self.read_page(page)?;
+        self.decode_bucket(page, &buf)
+    }
+
+    fn fixed_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut fixed_key = vec![0u8; self.keysize as usize];
+        let len = key.len().min(self.keysize as usize);
+        fixed_key[..len].copy_from_slice(&key[..len]);
+        fixed_key
+    }
+
+    fn hash(key: &[u8]) -> u64 {
+        mix(fnv1a(key))
+    }
+
+    fn slot(&self, hash: u64) -> usize {
+        (hash & ((1u64 << self.depth) - 1)) as usize
+    }
+
+    // The bucket `key` belongs in, its page, and where in it the key is if present.
+    fn find(&mut self, key: &[u8]) -> Result<(u64, Bucket, Option<usize>)> {
+        let page = self.directory[self.slot(Self::hash(key))];
+        let bucket = self.read_bucket(page)?;
+        let i = bucket.keys.iter().position(|k| k == key);
+        Ok((page, bucket, i))
+    }
+
+    pub fn search(&mut self, key: &[u8]) -> Result<Option<u64>> {
+        let key = self.fixed_key(key);
+        let (_, bucket, i) = self.find(&key)?;
+        Ok(i.map(|i| bucket.values[i]))
+    }
+
+    /// Looks up each of `keys`, reading every bucket they fall in once. Hashing scatters
+    /// neighbouring keys, so the keys are grouped by bucket rather than by key order.
+    pub fn search_many(&mut self, keys: &[Vec<u8>]) -> Result<Vec<Option<u64>>> {
+        let keys: Vec<Vec<u8>> = keys.iter().map(|key| self.fixed_key(key)).collect();
+        let mut by_page: Vec<(u64, usize)> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (self.directory[self.slot(Self::hash(key))], i))
+            .collect();
+        by_page.sort_unstable();
+
+        let mut values = vec![None; keys.len()];
+        for group in by_page.chunk_by(|a, b| a.0 == b.0) {
+            let bucket = self.read_bucket(group[0].0)?;
+            for &(_, i) in group {
+                let position = bucket.keys.iter().position(|k| *k == keys[i]);
+                values[i] = position.map(|position| bucket.values[position]);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Adds `key`, which the caller knows is not in the index yet.
+    pub fn insert(&mut self, key: Vec<u8>, value: u64) -> Result<()> {
+        self.insert_entry(key, value, false).map(|_| ())
+    }
+
+    /// Inserts `key` unless it is already present, in which case nothing changes and the value
+    /// stored under it is returned.
+    pub fn insert_if_absent(&mut self, key: Vec<u8>, value: u64) -> Result<Option<u64>> {
+        self.insert_entry(key, value, true)
+    }
+
+    fn insert_entry(&mut self, key: Vec<u8>, value: u64, unique: bool) -> Result<Option<u64>> {
+        let key = self.fixed_key(&key);
+        loop {
+            let (page, mut bucket, i) = self.find(&key)?;
+            if let (true, Some(i)) = (unique, i) {
+                return Ok(Some(bucket.values[i]));
+            }
+            self.begin_write()?;
+            if bucket.keys.len() < self.capacity as usize {
+                bucket.keys.push(key);
+                bucket.values.push(value);
+                self.write_bucket(page, &bucket)?;
+                self.len += 1;
+                return Ok(None);
+            }
+            self.split(page, bucket)?;
+        }
+    }
+
+    // Splits the full bucket at `page` on the next bit of its keys' hashes, doubling the
+    // directory first if the bucket already uses all the bits it has. The keys whose bit is set
+    // move to a new page at the end, which the directory slots with that bit now point to.
+    fn split(&mut self, page: u64, bucket: Bucket) -> Result<()> {
+        if bucket.depth == self.depth {
+            if self.depth == MAX_DEPTH {
+                let message = format!("the keys are too skewed for a hash directory of 2^{} slots", MAX_DEPTH);
+                return Err(Error::Unsupported(message));
+            }
+            self.directory.extend_from_within(..);
+            self.depth += 1;
+        }
+
+        let bit = 1u64 << bucket.depth;
+        let depth = bucket.depth + 1;
+        let mut low = Bucket {
+            depth,
+            keys: Vec::new(),
+            values: Vec::new(),
+        };
+        let mut high = Bucket {
+            depth,
+            keys: Vec::new(),
+            values: Vec::new(),
+        };
+        for (key, value) in bucket.keys.into_iter().zip(bucket.values) {
+            let half = if Self::hash(&key) & bit != 0 { &mut high } else { &mut low };
+            half.keys.push(key);
+            half.values.push(value);
+        }
+
+        let new_page = self.page_count;
+        self.page_count += 1;
+        self.write_bucket(new_page, &high)?;
+        self.write_bucket(page, &low)?;
+        for (slot, entry) in self.directory.iter_mut().enumerate() {
+            if *entry == page && slot as u64 & bit != 0 {
+                *entry = new_page;
+            }
+        }
+        self.directory_changed = true;
+        self.write_header()
+    }
+
+    /// Points `key` at `value`, returning the value it replaces, or None if `key` is missing.
+    pub fn update(&mut self, key: &[u8], value: u64) -> Result<Option<u64>> {
+        let key = self.fixed_key(key);
+        let (page, mut bucket, i) = self.find(&key)?;
+        let Some(i) = i else {
+            return Ok(None);
+        };
+        self.begin_write()?;
+        let old = std::mem::replace(&mut bucket.values[i], value);
+        self.write_bucket(page, &bucket)?;
+        Ok(Some(old))
+    }
+
+    // Buckets are not merged when they empty out; rebuilding the index reclaims them.
+    pub fn delete(&mut self, key: &[u8]) -> Result<Option<u64>> {
+        let key = self.fixed_key(key);
+        let (page, mut bucket, i) = self.find(&key)?;
+        let Some(i) = i else {
+            return Ok(None);
+        };
+        self.begin_write()?;
+        bucket.keys.swap_remove(i);
+        let value = bucket.values.swap_remove(i);
+        self.write_bucket(page, &bucket)?;
+        self.len -= 1;
+        Ok(Some(value))
+    }
+
+    // Distinct bucket pages, in file order so they are read sequentially.
+    fn bucket_pages(&self) -> Vec<u64> {
+        let mut pages = self.directory.clone();
+        pages.sort_unstable();
+        pages.dedup();
+        pages
+    }
+
+    /// Visits every key and value, bucket by bucket, in no particular order.
+    pub fn traverse<F>(&mut self, mut visit: F) -> Result<()>
+    where
+        F: FnMut(&[u8], u64) -> Result<()>,
+    {
+        for page in self.bucket_pages() {
+            let bucket = self.read_bucket(page)?;
+            for (key, value) in bucket.keys.iter().zip(&bucket.values) {
+                visit(key, *value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every bucket is referenced by exactly the directory slots its local depth
+    /// calls for, that its keys hash to those slots and are unique, and the entry count in the
+    /// header.
+    pub fn verify(&mut self) -> Result<()> {
+        let mut count = 0;
+        for page in self.bucket_pages() {
+            let bucket = self.read_bucket(page)?;
+            let slots: Vec<usize> = (0..self.directory.len()).filter(|&slot| self.directory[slot] == page).collect();
+            if slots.len() != 1 << (self.depth - bucket.depth) {
+                return Err(Error::Corruption(format!(
+                    "bucket page {} of depth {} has {} directory slots",
+                    page,
+                    bucket.depth,
+                    slots.len()
+                )));
+            }
+
+            let mask = (1u64 << bucket.depth) - 1;
+            let suffix = slots[0] as u64 & mask;
+            if slots.iter().any(|&slot| slot as u64 & mask != suffix) {
+                return Err(Error::Corruption(format!("bucket page {} is shared by unrelated slots", page)));
+            }
+            for (i, key) in bucket.keys.iter().enumerate() {
+                if Self::hash(key) & mask != suffix {
+                    return Err(Error::Corruption(format!("bucket page {} holds a key of another bucket", page)));
+                }
+                if bucket.keys[..i].contains(key) {
+                    return Err(Error::Corruption(format!("bucket page {} holds a key twice", page)));
+                }
+            }
+            count += bucket.keys.len() as u64;
+        }
+
+        if count != self.len {
+            return Err(Error::Corruption(format!(
+                "index holds {} keys but its header counts {}",
+                count, self.len
+            )));
+        }
+        Ok(())
+    }
+}
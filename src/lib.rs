@@ -0,0 +1,41 @@
+//! A small persistent key/record store: fixed-size records in a datafile, indexed by an
+//! on-disk B-tree.
+//!
+//! ```
+//! use rustdb::TableOptions;
+//!
+//! let mut table = TableOptions::new().key_size(4).record_size(16).open_in_memory()?;
+//! table.add_record(b"0001", b"hello")?;
+//! assert_eq!(&table.search_record(b"0001")?.unwrap()[..5], b"hello");
+//! # Ok::<(), rustdb::Error>(())
+//! ```
+
+mod bloom;
+mod btree;
+mod catalog;
+mod checksum;
+mod compression;
+mod database;
+mod encryption;
+mod error;
+mod hash;
+mod lsm;
+mod mmap;
+mod schema;
+mod sql;
+mod storage;
+mod table;
+
+pub use bloom::FilterStats;
+pub use btree::{DEFAULT_PAGE_SIZE, Index, Range};
+pub use catalog::{IndexInfo, TableInfo};
+pub use compression::Compression;
+pub use database::Database;
+pub use encryption::EncryptionKey;
+pub use error::{Error, Result};
+pub use hash::HashIndex;
+pub use mmap::MmapStorage;
+pub use schema::{Column, ColumnType, Schema, Value};
+pub use sql::QueryResult;
+pub use storage::{Backend, Durability, FileStorage, MemStorage, Storage};
+pub use table::{Engine, IndexKind, Scan, Table, TableOptions, TableStats, WriteBatch, WriteOutcome};
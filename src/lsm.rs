@@ -0,0 +1,1385 @@
+use crate::btree::prefix_range;
+use crate::checksum::fnv1a;
+use crate::error::{Error, Result};
+use crate::storage::{Durability, FileStorage, MemStorage, Storage, sync_parent_dir};
+use crate::table::{BatchWrite, WriteBatch, WriteOutcome, check_sizes};
+use std::collections::btree_map::Entry as MapEntry;
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+pub(crate) const MANIFEST_MAGIC: &[u8; 4] = b"RDBL";
+const MANIFEST_VERSION: u16 = 1;
+const RUN_MAGIC: &[u8; 4] = b"RDBR";
+const RUN_VERSION: u16 = 1;
+const WAL_MAGIC: &[u8; 4] = b"RDBW";
+const WAL_VERSION: u16 = 1;
+
+// Manifest: magic (4) + version (2) + keysize (2) + recordsize (2) + next run id (8)
+// + live record count (8) + WAL generation (8) + run count (4), then level (1) + id (8) for
+// every run, and an FNV-1a checksum (8) of all of it.
+const MANIFEST_HEADER_SIZE: usize = 38;
+// Run file: magic (4) + version (2) + keysize (2) + recordsize (2) + entries per block (4)
+// + entries (8) + tombstones (8), then the entries in key order, the first key of every block
+// and the last key.
+const RUN_HEADER_SIZE: u64 = 30;
+// WAL: magic (4) + version (2) + generation (8), then one record per batch of writes: entry
+// count (4), one entry per write: op (1) + key + record, and an FNV-1a checksum (8) of the count
+// and the entries.
+const WAL_HEADER_SIZE: u64 = 14;
+const WAL_BATCH_OVERHEAD: usize = 12;
+
+// Run entries are the flag, key and record; a tombstone has a zeroed record.
+const LIVE: u8 = 0;
+const TOMBSTONE: u8 = 1;
+
+// WAL ops. Inserts, replacements and deletes are told apart so that replaying them keeps the
+// record count exact.
+const WAL_INSERT: u8 = 1;
+const WAL_REPLACE: u8 = 2;
+const WAL_DELETE: u8 = 3;
+
+// A lookup reads one block of each run it has to ask.
+const BLOCK_SIZE: usize = 4096;
+// Level 0 is merged into level 1 once it has this many runs.
+const L0_RUNS: usize = 4;
+// Each level below 0 holds this many times as many entries as the one above it.
+const LEVEL_RATIO: u64 = 10;
+
+// A key and its record, or None for a tombstone.
+type Entry = (Vec<u8>, Option<Vec<u8>>);
+type Source<'a> = Box<dyn DoubleEndedIterator<Item = Result<Entry>> + 'a>;
+
+// A table kept as a log-structured merge tree. Writes go to the WAL and the memtable; a full
+// memtable is written out as a sorted run in level 0, and runs are merged into the levels below
+// as those fill up. Level 0 keeps its runs in the order they were written, and they may
+// overlap; the runs of every other level are sorted by key and do not. Data only moves down,
+// so for any key the memtable is newer than level 0, level 0 than level 1, and so on.
+pub(crate) struct LsmTable {
+    keysize: u16,
+    recordsize: u16,
+    files: Files,
+    wal: Box<dyn Storage>,
+    wal_end: u64,
+    // Bumped at every flush. The manifest names the generation of the WAL that is not in any
+    // run yet, so a WAL left over from a crash right after a flush is not replayed twice.
+    generation: u64,
+    memtable: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    memtable_entries: usize,
+    levels: Vec<Vec<Run>>,
+    next_id: u64,
+    records: u64,
+    durability: Durability,
+    last_commit: Instant,
+    // WAL appends whose sync group commit held back.
+    held_back: bool,
+}
+
+// Where the files of a table live: the manifest at the table's path and the WAL and runs next
+// to it, or only in memory.
+pub(crate) enum Files {
+    Disk(PathBuf),
+    Memory,
+}
+
+struct Manifest {
+    keysize: u16,
+    recordsize: u16,
+    next_id: u64,
+    records: u64,
+    generation: u64,
+    runs: Vec<(u8, u64)>,
+}
+
+// A sorted immutable run. Its block fences, the first key of every block, stay in memory.
+struct Run {
+    id: u64,
+    storage: Box<dyn Storage>,
+    keysize: usize,
+    recordsize: usize,
+    entries: u64,
+    tombstones: u64,
+    block_entries: u64,
+    fences: Vec<Vec<u8>>,
+    last: Vec<u8>,
+}
+
+// Collects the entries of a run in key order; `finish` writes it out in one go.
+struct RunWriter {
+    keysize: usize,
+    recordsize: usize,
+    block_entries: u64,
+    data: Vec<u8>,
+    fences: Vec<Vec<u8>>,
+    last: Vec<u8>,
+    entries: u64,
+    tombstones: u64,
+}
+
+// The entries of one run within a range, read a block at a time from either end.
+struct RunCursor<'a> {
+    run: &'a mut Run,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    next_block: usize,
+    end_block: usize,
+    front: VecDeque<Entry>,
+    back: VecDeque<Entry>,
+}
+
+// Merges sorted sources, given newest first, into one sorted sequence in which every key comes
+// from the newest source that has it. Each source has a slot per end for the next entry it
+// returns there; once a source runs dry, what is left of it is in the other end's slot.
+struct Merge<'a> {
+    sources: Vec<Source<'a>>,
+    front: Vec<Option<Entry>>,
+    back: Vec<Option<Entry>>,
+}
+
+// Where a key of a batch stands: whether it existed before the batch, whether it does after the
+// writes seen so far, and the record to store if one was written.
+struct BatchChange {
+    existed: bool,
+    present: bool,
+    record: Option<Vec<u8>>,
+}
+
+/// Key and record pairs of an LSM table in key order, merged from its memtable and runs.
+pub(crate) struct LsmScan<'a> {
+    merge: Merge<'a>,
+}
+
+impl Files {
+    fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+        let mut path: OsString = path.as_os_str().to_owned();
+        path.push(suffix);
+        PathBuf::from(path)
+    }
+
+    fn run_path(path: &Path, id: u64) -> PathBuf {
+        Self::with_suffix(path, &format!(".{}.run", id))
+    }
+
+    fn open_wal(&self) -> Result<Box<dyn Storage>> {
+        Ok(match self {
+            Files::Disk(path) => Box::new(FileStorage::create(Self::with_suffix(path, ".wal"))?),
+            Files::Memory => Box::new(MemStorage::new()),
+        })
+    }
+
+    fn open_run(&self, id: u64) -> Result<Box<dyn Storage>> {
+        match self {
+            Files::Disk(path) => Ok(Box::new(FileStorage::open(Self::run_path(path, id))?)),
+            Files::Memory => Err(Error::Corruption(format!("run {} is missing", id))),
+        }
+    }
+
+    // A run that a crash left behind before the manifest named it may have the same id.
+    fn create_run(&self, id: u64) -> Result<Box<dyn Storage>> {
+        Ok(match self {
+            Files::Disk(path) => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(Self::run_path(path, id))?;
+                Box::new(FileStorage::new(file))
+            }
+            Files::Memory => Box::new(MemStorage::new()),
+        })
+    }
+
+    fn remove_run(&self, id: u64) -> Result<()> {
+        if let Files::Disk(path) = self {
+            fs::remove_file(Self::run_path(path, id))?;
+        }
+        Ok(())
+    }
+
+    fn read_manifest(&self) -> Result<Option<Manifest>> {
+        match self {
+            Files::Disk(path) if fs::metadata(path).is_ok_and(|meta| meta.len() > 0) => {
+                Manifest::decode(&fs::read(path)?).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Written to a temporary file that replaces the manifest once it is on disk, so a crash
+    // leaves either the old manifest or the new one.
+    fn write_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let Files::Disk(path) = self else {
+            return Ok(());
+        };
+        let tmp = Self::with_suffix(path, ".tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&manifest.encode())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        // Makes the rename, and the new runs it names, durable before any old run is removed:
+        // otherwise a crash could bring back a manifest naming runs that are gone.
+        sync_parent_dir(path)?;
+        Ok(())
+    }
+
+    // Runs that a crash left behind: written by a flush or compaction that never got to name
+    // them in the manifest, or merged away before their files were removed.
+    fn remove_orphans(&self, live: &[u64]) -> Result<()> {
+        let Files::Disk(path) = self else {
+            return Ok(());
+        };
+        for id in Self::run_ids(path)? {
+            if !live.contains(&id) {
+                fs::remove_file(Self::run_path(path, id))?;
+            }
+        }
+        Ok(())
+    }
+
+    // The ids of all run files next to the table at `path`, whether the manifest names them or not.
+    fn run_ids(path: &Path) -> Result<Vec<u64>> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(Vec::new());
+        };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let prefix = format!("{}.", name.to_string_lossy());
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let file_name = entry?.file_name();
+            let file_name = file_name.to_string_lossy();
+            let id = file_name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".run"))
+                .and_then(|id| id.parse::<u64>().ok());
+            ids.extend(id);
+        }
+        Ok(ids)
+    }
+}
+
+impl Manifest {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MANIFEST_HEADER_SIZE + self.runs.len() * 9 + 8);
+        bytes.extend_from_slice(MANIFEST_MAGIC);
+        bytes.extend_from_slice(&MANIFEST_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.keysize.to_le_bytes());
+        bytes.extend_from_slice(&self.recordsize.to_le_bytes());
+        bytes.extend_from_slice(&self.next_id.to_le_bytes());
+        bytes.extend_from_slice(&self.records.to_le_bytes());
+        bytes.extend_from_slice(&self.generation.to_le_bytes());
+        bytes.extend_from_slice(&(self.runs.len() as u32).to_le_bytes());
+        for (level, id) in &self.runs {
+            bytes.push(*level);
+            bytes.extend_from_slice(&id.to_le_bytes());
+        }
+        bytes.extend_from_slice(&fnv1a(&bytes).to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 4 || &bytes[0..4] != MANIFEST_MAGIC {
+            return Err(Error::Corruption("not an LSM table manifest".to_string()));
+        }
+        if bytes.len() < MANIFEST_HEADER_SIZE + 8 {
+            return Err(Error::Corruption("LSM manifest is truncated".to_string()));
+        }
+        let version = u16::from_le_bytes(bytes[4..6].try_into().unwrap());
+        if version != MANIFEST_VERSION {
+            return Err(Error::VersionMismatch {
+                found: version,
+                expected: MANIFEST_VERSION,
+            });
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 8);
+        let count = u32::from_le_bytes(body[34..38].try_into().unwrap()) as usize;
+        if body.len() != MANIFEST_HEADER_SIZE + count * 9 || fnv1a(body).to_le_bytes() != checksum {
+            return Err(Error::Corruption("LSM manifest fails its checksum".to_string()));
+        }
+        let u64_at = |at: usize| u64::from_le_bytes(body[at..at + 8].try_into().unwrap());
+        let runs = body[MANIFEST_HEADER_SIZE..]
+            .chunks_exact(9)
+            .map(|run| (run[0], u64::from_le_bytes(run[1..9].try_into().unwrap())))
+            .collect();
+        Ok(Manifest {
+            keysize: u16::from_le_bytes(body[6..8].try_into().unwrap()),
+            recordsize: u16::from_le_bytes(body[8..10].try_into().unwrap()),
+            next_id: u64_at(10),
+            records: u64_at(18),
+            generation: u64_at(26),
+            runs,
+        })
+    }
+}
+
+impl Run {
+    fn open(id: u64, mut storage: Box<dyn Storage>, keysize: u16, recordsize: u16) -> Result<Self> {
+        let corrupt = |what: &str| Error::Corruption(format!("run {} {}", id, what));
+        let len = storage.len()?;
+        if len < RUN_HEADER_SIZE {
+            return Err(corrupt("is truncated"));
+        }
+        let mut header = [0u8; RUN_HEADER_SIZE as usize];
+        storage.read_at(0, &mut header)?;
+        if &header[0..4] != RUN_MAGIC || u16::from_le_bytes(header[4..6].try_into().unwrap()) != RUN_VERSION {
+            return Err(corrupt("has a bad header"));
+        }
+        if u16::from_le_bytes(header[6..8].try_into().unwrap()) != keysize
+            || u16::from_le_bytes(header[8..10].try_into().unwrap()) != recordsize
+        {
+            return Err(corrupt("does not match the table's key and record sizes"));
+        }
+        let block_entries = u32::from_le_bytes(header[10..14].try_into().unwrap()) as u64;
+        let entries = u64::from_le_bytes(header[14..22].try_into().unwrap());
+        let tombstones = u64::from_le_bytes(header[22..30].try_into().unwrap());
+        if block_entries == 0 || entries == 0 {
+            return Err(corrupt("has no entries"));
+        }
+
+        let (keysize, recordsize) = (keysize as usize, recordsize as usize);
+        let blocks = entries.div_ceil(block_entries) as usize;
+        let keys_at = RUN_HEADER_SIZE + entries * (1 + keysize + recordsize) as u64;
+        if len != keys_at + ((blocks + 1) * keysize) as u64 {
+            return Err(corrupt(&format!("is {} bytes, which does not fit {} entries", len, entries)));
+        }
+        let mut keys = vec![0u8; (blocks + 1) * keysize];
+        storage.read_at(keys_at, &mut keys)?;
+        let mut fences: Vec<Vec<u8>> = keys.chunks_exact(keysize).map(<[u8]>::to_vec).collect();
+        let last = fences.pop().unwrap();
+
+        Ok(Run {
+            id,
+            storage,
+            keysize,
+            recordsize,
+            entries,
+            tombstones,
+            block_entries,
+            fences,
+            last,
+        })
+    }
+
+    fn entry_size(&self) -> usize {
+        1 + self.keysize + self.recordsize
+    }
+
+    fn bytes(&self) -> u64 {
+        RUN_HEADER_SIZE + self.entries * self.entry_size() as u64 + ((self.fences.len() + 1) * self.keysize) as u64
+    }
+
+    fn first(&self) -> &[u8] {
+        &self.fences[0]
+    }
+
+    fn read_block(&mut self, block: usize) -> Result<Vec<Entry>> {
+        let first = block as u64 * self.block_entries;
+        let count = self.block_entries.min(self.entries - first) as usize;
+        let entry_size = self.entry_size();
+        let mut data = vec![0u8; count * entry_size];
+        self.storage.read_at(RUN_HEADER_SIZE + first * entry_size as u64, &mut data)?;
+
+        data.chunks_exact(entry_size)
+            .map(|entry| {
+                let key = entry[1..1 + self.keysize].to_vec();
+                match entry[0] {
+                    LIVE => Ok((key, Some(entry[1 + self.keysize..].to_vec()))),
+                    TOMBSTONE => Ok((key, None)),
+                    flag => Err(Error::Corruption(format!("run {} has an entry with flag {}", self.id, flag))),
+                }
+            })
+            .collect()
+    }
+
+    // The entry of `key` in this run: Some(None) for a tombstone.
+    fn get(&mut self, key: &[u8]) -> Result<Option<Option<Vec<u8>>>> {
+        if key < self.first() || key > self.last.as_slice() {
+            return Ok(None);
+        }
+        let block = self.fences.partition_point(|fence| fence.as_slice() <= key) - 1;
+        let mut entries = self.read_block(block)?;
+        Ok(match entries.binary_search_by(|(k, _)| k.as_slice().cmp(key)) {
+            Ok(i) => Some(entries.swap_remove(i).1),
+            Err(_) => None,
+        })
+    }
+
+    // `get` for keys in ascending order, filling in the entries of those not found yet. Each
+    // block is read at most once.
+    fn get_sorted(&mut self, keys: &[Vec<u8>], found: &mut [Option<Option<Vec<u8>>>]) -> Result<()> {
+        let start = keys.partition_point(|key| key.as_slice() < self.first());
+        let end = keys.partition_point(|key| key <= &self.last);
+        let mut block = None;
+        let mut entries = Vec::new();
+        for i in start..end {
+            if found[i].is_some() {
+                continue;
+            }
+            let key = keys[i].as_slice();
+            let key_block = self.fences.partition_point(|fence| fence.as_slice() <= key) - 1;
+            if block != Some(key_block) {
+                entries = self.read_block(key_block)?;
+                block = Some(key_block);
+            }
+            if let Ok(j) = entries.binary_search_by(|(k, _)| k.as_slice().cmp(key)) {
+                found[i] = Some(entries[j].1.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn overlaps(&self, start: &Bound<Vec<u8>>, end: &Bound<Vec<u8>>) -> bool {
+        let before = match end {
+            Bound::Included(end) => end.as_slice() < self.first(),
+            Bound::Excluded(end) => end.as_slice() <= self.first(),
+            Bound::Unbounded => false,
+        };
+        let after = match start {
+            Bound::Included(start) => start > &self.last,
+            Bound::Excluded(start) => start >= &self.last,
+            Bound::Unbounded => false,
+        };
+        !before && !after
+    }
+}
+
+impl RunWriter {
+    fn new(keysize: u16, recordsize: u16) -> Self {
+        let (keysize, recordsize) = (keysize as usize, recordsize as usize);
+        RunWriter {
+            keysize,
+            recordsize,
+            block_entries: (BLOCK_SIZE / (1 + keysize + recordsize)).max(1) as u64,
+            data: Vec::new(),
+            fences: Vec::new(),
+            last: Vec::new(),
+            entries: 0,
+            tombstones: 0,
+        }
+    }
+
+    // Keys have to come in ascending order.
+    fn push(&mut self, key: &[u8], record: Option<&[u8]>) {
+        if self.entries.is_multiple_of(self.block_entries) {
+            self.fences.push(key.to_vec());
+        }
+        self.data.push(if record.is_some() { LIVE } else { TOMBSTONE });
+        self.data.extend_from_slice(key);
+        match record {
+            Some(record) => self.data.extend_from_slice(record),
+            None => {
+                self.data.resize(self.data.len() + self.recordsize, 0);
+                self.tombstones += 1;
+            }
+        }
+        self.last = key.to_vec();
+        self.entries += 1;
+    }
+
+    fn len(&self) -> u64 {
+        self.entries
+    }
+
+    // Writes and syncs the run, which is only named in the manifest afterwards.
+    fn finish(self, id: u64, mut storage: Box<dyn Storage>) -> Result<Run> {
+        let mut file = Vec::with_capacity(RUN_HEADER_SIZE as usize + self.data.len());
+        file.extend_from_slice(RUN_MAGIC);
+        file.extend_from_slice(&RUN_VERSION.to_le_bytes());
+        file.extend_from_slice(&(self.keysize as u16).to_le_bytes());
+        file.extend_from_slice(&(self.recordsize as u16).to_le_bytes());
+        file.extend_from_slice(&(self.block_entries as u32).to_le_bytes());
+        file.extend_from_slice(&self.entries.to_le_bytes());
+        file.extend_from_slice(&self.tombstones.to_le_bytes());
+        file.extend_from_slice(&self.data);
+        for fence in &self.fences {
+            file.extend_from_slice(fence);
+        }
+        file.extend_from_slice(&self.last);
+        storage.write_at(0, &file)?;
+        storage.sync()?;
+
+        Ok(Run {
+            id,
+            storage,
+            keysize: self.keysize,
+            recordsize: self.recordsize,
+            entries: self.entries,
+            tombstones: self.tombstones,
+            block_entries: self.block_entries,
+            fences: self.fences,
+            last: self.last,
+        })
+    }
+}
+
+impl<'a> RunCursor<'a> {
+    fn new(run: &'a mut Run, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> Self {
+        let next_block = match &start {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                run.fences.partition_point(|fence| fence <= key).saturating_sub(1)
+            }
+            Bound::Unbounded => 0,
+        };
+        let end_block = match &end {
+            Bound::Included(key) => run.fences.partition_point(|fence| fence <= key),
+            Bound::Excluded(key) => run.fences.partition_point(|fence| fence < key),
+            Bound::Unbounded => run.fences.len(),
+        };
+        RunCursor {
+            run,
+            start,
+            end,
+            next_block,
+            end_block: end_block.max(next_block),
+            front: VecDeque::new(),
+            back: VecDeque::new(),
+        }
+    }
+
+    fn load(&mut self, block: usize) -> Result<VecDeque<Entry>> {
+        let range = (self.start.as_ref(), self.end.as_ref());
+        Ok(self.run.read_block(block)?.into_iter().filter(|(key, _)| range.contains(key)).collect())
+    }
+}
+
+impl Iterator for RunCursor<'_> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.front.pop_front() {
+                return Some(Ok(entry));
+            }
+            if self.next_block == self.end_block {
+                return self.back.pop_front().map(Ok);
+            }
+            match self.load(self.next_block) {
+                Ok(entries) => self.front = entries,
+                Err(err) => return Some(Err(err)),
+            }
+            self.next_block += 1;
+        }
+    }
+}
+
+impl DoubleEndedIterator for RunCursor<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.back.pop_back() {
+                return Some(Ok(entry));
+            }
+            if self.next_block == self.end_block {
+                return self.front.pop_back().map(Ok);
+            }
+            match self.load(self.end_block - 1) {
+                Ok(entries) => self.back = entries,
+                Err(err) => return Some(Err(err)),
+            }
+            self.end_block -= 1;
+        }
+    }
+}
+
+impl<'a> Merge<'a> {
+    fn new(sources: Vec<Source<'a>>) -> Self {
+        let n = sources.len();
+        Merge {
+            sources,
+            front: vec![None; n],
+            back: vec![None; n],
+        }
+    }
+
+    // Fills the front slot of every source that can still fill it.
+    fn fill_front(&mut self) -> Result<()> {
+        for i in 0..self.sources.len() {
+            if self.front[i].is_none() {
+                self.front[i] = match self.sources[i].next() {
+                    Some(entry) => Some(entry?),
+                    None => self.back[i].take(),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_back(&mut self) -> Result<()> {
+        for i in 0..self.sources.len() {
+            if self.back[i].is_none() {
+                self.back[i] = match self.sources[i].next_back() {
+                    Some(entry) => Some(entry?),
+                    None => self.front[i].take(),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    // Takes the entries under `key` out of `slots`, returning the newest.
+    fn take(slots: &mut [Option<Entry>], key: &[u8]) -> Entry {
+        let mut newest = None;
+        for slot in slots {
+            if slot.as_ref().is_some_and(|(k, _)| k.as_slice() == key) {
+                let entry = slot.take();
+                newest = newest.or(entry);
+            }
+        }
+        newest.expect("the key came from one of the slots")
+    }
+}
+
+impl Iterator for Merge<'_> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.fill_front() {
+            return Some(Err(err));
+        }
+        let key = self.front.iter().flatten().map(|(key, _)| key).min()?.clone();
+        Some(Ok(Self::take(&mut self.front, &key)))
+    }
+}
+
+impl DoubleEndedIterator for Merge<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.fill_back() {
+            return Some(Err(err));
+        }
+        let key = self.back.iter().flatten().map(|(key, _)| key).max()?.clone();
+        Some(Ok(Self::take(&mut self.back, &key)))
+    }
+}
+
+impl Iterator for LsmScan<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.merge.next()? {
+                Ok((key, Some(record))) => return Some(Ok((key, record))),
+                Ok((_, None)) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for LsmScan<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.merge.next_back()? {
+                Ok((key, Some(record))) => return Some(Ok((key, record))),
+                Ok((_, None)) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+// Whether no key can fall between the bounds, which `BTreeMap::range` would panic on.
+fn is_empty_range(start: &Bound<Vec<u8>>, end: &Bound<Vec<u8>>) -> bool {
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end)) => {
+            start >= end
+        }
+        _ => false,
+    }
+}
+
+impl LsmTable {
+    /// Whether `path` holds the manifest of an LSM table.
+    pub(crate) fn is_lsm(path: &Path) -> Result<bool> {
+        let Ok(mut file) = File::open(path) else {
+            return Ok(false);
+        };
+        let mut magic = [0u8; 4];
+        Ok(file.read_exact(&mut magic).is_ok() && &magic == MANIFEST_MAGIC)
+    }
+
+    // The manifest, WAL and runs of the table at `path`, whichever of them exist, including a
+    // manifest or runs that a crash left behind.
+    pub(crate) fn files(path: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = vec![
+            path.to_path_buf(),
+            Files::with_suffix(path, ".wal"),
+            Files::with_suffix(path, ".tmp"),
+        ];
+        files.extend(Files::run_ids(path)?.into_iter().map(|id| Files::run_path(path, id)));
+        Ok(files.into_iter().filter(|file| file.exists()).collect())
+    }
+
+    // Opens the table in `files`, creating it with the given sizes if there is none. The WAL
+    // is replayed into the memtable.
+    pub(crate) fn open(files: Files, keysize: u16, recordsize: u16, memtable_size: usize) -> Result<Self> {
+        let manifest = match files.read_manifest()? {
+            Some(manifest) => manifest,
+            None => {
+                let manifest = Manifest {
+                    keysize,
+                    recordsize,
+                    next_id: 0,
+                    records: 0,
+                    generation: 0,
+                    runs: Vec::new(),
+                };
+                files.write_manifest(&manifest)?;
+                manifest
+            }
+        };
+        let (keysize, recordsize) = (manifest.keysize, manifest.recordsize);
+        if keysize == 0 || recordsize == 0 {
+            return Err(Error::Corruption("LSM manifest has a zero key or record size".to_string()));
+        }
+
+        let mut levels: Vec<Vec<Run>> = vec![Vec::new()];
+        for &(level, id) in &manifest.runs {
+            let level = level as usize;
+            if levels.len() <= level {
+                levels.resize_with(level + 1, Vec::new);
+            }
+            levels[level].push(Run::open(id, files.open_run(id)?, keysize, recordsize)?);
+        }
+        let live: Vec<u64> = manifest.runs.iter().map(|&(_, id)| id).collect();
+        files.remove_orphans(&live)?;
+
+        let entry_size = 1 + keysize as usize + recordsize as usize;
+        let mut table = LsmTable {
+            keysize,
+            recordsize,
+            wal: files.open_wal()?,
+            files,
+            wal_end: WAL_HEADER_SIZE,
+            generation: manifest.generation,
+            memtable: BTreeMap::new(),
+            memtable_entries: (memtable_size / entry_size).max(1),
+            levels,
+            next_id: manifest.next_id,
+            records: manifest.records,
+            durability: Durability::default(),
+            last_commit: Instant::now(),
+            held_back: false,
+        };
+        table.replay_wal()?;
+        if table.memtable.len() >= table.memtable_entries {
+            table.flush()?;
+        }
+        Ok(table)
+    }
+
+    // Applies the writes logged since the last flush. A batch is applied whole or not at all:
+    // the log ends at the first batch that is cut short or fails its checksum, where an append
+    // never fully reached the disk, and it is cut off there.
+    fn replay_wal(&mut self) -> Result<()> {
+        let len = self.wal.len()?;
+        let mut header = [0u8; WAL_HEADER_SIZE as usize];
+        if len < WAL_HEADER_SIZE {
+            return self.reset_wal();
+        }
+        self.wal.read_at(0, &mut header)?;
+        if &header[0..4] != WAL_MAGIC {
+            return self.reset_wal();
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != WAL_VERSION {
+            return Err(Error::VersionMismatch {
+                found: version,
+                expected: WAL_VERSION,
+            });
+        }
+        if u64::from_le_bytes(header[6..14].try_into().unwrap()) != self.generation {
+            // Written before the last flush, which has all of it.
+            return self.reset_wal();
+        }
+
+        let entry_size = 1 + self.keysize as usize + self.recordsize as usize;
+        let mut log = vec![0u8; (len - WAL_HEADER_SIZE) as usize];
+        self.wal.read_at(WAL_HEADER_SIZE, &mut log)?;
+        let mut end = WAL_HEADER_SIZE;
+        let mut rest = &log[..];
+        while rest.len() >= WAL_BATCH_OVERHEAD {
+            let count = u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+            let size = count
+                .checked_mul(entry_size)
+                .and_then(|entries| entries.checked_add(WAL_BATCH_OVERHEAD))
+                .filter(|&size| count > 0 && size <= rest.len());
+            let Some(size) = size else { break };
+            let (body, checksum) = rest[..size].split_at(size - 8);
+            if fnv1a(body).to_le_bytes() != checksum {
+                break;
+            }
+
+            for entry in body[4..].chunks_exact(entry_size) {
+                let (op, key) = (entry[0], entry[1..1 + self.keysize as usize].to_vec());
+                let record = entry[1 + self.keysize as usize..].to_vec();
+                match op {
+                    WAL_INSERT | WAL_REPLACE => self.apply_to_memtable(op, key, Some(record)),
+                    WAL_DELETE => self.apply_to_memtable(op, key, None),
+                    op => return Err(Error::Corruption(format!("WAL entry has an unknown op {}", op))),
+                }
+            }
+            end += size as u64;
+            rest = &rest[size..];
+        }
+        if end < len {
+            self.wal.truncate(end)?;
+        }
+        self.wal_end = end;
+        Ok(())
+    }
+
+    fn reset_wal(&mut self) -> Result<()> {
+        let mut header = Vec::with_capacity(WAL_HEADER_SIZE as usize);
+        header.extend_from_slice(WAL_MAGIC);
+        header.extend_from_slice(&WAL_VERSION.to_le_bytes());
+        header.extend_from_slice(&self.generation.to_le_bytes());
+        self.wal.truncate(0)?;
+        self.wal.write_at(0, &header)?;
+        self.wal.sync()?;
+        self.wal_end = WAL_HEADER_SIZE;
+        Ok(())
+    }
+
+    fn apply_to_memtable(&mut self, op: u8, key: Vec<u8>, record: Option<Vec<u8>>) {
+        match op {
+            WAL_INSERT => self.records += 1,
+            WAL_DELETE => self.records = self.records.saturating_sub(1),
+            _ => {}
+        }
+        self.memtable.insert(key, record);
+    }
+
+    pub(crate) fn keysize(&self) -> u16 {
+        self.keysize
+    }
+
+    pub(crate) fn recordsize(&self) -> u16 {
+        self.recordsize
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.records
+    }
+
+    pub(crate) fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    pub(crate) fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    // Runs per level, from level 0 down.
+    pub(crate) fn runs(&self) -> Vec<usize> {
+        self.levels.iter().map(Vec::len).collect()
+    }
+
+    // Tombstones in the memtable and runs, which compaction drops once they reach the bottom.
+    pub(crate) fn tombstones(&self) -> u64 {
+        let memtable = self.memtable.values().filter(|record| record.is_none()).count() as u64;
+        memtable + self.levels.iter().flatten().map(|run| run.tombstones).sum::<u64>()
+    }
+
+    // The bytes of the WAL and all runs.
+    pub(crate) fn bytes(&self) -> Result<u64> {
+        Ok(self.wal.len()? + self.levels.iter().flatten().map(Run::bytes).sum::<u64>())
+    }
+
+    fn fixed_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut fixed_key = key[..key.len().min(self.keysize as usize)].to_vec();
+        fixed_key.resize(self.keysize as usize, 0);
+        fixed_key
+    }
+
+    fn fixed_record(&self, record: &[u8]) -> Vec<u8> {
+        let mut fixed_record = record.to_vec();
+        fixed_record.resize(self.recordsize as usize, 0);
+        fixed_record
+    }
+
+    // The record of the fixed `key`, from the newest place that has it: the memtable, then
+    // the runs of level 0 from the newest, then at most one run of every level below.
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(record) = self.memtable.get(key) {
+            return Ok(record.clone());
+        }
+        for run in self.levels[0].iter_mut().rev() {
+            if let Some(record) = run.get(key)? {
+                return Ok(record);
+            }
+        }
+        for runs in self.levels.iter_mut().skip(1) {
+            let i = runs.partition_point(|run| run.last.as_slice() < key);
+            if let Some(run) = runs.get_mut(i)
+                && let Some(record) = run.get(key)?
+            {
+                return Ok(record);
+            }
+        }
+        Ok(None)
+    }
+
+    // Logs the writes as one checksummed batch in one append and applies them to the memtable,
+    // which is flushed once it is full.
+    fn apply(&mut self, writes: Vec<(u8, Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let entry_size = 1 + self.keysize as usize + self.recordsize as usize;
+        let mut log = Vec::with_capacity(writes.len() * entry_size + WAL_BATCH_OVERHEAD);
+        log.extend_from_slice(&(writes.len() as u32).to_le_bytes());
+        for (op, key, record) in &writes {
+            log.push(*op);
+            log.extend_from_slice(key);
+            match record {
+                Some(record) => log.extend_from_slice(record),
+                None => log.resize(log.len() + self.recordsize as usize, 0),
+            }
+        }
+        log.extend_from_slice(&fnv1a(&log).to_le_bytes());
+        self.wal.write_at(self.wal_end, &log)?;
+        self.wal_end += log.len() as u64;
+
+        for (op, key, record) in writes {
+            self.apply_to_memtable(op, key, record);
+        }
+        if self.memtable.len() >= self.memtable_entries {
+            self.flush()?;
+        }
+        self.commit()
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if let Durability::GroupCommit(interval) = self.durability
+            && self.last_commit.elapsed() < interval
+        {
+            self.held_back = true;
+            return Ok(());
+        }
+        self.wal.commit(self.durability)?;
+        self.last_commit = Instant::now();
+        self.held_back = false;
+        Ok(())
+    }
+
+    // Syncs the WAL appends that group commit held back once the interval has passed; see
+    // `IndexedTable::commit_due`.
+    pub(crate) fn commit_due(&mut self) -> Result<()> {
+        if let Durability::GroupCommit(interval) = self.durability
+            && self.held_back
+            && self.last_commit.elapsed() >= interval
+        {
+            self.wal.commit(self.durability)?;
+            self.last_commit = Instant::now();
+            self.held_back = false;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        self.wal.sync()?;
+        self.last_commit = Instant::now();
+        self.held_back = false;
+        Ok(())
+    }
+
+    fn manifest(&self) -> Manifest {
+        let runs = self
+            .levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, runs)| runs.iter().map(move |run| (level as u8, run.id)))
+            .collect();
+        Manifest {
+            keysize: self.keysize,
+            recordsize: self.recordsize,
+            next_id: self.next_id,
+            records: self.records,
+            generation: self.generation,
+            runs,
+        }
+    }
+
+    fn write_run(&mut self, writer: RunWriter) -> Result<Run> {
+        let id = self.next_id;
+        self.next_id += 1;
+        writer.finish(id, self.files.create_run(id)?)
+    }
+
+    // Writes the memtable out as the newest run of level 0 and starts a new WAL. Tombstones
+    // are left out while there are no runs they could hide a record in.
+    fn flush(&mut self) -> Result<()> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+        let bottom = self.levels.iter().all(Vec::is_empty);
+        let mut writer = RunWriter::new(self.keysize, self.recordsize);
+        for (key, record) in &self.memtable {
+            if record.is_some() || !bottom {
+                writer.push(key, record.as_deref());
+            }
+        }
+        if writer.len() > 0 {
+            let run = self.write_run(writer)?;
+            self.levels[0].push(run);
+        }
+
+        self.generation += 1;
+        self.files.write_manifest(&self.manifest())?;
+        self.reset_wal()?;
+        self.memtable.clear();
+        self.compact()
+    }
+
+    fn level_limit(&self, level: usize) -> u64 {
+        self.memtable_entries as u64 * LEVEL_RATIO.pow(level as u32)
+    }
+
+    // Merges levels down until level 0 has fewer than `L0_RUNS` runs and every other level is
+    // within its limit. A level over its limit gives up its oldest run.
+    fn compact(&mut self) -> Result<()> {
+        loop {
+            if self.levels[0].len() >= L0_RUNS {
+                self.merge_down(0, None)?;
+                continue;
+            }
+            let full = (1..self.levels.len()).find(|&level| {
+                self.levels[level].iter().map(|run| run.entries).sum::<u64>() > self.level_limit(level)
+            });
+            let Some(level) = full else {
+                return Ok(());
+            };
+            let oldest = self.levels[level]
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, run)| run.id)
+                .map(|(i, _)| i)
+                .expect("a full level has runs");
+            self.merge_down(level, Some(oldest))?;
+        }
+    }
+
+    // Merges run `pick` of `level`, or all of it, with the runs of the next level it overlaps,
+    // and puts the result there. The inputs are removed once the manifest no longer names them;
+    // if the merge fails they stay where they were.
+    fn merge_down(&mut self, level: usize, pick: Option<usize>) -> Result<()> {
+        if self.levels.len() == level + 1 {
+            self.levels.push(Vec::new());
+        }
+        let mut upper = match pick {
+            Some(i) => vec![self.levels[level].remove(i)],
+            None => std::mem::take(&mut self.levels[level]),
+        };
+        let first = upper.iter().map(|run| run.first().to_vec()).min().expect("merged runs are never empty");
+        let last = upper.iter().map(|run| run.last.clone()).max().expect("merged runs are never empty");
+        let next = &mut self.levels[level + 1];
+        let from = next.partition_point(|run| run.last < first);
+        let to = next.partition_point(|run| run.first() <= last.as_slice());
+        let mut lower: Vec<Run> = next.drain(from..to).collect();
+        let bottom = self.levels[level + 2..].iter().all(Vec::is_empty);
+
+        let merged = match self.merge_runs(&mut upper, &mut lower, bottom) {
+            Ok(merged) => merged,
+            Err(err) => {
+                self.levels[level + 1].splice(from..from, lower);
+                match pick {
+                    Some(i) => self.levels[level].insert(i, upper.pop().unwrap()),
+                    None => self.levels[level] = upper,
+                }
+                return Err(err);
+            }
+        };
+        self.levels[level + 1].splice(from..from, merged);
+        self.files.write_manifest(&self.manifest())?;
+
+        let ids: Vec<u64> = upper.iter().chain(&lower).map(|run| run.id).collect();
+        drop((upper, lower));
+        for id in ids {
+            self.files.remove_run(id)?;
+        }
+        Ok(())
+    }
+
+    // Merges `upper`, in the order level 0 keeps them, over the older `lower`, into runs of
+    // about a memtable each. Runs written before a failure are removed at the next open.
+    fn merge_runs(&mut self, upper: &mut [Run], lower: &mut [Run], bottom: bool) -> Result<Vec<Run>> {
+        let mut sources: Vec<Source<'_>> = Vec::new();
+        for run in upper.iter_mut().rev().chain(lower.iter_mut()) {
+            sources.push(Box::new(RunCursor::new(run, Bound::Unbounded, Bound::Unbounded)));
+        }
+
+        let mut merged = Vec::new();
+        let mut writer = RunWriter::new(self.keysize, self.recordsize);
+        for entry in Merge::new(sources) {
+            let (key, record) = entry?;
+            if record.is_none() && bottom {
+                continue;
+            }
+            writer.push(&key, record.as_deref());
+            if writer.len() >= self.memtable_entries as u64 {
+                let full = std::mem::replace(&mut writer, RunWriter::new(self.keysize, self.recordsize));
+                merged.push(self.write_run(full)?);
+            }
+        }
+        if writer.len() > 0 {
+            merged.push(self.write_run(writer)?);
+        }
+        Ok(merged)
+    }
+
+    pub(crate) fn search_record(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let fixed_key = self.fixed_key(key);
+        self.get(&fixed_key)
+    }
+
+    // The keys are sorted and deduplicated, then looked up in one pass over the memtable and
+    // one over each run, newest first, so that a block is read once for all its keys.
+    pub(crate) fn multi_get(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut unique = Vec::with_capacity(keys.len());
+        for key in keys {
+            check_sizes(self.keysize, self.recordsize, key, &[])?;
+            unique.push(self.fixed_key(key));
+        }
+        unique.sort_unstable();
+        unique.dedup();
+
+        let mut found: Vec<Option<Option<Vec<u8>>>> =
+            unique.iter().map(|key| self.memtable.get(key).cloned()).collect();
+        for run in self.levels[0].iter_mut().rev() {
+            run.get_sorted(&unique, &mut found)?;
+        }
+        for run in self.levels.iter_mut().skip(1).flatten() {
+            run.get_sorted(&unique, &mut found)?;
+        }
+
+        Ok(keys
+            .iter()
+            .map(|key| found[unique.binary_search(&self.fixed_key(key)).unwrap()].clone().flatten())
+            .collect())
+    }
+
+    pub(crate) fn add_record(&mut self, key: &[u8], record: &[u8]) -> Result<()> {
+        check_sizes(self.keysize, self.recordsize, key, record)?;
+        let fixed_key = self.fixed_key(key);
+        if self.get(&fixed_key)?.is_some() {
+            return Err(Error::DuplicateKey);
+        }
+        let record = self.fixed_record(record);
+        self.apply(vec![(WAL_INSERT, fixed_key, Some(record))])
+    }
+
+    pub(crate) fn update_record(&mut self, key: &[u8], new_record: &[u8]) -> Result<()> {
+        check_sizes(self.keysize, self.recordsize, key, new_record)?;
+        let fixed_key = self.fixed_key(key);
+        if self.get(&fixed_key)?.is_none() {
+            return Err(Error::KeyNotFound);
+        }
+        let record = self.fixed_record(new_record);
+        self.apply(vec![(WAL_REPLACE, fixed_key, Some(record))])
+    }
+
+    pub(crate) fn upsert(&mut self, key: &[u8], record: &[u8]) -> Result<WriteOutcome> {
+        check_sizes(self.keysize, self.recordsize, key, record)?;
+        let fixed_key = self.fixed_key(key);
+        let (op, outcome) = match self.get(&fixed_key)? {
+            Some(_) => (WAL_REPLACE, WriteOutcome::Replaced),
+            None => (WAL_INSERT, WriteOutcome::Inserted),
+        };
+        let record = self.fixed_record(record);
+        self.apply(vec![(op, fixed_key, Some(record))])?;
+        Ok(outcome)
+    }
+
+    pub(crate) fn insert_if_absent(&mut self, key: &[u8], record: &[u8]) -> Result<WriteOutcome> {
+        check_sizes(self.keysize, self.recordsize, key, record)?;
+        let fixed_key = self.fixed_key(key);
+        if let Some(current) = self.get(&fixed_key)? {
+            return Ok(WriteOutcome::Kept(current));
+        }
+        let record = self.fixed_record(record);
+        self.apply(vec![(WAL_INSERT, fixed_key, Some(record))])?;
+        Ok(WriteOutcome::Inserted)
+    }
+
+    pub(crate) fn compare_and_swap(&mut self, key: &[u8], expected: &[u8], new: &[u8]) -> Result<WriteOutcome> {
+        check_sizes(self.keysize, self.recordsize, key, new)?;
+        check_sizes(self.keysize, self.recordsize, key, expected)?;
+        let fixed_key = self.fixed_key(key);
+        let Some(current) = self.get(&fixed_key)? else {
+            return Ok(WriteOutcome::Missing);
+        };
+        if current != self.fixed_record(expected) {
+            return Ok(WriteOutcome::Kept(current));
+        }
+        let record = self.fixed_record(new);
+        self.apply(vec![(WAL_REPLACE, fixed_key, Some(record))])?;
+        Ok(WriteOutcome::Replaced)
+    }
+
+    // A delete only adds a tombstone, which hides the record in older runs until compaction
+    // merges the two.
+    pub(crate) fn delete_record(&mut self, key: &[u8]) -> Result<()> {
+        check_sizes(self.keysize, self.recordsize, key, &[])?;
+        let fixed_key = self.fixed_key(key);
+        if self.get(&fixed_key)?.is_none() {
+            return Err(Error::KeyNotFound);
+        }
+        self.apply(vec![(WAL_DELETE, fixed_key, None)])
+    }
+
+    // Checked as a whole first, like an indexed table's batch, and then logged in one append.
+    pub(crate) fn write_batch(&mut self, batch: &WriteBatch) -> Result<()> {
+        let mut changes: BTreeMap<Vec<u8>, BatchChange> = BTreeMap::new();
+        for (key, write) in &batch.writes {
+            let record = match write {
+                BatchWrite::Put(record) | BatchWrite::Update(record) => record.as_slice(),
+                BatchWrite::Delete => &[],
+            };
+            check_sizes(self.keysize, self.recordsize, key, record)?;
+
+            let fixed_key = self.fixed_key(key);
+            let change = match changes.entry(fixed_key) {
+                MapEntry::Occupied(entry) => entry.into_mut(),
+                MapEntry::Vacant(entry) => {
+                    let existed = self.get(entry.key())?.is_some();
+                    entry.insert(BatchChange {
+                        existed,
+                        present: existed,
+                        record: None,
+                    })
+                }
+            };
+            match write {
+                BatchWrite::Put(record) => {
+                    change.present = true;
+                    change.record = Some(self.fixed_record(record));
+                }
+                BatchWrite::Update(record) if change.present => change.record = Some(self.fixed_record(record)),
+                BatchWrite::Delete if change.present => {
+                    change.present = false;
+                    change.record = None;
+                }
+                _ => return Err(Error::KeyNotFound),
+            }
+        }
+
+        let writes = changes
+            .into_iter()
+            .filter_map(|(key, change)| match (change.existed, change.present) {
+                (false, true) => Some((WAL_INSERT, key, change.record)),
+                (true, true) => Some((WAL_REPLACE, key, change.record)),
+                (true, false) => Some((WAL_DELETE, key, None)),
+                (false, false) => None,
+            })
+            .collect();
+        self.apply(writes)
+    }
+
+    pub(crate) fn scan_range<'k>(&mut self, range: impl RangeBounds<&'k [u8]>) -> LsmScan<'_> {
+        let start = range.start_bound().map(|key| self.fixed_key(key));
+        let end = range.end_bound().map(|key| self.fixed_key(key));
+        self.scan_bounds(start, end)
+    }
+
+    pub(crate) fn scan_prefix(&mut self, prefix: &[u8]) -> LsmScan<'_> {
+        let (start, end) = prefix_range(prefix, self.keysize as usize);
+        let start = start.map(|key| self.fixed_key(&key));
+        let end = end.map(|key| self.fixed_key(&key));
+        self.scan_bounds(start, end)
+    }
+
+    // Sources newest first: the memtable, level 0 from its newest run, then the levels below.
+    fn scan_bounds(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> LsmScan<'_> {
+        let mut sources: Vec<Source<'_>> = Vec::new();
+        if !is_empty_range(&start, &end) {
+            let memtable = self.memtable.range((start.clone(), end.clone()));
+            sources.push(Box::new(memtable.map(|(key, record)| Ok((key.clone(), record.clone())))));
+
+            let (level0, below) = self.levels.split_first_mut().expect("level 0 always exists");
+            for run in level0.iter_mut().rev().chain(below.iter_mut().flatten()) {
+                if run.overlaps(&start, &end) {
+                    sources.push(Box::new(RunCursor::new(run, start.clone(), end.clone())));
+                }
+            }
+        }
+        LsmScan {
+            merge: Merge::new(sources),
+        }
+    }
+
+    pub(crate) fn min_key(&mut self) -> Result<Option<Vec<u8>>> {
+        self.scan_range(..).next().transpose().map(|entry| entry.map(|(key, _)| key))
+    }
+
+    pub(crate) fn max_key(&mut self) -> Result<Option<Vec<u8>>> {
+        self.scan_range(..).next_back().transpose().map(|entry| entry.map(|(key, _)| key))
+    }
+
+    pub(crate) fn for_each_record<F>(&mut self, mut visit: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        for entry in self.scan_range(..) {
+            let (key, record) = entry?;
+            visit(&key, &record)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that every run is sorted and agrees with its fences, that the runs of each level
+    /// below 0 are sorted and disjoint, and that the live records add up to the count.
+    pub(crate) fn verify(&mut self) -> Result<()> {
+        for (level, runs) in self.levels.iter_mut().enumerate() {
+            for run in runs.iter_mut() {
+                let mut previous: Option<Vec<u8>> = None;
+                let mut tombstones = 0;
+                for block in 0..run.fences.len() {
+                    let entries = run.read_block(block)?;
+                    if entries.first().map(|(key, _)| key) != Some(&run.fences[block]) {
+                        return Err(Error::Corruption(format!(
+                            "run {} block {} does not start at its fence",
+                            run.id, block
+                        )));
+                    }
+                    for (key, record) in entries {
+                        if previous.as_ref().is_some_and(|previous| *previous >= key) {
+                            return Err(Error::Corruption(format!("run {} is out of order", run.id)));
+                        }
+                        tombstones += record.is_none() as u64;
+                        previous = Some(key);
+                    }
+                }
+                if previous.as_ref() != Some(&run.last) || tombstones != run.tombstones {
+                    return Err(Error::Corruption(format!("run {} does not match its header", run.id)));
+                }
+            }
+            if level > 0 && runs.windows(2).any(|pair| pair[0].last.as_slice() >= pair[1].first()) {
+                return Err(Error::Corruption(format!("runs of level {} overlap", level)));
+            }
+        }
+
+        let mut live = 0;
+        for entry in self.scan_range(..) {
+            entry?;
+            live += 1;
+        }
+        if live != self.records {
+            return Err(Error::Corruption(format!(
+                "LSM table holds {} live records, expected {}",
+                live, self.records
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LsmTable {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
@@ -0,0 +1,105 @@
+use crate::storage::Storage;
+use memmap2::MmapMut;
+use std::fs::File;
+use std::io;
+
+// The mapping is grown in chunks so that appending pages does not remap on every write.
+const MIN_GROW: u64 = 1 << 20;
+
+pub struct MmapStorage {
+    file: File,
+    map: MmapMut,
+    len: u64,
+}
+
+impl MmapStorage {
+    /// Maps `file`, which the storage then owns. Nothing else may truncate or resize the file
+    /// while it is mapped.
+    pub fn new(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        if len == 0 {
+            file.set_len(MIN_GROW)?;
+        }
+        // SAFETY: the storage owns the file, and nothing else may truncate it while it is mapped
+        // (see above). The storage itself only ever grows the file while a mapping exists; `truncate`
+        // zeroes the tail instead of shrinking it, and `drop` shrinks it only once the mapping is
+        // never touched again.
+        let map = unsafe { MmapMut::map_mut(&file)? };
+
+        Ok(MmapStorage { file, map, len })
+    }
+
+    fn grow(&mut self, min_len: u64) -> io::Result<()> {
+        let capacity = (self.map.len() as u64 * 2).max(min_len).max(MIN_GROW);
+        self.file.set_len(capacity)?;
+        // SAFETY: as in `new`; the file only grew, so the old mapping stays valid until it is replaced.
+        self.map = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+}
+
+impl Storage for MmapStorage {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let bytes = self.mapped(offset, buf.len()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "Read past end of mapped file")
+        })?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let end = offset + buf.len() as u64;
+        if end > self.map.len() as u64 {
+            self.grow(end)?;
+        }
+
+        let start = offset as usize;
+        self.map[start..start + buf.len()].copy_from_slice(buf);
+        self.len = self.len.max(end);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.map.flush_async()
+    }
+
+    fn sync_data(&mut self) -> io::Result<()> {
+        self.map.flush()?;
+        self.file.sync_data()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.map.flush()?;
+        self.file.sync_all()
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        if len > self.map.len() as u64 {
+            self.grow(len)?;
+        } else {
+            self.map[len as usize..].fill(0);
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    fn mapped(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        if offset + len as u64 > self.len {
+            return None;
+        }
+        let start = offset as usize;
+        Some(&self.map[start..start + len])
+    }
+}
+
+impl Drop for MmapStorage {
+    fn drop(&mut self) {
+        // Give back the preallocated tail so the file is the same size the File backend would leave.
+        let _ = self.map.flush();
+        let _ = self.file.set_len(self.len);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::error::{Error, Result};
+use bincode::{Decode, Encode};
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum ColumnType {
+    Int,
+    Float,
+    Bool,
+    Text(u16),
+}
+
+impl ColumnType {
+    pub fn size(self) -> u16 {
+        match self {
+            ColumnType::Int | ColumnType::Float => 8,
+            ColumnType::Bool => 1,
+            ColumnType::Text(len) => len,
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnType::Int => write!(f, "INT"),
+            ColumnType::Float => write!(f, "FLOAT"),
+            ColumnType::Bool => write!(f, "BOOL"),
+            ColumnType::Text(len) => write!(f, "TEXT({})", len),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Column {
+    pub name: String,
+    pub ty: ColumnType,
+}
+
+/// Typed columns of a table. The key column is stored as the record key and the others are
+/// packed, in order, into the fixed-size record.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Schema {
+    pub columns: Vec<Column>,
+    pub key: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+    // Only produced by queries, e.g. the MIN of no rows; it cannot be stored.
+    Null,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Text(v) => write!(f, "{}", v),
+            Value::Null => write!(f, "NULL"),
+        }
+    }
+}
+
+impl Value {
+    // Ints and floats compare with each other; any other mix of types is unordered.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+
+    /// Converts the value to `ty`, widening ints to floats.
+    pub fn coerce(self, ty: ColumnType) -> Result<Value> {
+        match (self, ty) {
+            (Value::Int(v), ColumnType::Int) => Ok(Value::Int(v)),
+            (Value::Int(v), ColumnType::Float) => Ok(Value::Float(v as f64)),
+            (Value::Float(v), ColumnType::Float) => Ok(Value::Float(v)),
+            (Value::Bool(v), ColumnType::Bool) => Ok(Value::Bool(v)),
+            (Value::Text(v), ColumnType::Text(len)) if v.len() <= len as usize && !v.contains('\0') => {
+                Ok(Value::Text(v))
+            }
+            (Value::Text(v), ColumnType::Text(len)) => Err(Error::Sql(format!(
+                "'{}' does not fit in TEXT({})",
+                v, len
+            ))),
+            (value, ty) => Err(Error::Sql(format!("{} is not a valid {}", value, ty))),
+        }
+    }
+
+    // Integers and floats are stored so that comparing the bytes orders them like numbers,
+    // which keeps key columns of any type sorted in the B-tree.
+    fn encode(&self, out: &mut [u8]) {
+        match self {
+            Value::Int(v) => out.copy_from_slice(&((*v as u64) ^ (1 << 63)).to_be_bytes()),
+            Value::Float(v) => {
+                let bits = v.to_bits();
+                let ordered = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+                out.copy_from_slice(&ordered.to_be_bytes());
+            }
+            Value::Bool(v) => out[0] = *v as u8,
+            Value::Text(v) => {
+                out.fill(0);
+                out[..v.len()].copy_from_slice(v.as_bytes());
+            }
+            Value::Null => unreachable!("coerce rejects NULL"),
+        }
+    }
+
+    fn decode(ty: ColumnType, bytes: &[u8]) -> Value {
+        match ty {
+            ColumnType::Int => Value::Int((u64::from_be_bytes(bytes.try_into().unwrap()) ^ (1 << 63)) as i64),
+            ColumnType::Float => {
+                let ordered = u64::from_be_bytes(bytes.try_into().unwrap());
+                let bits = if ordered >> 63 == 1 { ordered ^ (1 << 63) } else { !ordered };
+                Value::Float(f64::from_bits(bits))
+            }
+            ColumnType::Bool => Value::Bool(bytes[0] != 0),
+            ColumnType::Text(_) => {
+                let end = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+                Value::Text(String::from_utf8_lossy(&bytes[..end]).into_owned())
+            }
+        }
+    }
+}
+
+impl Schema {
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name == name)
+    }
+
+    pub fn key_size(&self) -> u16 {
+        self.columns[self.key].ty.size()
+    }
+
+    // Tables need a non-empty record, so a key-only schema still gets one padding byte.
+    pub fn record_size(&self) -> u16 {
+        let size: u16 = self.value_columns().map(|(_, column)| column.ty.size()).sum();
+        size.max(1)
+    }
+
+    fn value_columns(&self) -> impl Iterator<Item = (usize, &Column)> {
+        self.columns.iter().enumerate().filter(move |(i, _)| *i != self.key)
+    }
+
+    /// Encodes a value of column `i` as it would be stored, in byte-comparable form.
+    pub fn encode_column(&self, i: usize, value: &Value) -> Result<Vec<u8>> {
+        let ty = self.columns[i].ty;
+        let mut bytes = vec![0u8; ty.size() as usize];
+        value.clone().coerce(ty)?.encode(&mut bytes);
+        Ok(bytes)
+    }
+
+    pub fn encode_key(&self, value: &Value) -> Result<Vec<u8>> {
+        self.encode_column(self.key, value)
+    }
+
+    /// Encodes a full row, given in column order, into its key and record.
+    pub fn encode_row(&self, row: &[Value]) -> Result<(Vec<u8>, Vec<u8>)> {
+        let key = self.encode_key(&row[self.key])?;
+
+        let mut record = vec![0u8; self.record_size() as usize];
+        let mut pos = 0;
+        for (i, column) in self.value_columns() {
+            let size = column.ty.size() as usize;
+            row[i].clone().coerce(column.ty)?.encode(&mut record[pos..pos + size]);
+            pos += size;
+        }
+
+        Ok((key, record))
+    }
+
+    pub fn decode_key(&self, key: &[u8]) -> Value {
+        Value::decode(self.columns[self.key].ty, key)
+    }
+
+    pub fn decode_row(&self, key: &[u8], record: &[u8]) -> Vec<Value> {
+        let mut row = Vec::with_capacity(self.columns.len());
+        let mut pos = 0;
+        for (i, column) in self.columns.iter().enumerate() {
+            if i == self.key {
+                row.push(Value::decode(column.ty, key));
+            } else {
+                let size = column.ty.size() as usize;
+                row.push(Value::decode(column.ty, &record[pos..pos + size]));
+                pos += size;
+            }
+        }
+        row
+    }
+}
@@ -0,0 +1,226 @@
+use super::parser::{Function, Predicate, SelectItem};
+use super::{QueryResult, SqlTable, column_index};
+use crate::error::{Error, Result};
+use crate::schema::{ColumnType, Schema, Value};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+// A select item resolved against the schema.
+#[derive(Clone, Copy)]
+enum Output {
+    Group,
+    Aggregate(Function, Option<usize>),
+}
+
+#[derive(Clone)]
+struct Accumulator {
+    function: Function,
+    column: Option<usize>,
+    count: u64,
+    int_sum: i64,
+    float_sum: f64,
+    best: Option<Value>,
+}
+
+impl Accumulator {
+    fn new(function: Function, column: Option<usize>) -> Self {
+        Accumulator {
+            function,
+            column,
+            count: 0,
+            int_sum: 0,
+            float_sum: 0.0,
+            best: None,
+        }
+    }
+
+    fn add(&mut self, row: &[Value]) -> Result<()> {
+        self.count += 1;
+        let Some(column) = self.column else {
+            return Ok(());
+        };
+
+        match (&row[column], self.function) {
+            (Value::Int(v), Function::Sum | Function::Avg) => {
+                self.int_sum = self
+                    .int_sum
+                    .checked_add(*v)
+                    .ok_or_else(|| Error::Sql("integer overflow in SUM".to_string()))?;
+                self.float_sum += *v as f64;
+            }
+            (Value::Float(v), Function::Sum | Function::Avg) => self.float_sum += v,
+            (value, Function::Min | Function::Max) => {
+                let wanted = if self.function == Function::Min { Ordering::Less } else { Ordering::Greater };
+                let better = match &self.best {
+                    Some(best) => value.compare(best) == Some(wanted),
+                    None => true,
+                };
+                if better {
+                    self.best = Some(value.clone());
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // SUM keeps the type of its column; AVG is always a float. Apart from COUNT, aggregates of
+    // no rows are NULL.
+    fn finish(&self, schema: &Schema) -> Value {
+        let ty = self.column.map(|column| schema.columns[column].ty);
+        match self.function {
+            Function::Count => Value::Int(self.count as i64),
+            _ if self.count == 0 => Value::Null,
+            Function::Sum if ty == Some(ColumnType::Int) => Value::Int(self.int_sum),
+            Function::Sum => Value::Float(self.float_sum),
+            Function::Avg => Value::Float(self.float_sum / self.count as f64),
+            Function::Min | Function::Max => self.best.clone().unwrap_or(Value::Null),
+        }
+    }
+}
+
+pub(super) fn is_aggregate(columns: Option<&[SelectItem]>, group_by: Option<&str>) -> bool {
+    group_by.is_some()
+        || columns.is_some_and(|items| items.iter().any(|item| matches!(item, SelectItem::Aggregate(..))))
+}
+
+/// Runs a SELECT with aggregates and an optional GROUP BY. Groups come out in the order of
+/// their value.
+pub(super) fn select(
+    table: &mut SqlTable,
+    columns: Option<&[SelectItem]>,
+    filter: &[Predicate],
+    group_by: Option<&str>,
+) -> Result<QueryResult> {
+    let schema = table.schema.clone();
+    let items = columns.ok_or_else(|| Error::Sql("SELECT * cannot be used with GROUP BY".to_string()))?;
+    let group = group_by.map(|name| column_index(&schema, name)).transpose()?;
+
+    let outputs = items
+        .iter()
+        .map(|item| resolve(&schema, item, group))
+        .collect::<Result<Vec<_>>>()?;
+    let names = items.iter().map(label).collect();
+    let accumulators: Vec<Accumulator> = outputs
+        .iter()
+        .filter_map(|output| match *output {
+            Output::Aggregate(function, column) => Some(Accumulator::new(function, column)),
+            Output::Group => None,
+        })
+        .collect();
+
+    if filter.is_empty()
+        && group.is_none()
+        && let Some(row) = from_index(table, &outputs)?
+    {
+        return Ok(QueryResult::Rows {
+            columns: names,
+            rows: vec![row],
+        });
+    }
+
+    // Encoded values sort like the values themselves, so they make good group keys.
+    let mut groups: BTreeMap<Vec<u8>, (Value, Vec<Accumulator>)> = BTreeMap::new();
+    if group.is_none() {
+        groups.insert(Vec::new(), (Value::Null, accumulators.clone()));
+    }
+
+    let (_, rows) = table.matching_rows(filter)?;
+    for (_, row) in rows {
+        let key = match group {
+            Some(column) => schema.encode_column(column, &row[column])?,
+            None => Vec::new(),
+        };
+        let (_, state) = groups.entry(key).or_insert_with(|| {
+            let value = group.map_or(Value::Null, |column| row[column].clone());
+            (value, accumulators.clone())
+        });
+        for accumulator in state.iter_mut() {
+            accumulator.add(&row)?;
+        }
+    }
+
+    let rows = groups
+        .into_values()
+        .map(|(value, state)| {
+            let mut state = state.iter();
+            outputs
+                .iter()
+                .map(|output| match output {
+                    Output::Group => value.clone(),
+                    Output::Aggregate(..) => state.next().unwrap().finish(&schema),
+                })
+                .collect()
+        })
+        .collect();
+    Ok(QueryResult::Rows { columns: names, rows })
+}
+
+fn resolve(schema: &Schema, item: &SelectItem, group: Option<usize>) -> Result<Output> {
+    match item {
+        SelectItem::Column(name) => {
+            let column = column_index(schema, name)?;
+            if Some(column) != group {
+                return Err(Error::Sql(format!(
+                    "{} must be aggregated or appear in GROUP BY",
+                    name
+                )));
+            }
+            Ok(Output::Group)
+        }
+        SelectItem::Aggregate(function, name) => {
+            let column = name.as_deref().map(|name| column_index(schema, name)).transpose()?;
+            if let (Function::Sum | Function::Avg, Some(column)) = (function, column) {
+                let ty = schema.columns[column].ty;
+                if !matches!(ty, ColumnType::Int | ColumnType::Float) {
+                    return Err(Error::Sql(format!("{} needs a numeric column, not {}", label(item), ty)));
+                }
+            }
+            Ok(Output::Aggregate(*function, column))
+        }
+    }
+}
+
+// Without a filter, COUNT comes from the index header and MIN/MAX of the key from the ends of
+// the B-tree, so no records are read.
+fn from_index(table: &mut SqlTable, outputs: &[Output]) -> Result<Option<Vec<Value>>> {
+    let key = table.schema.key;
+    let answerable = outputs.iter().all(|output| match output {
+        Output::Aggregate(Function::Count, _) => true,
+        Output::Aggregate(Function::Min | Function::Max, column) => *column == Some(key),
+        _ => false,
+    });
+    if !answerable {
+        return Ok(None);
+    }
+
+    let mut row = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        let key = match output {
+            Output::Aggregate(Function::Count, _) => {
+                row.push(Value::Int(table.table.count() as i64));
+                continue;
+            }
+            Output::Aggregate(Function::Min, _) => table.table.min_key()?,
+            _ => table.table.max_key()?,
+        };
+        row.push(key.map_or(Value::Null, |key| table.schema.decode_key(&key)));
+    }
+    Ok(Some(row))
+}
+
+fn label(item: &SelectItem) -> String {
+    match item {
+        SelectItem::Column(name) => name.clone(),
+        SelectItem::Aggregate(function, column) => {
+            let function = match function {
+                Function::Count => "count",
+                Function::Sum => "sum",
+                Function::Avg => "avg",
+                Function::Min => "min",
+                Function::Max => "max",
+            };
+            format!("{}({})", function, column.as_deref().unwrap_or("*"))
+        }
+    }
+}
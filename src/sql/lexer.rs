@@ -0,0 +1,68 @@
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Word(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 13] = ["<=", ">=", "!=", "<>", "(", ")", ",", "*", "=", "<", ">", ";", "-"];
+
+pub(crate) fn tokenize(sql: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = sql;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let number = &rest[..end];
+            let token = if number.contains('.') {
+                number.parse().map(Token::Float).ok()
+            } else {
+                number.parse().map(Token::Int).ok()
+            };
+            tokens.push(token.ok_or_else(|| Error::Sql(format!("bad number {}", number)))?);
+            rest = &rest[end..];
+        } else if c == '\'' {
+            // Strings are single-quoted; a doubled quote stands for one quote.
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '\'')) => {
+                        if rest[2 + i..].starts_with('\'') {
+                            value.push('\'');
+                            chars.next();
+                        } else {
+                            break 2 + i;
+                        }
+                    }
+                    Some((_, c)) => value.push(c),
+                    None => return Err(Error::Sql("unterminated string".to_string())),
+                }
+            };
+            tokens.push(Token::Str(value));
+            rest = &rest[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            return Err(Error::Sql(format!("unexpected character {:?}", c)));
+        }
+    }
+
+    Ok(tokens)
+}
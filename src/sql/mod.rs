@@ -0,0 +1,457 @@
+mod aggregate;
+mod lexer;
+mod parser;
+mod planner;
+
+use crate::btree::Index;
+use crate::catalog::IndexInfo;
+use crate::database::Database;
+use crate::error::{Error, Result};
+use crate::schema::{ColumnType, Schema, Value};
+use crate::storage::{FileStorage, Storage};
+use crate::table::{Table, TableOptions};
+use parser::{Op, Predicate, SelectItem, Statement};
+use planner::{Access, Plan, SecondaryIndex, Stats};
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs::OpenOptions;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryResult {
+    Created,
+    Rows {
+        columns: Vec<String>,
+        rows: Vec<Vec<Value>>,
+    },
+    Affected(u64),
+    Explain {
+        plan: String,
+        estimated_rows: u64,
+        estimated_reads: u64,
+        actual_rows: u64,
+        actual_reads: u64,
+    },
+}
+
+impl fmt::Display for QueryResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryResult::Created => write!(f, "created"),
+            QueryResult::Affected(n) => write!(f, "{} row(s) affected", n),
+            QueryResult::Rows { columns, rows } => {
+                write!(f, "{}", columns.join(" | "))?;
+                for row in rows {
+                    let values: Vec<String> = row.iter().map(Value::to_string).collect();
+                    write!(f, "\n{}", values.join(" | "))?;
+                }
+                Ok(())
+            }
+            QueryResult::Explain {
+                plan,
+                estimated_rows,
+                estimated_reads,
+                actual_rows,
+                actual_reads,
+            } => {
+                writeln!(f, "plan: {}", plan)?;
+                writeln!(f, "estimated: {} row(s), {} node read(s)", estimated_rows, estimated_reads)?;
+                write!(f, "actual: {} row(s), {} node read(s)", actual_rows, actual_reads)
+            }
+        }
+    }
+}
+
+// A table opened for SQL, with its column definitions and secondary indexes.
+struct SqlTable {
+    table: Table,
+    schema: Schema,
+    indexes: Vec<Secondary>,
+}
+
+// (key, row) pairs, in the order an access path produced them.
+type Rows = Vec<(Vec<u8>, Vec<Value>)>;
+
+struct Secondary {
+    info: IndexInfo,
+    column: usize,
+    index: Index,
+}
+
+impl Database {
+    /// Runs one SQL statement against the tables of this database.
+    pub fn execute(&mut self, sql: &str) -> Result<QueryResult> {
+        match parser::parse(sql)? {
+            Statement::CreateTable { name, schema } => {
+                let mut options = TableOptions::new();
+                options.key_size(schema.key_size()).record_size(schema.record_size());
+                self.create_table_with_schema(&name, &options, Some(schema))?;
+                Ok(QueryResult::Created)
+            }
+            Statement::CreateIndex { name, table, column } => {
+                let (_, schema) = self.table_schema(&table)?;
+                column_index(&schema, &column)?;
+                self.add_index(&table, &name, &column)?;
+                // Opening the table builds the new index from its rows.
+                self.open_sql_table(&table)?;
+                Ok(QueryResult::Created)
+            }
+            Statement::Insert { table, columns, rows } => {
+                let mut table = self.open_sql_table(&table)?;
+                let order = match columns {
+                    Some(columns) => insert_order(&table.schema, &columns)?,
+                    None => (0..table.schema.columns.len()).collect(),
+                };
+
+                for values in &rows {
+                    if values.len() != order.len() {
+                        return Err(Error::Sql(format!(
+                            "expected {} values, got {}",
+                            order.len(),
+                            values.len()
+                        )));
+                    }
+                    let row: Vec<Value> = order.iter().map(|&i| values[i].clone()).collect();
+                    table.insert(&row)?;
+                }
+                table.commit()?;
+                Ok(QueryResult::Affected(rows.len() as u64))
+            }
+            Statement::Select {
+                table,
+                columns,
+                filter,
+                group_by,
+            } => {
+                let mut table = self.open_sql_table(&table)?;
+                if aggregate::is_aggregate(columns.as_deref(), group_by.as_deref()) {
+                    return aggregate::select(&mut table, columns.as_deref(), &filter, group_by.as_deref());
+                }
+
+                let projection = match columns {
+                    Some(columns) => columns
+                        .iter()
+                        .map(|item| match item {
+                            SelectItem::Column(name) => column_index(&table.schema, name),
+                            SelectItem::Aggregate(..) => unreachable!(),
+                        })
+                        .collect::<Result<Vec<_>>>()?,
+                    None => (0..table.schema.columns.len()).collect(),
+                };
+
+                let (_, rows) = table.matching_rows(&filter)?;
+                let rows = rows
+                    .into_iter()
+                    .map(|(_, row)| projection.iter().map(|&i| row[i].clone()).collect())
+                    .collect();
+                Ok(QueryResult::Rows {
+                    columns: projection.iter().map(|&i| table.schema.columns[i].name.clone()).collect(),
+                    rows,
+                })
+            }
+            Statement::Update {
+                table,
+                assignments,
+                filter,
+            } => {
+                let mut table = self.open_sql_table(&table)?;
+                let schema = table.schema.clone();
+                let assignments = assignments
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let i = column_index(&schema, &name)?;
+                        if i == schema.key {
+                            return Err(Error::Sql(format!("cannot update the primary key {}", name)));
+                        }
+                        Ok((i, value.coerce(schema.columns[i].ty)?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                let (_, rows) = table.matching_rows(&filter)?;
+                for (key, old) in &rows {
+                    let mut row = old.clone();
+                    for (i, value) in &assignments {
+                        row[*i] = value.clone();
+                    }
+                    table.update(key, old, &row)?;
+                }
+                table.commit()?;
+                Ok(QueryResult::Affected(rows.len() as u64))
+            }
+            Statement::Delete { table, filter } => {
+                let mut table = self.open_sql_table(&table)?;
+                let (_, rows) = table.matching_rows(&filter)?;
+                for (key, row) in &rows {
+                    table.delete(key, row)?;
+                }
+                table.commit()?;
+                Ok(QueryResult::Affected(rows.len() as u64))
+            }
+            Statement::Explain(statement) => {
+                let (table, filter) = match *statement {
+                    Statement::Select { table, filter, .. }
+                    | Statement::Update { table, filter, .. }
+                    | Statement::Delete { table, filter } => (table, filter),
+                    _ => return Err(Error::Sql("EXPLAIN supports SELECT, UPDATE and DELETE".to_string())),
+                };
+
+                // Runs the lookup part of the statement, without changing anything.
+                let mut table = self.open_sql_table(&table)?;
+                let reads_before = table.node_reads();
+                let (plan, rows) = table.matching_rows(&filter)?;
+                Ok(QueryResult::Explain {
+                    plan: plan.description,
+                    estimated_rows: plan.estimated_rows,
+                    estimated_reads: plan.estimated_reads,
+                    actual_rows: rows.len() as u64,
+                    actual_reads: table.node_reads() - reads_before,
+                })
+            }
+        }
+    }
+
+    fn table_schema(&self, name: &str) -> Result<(Vec<IndexInfo>, Schema)> {
+        let info = self.table_info(name).ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        let schema = info
+            .schema
+            .clone()
+            .ok_or_else(|| Error::Sql(format!("table {} has no column definitions", name)))?;
+        Ok((info.indexes.clone(), schema))
+    }
+
+    fn open_sql_table(&self, name: &str) -> Result<SqlTable> {
+        let (infos, schema) = self.table_schema(name)?;
+        let mut table = self.open_table(name)?;
+        if table.index().is_none() {
+            return Err(Error::Sql(format!("table {} has no B-tree index to run SQL on", name)));
+        }
+
+        let mut indexes = Vec::new();
+        for info in infos {
+            let column = column_index(&schema, &info.column)?;
+            let index = self.open_secondary(&mut table, &schema, &info, column)?;
+            indexes.push(Secondary { info, column, index });
+        }
+
+        Ok(SqlTable { table, schema, indexes })
+    }
+
+    // A secondary index that is missing, was left dirty, or disagrees with the table on the
+    // number of rows is rebuilt from the table.
+    fn open_secondary(&self, table: &mut Table, schema: &Schema, info: &IndexInfo, column: usize) -> Result<Index> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.file_path(&info.file))?;
+        let mut storage: Box<dyn Storage> = Box::new(FileStorage::new(file));
+
+        let primary = table.index().expect("SQL tables have a B-tree index");
+        let page_size = primary.page_size();
+        if Index::clean_len(storage.as_mut())? == Some(primary.len()) {
+            return Index::open_with_storage(storage);
+        }
+
+        storage.truncate(0)?;
+        let keysize = schema.columns[column].ty.size() + schema.key_size();
+        let mut index = Index::create_with_storage(storage, keysize, page_size)?;
+        for entry in table.scan()? {
+            let (key, record) = entry?;
+            let row = schema.decode_row(&key, &record);
+            index.insert(secondary_key(schema, column, &row, &key)?, 0)?;
+        }
+        index.sync()?;
+        Ok(index)
+    }
+}
+
+impl SqlTable {
+    // Checked by `open_sql_table`.
+    fn primary(&self) -> &Index {
+        self.table.index().expect("SQL tables have a B-tree index")
+    }
+
+    fn node_reads(&self) -> u64 {
+        self.primary().node_reads() + self.indexes.iter().map(|s| s.index.node_reads()).sum::<u64>()
+    }
+
+    fn insert(&mut self, row: &[Value]) -> Result<()> {
+        let (key, record) = self.schema.encode_row(row)?;
+        self.table.add_record(&key, &record)?;
+        for secondary in &mut self.indexes {
+            let entry = secondary_key(&self.schema, secondary.column, row, &key)?;
+            secondary.index.insert(entry, 0)?;
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, key: &[u8], old: &[Value], new: &[Value]) -> Result<()> {
+        let (_, record) = self.schema.encode_row(new)?;
+        self.table.update_record(key, &record)?;
+        for secondary in &mut self.indexes {
+            if old[secondary.column] != new[secondary.column] {
+                let old_entry = secondary_key(&self.schema, secondary.column, old, key)?;
+                secondary.index.delete(&old_entry)?;
+                let new_entry = secondary_key(&self.schema, secondary.column, new, key)?;
+                secondary.index.insert(new_entry, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8], row: &[Value]) -> Result<()> {
+        self.table.delete_record(key)?;
+        for secondary in &mut self.indexes {
+            let entry = secondary_key(&self.schema, secondary.column, row, key)?;
+            secondary.index.delete(&entry)?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let durability = self.table.durability();
+        for secondary in &mut self.indexes {
+            secondary.index.commit(durability)?;
+        }
+        Ok(())
+    }
+
+    fn plan(&self, filter: &[Predicate]) -> Result<Plan> {
+        let stats = |index: &Index| Stats {
+            rows: index.len(),
+            t: index.t(),
+        };
+        let indexes: Vec<SecondaryIndex> = self
+            .indexes
+            .iter()
+            .map(|secondary| SecondaryIndex {
+                name: &secondary.info.name,
+                column: secondary.column,
+                stats: stats(&secondary.index),
+            })
+            .collect();
+        planner::plan(&self.schema, filter, stats(self.primary()), &indexes)
+    }
+
+    // The plan used and the (key, row) pairs that satisfy every predicate.
+    fn matching_rows(&mut self, filter: &[Predicate]) -> Result<(Plan, Rows)> {
+        let schema = &self.schema;
+        let checks = filter
+            .iter()
+            .map(|predicate| {
+                let i = column_index(schema, &predicate.column)?;
+                check_literal(&predicate.value, schema.columns[i].ty)?;
+                Ok((i, predicate.op, predicate.value.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let matches = |row: &[Value]| {
+            checks.iter().all(|(i, op, value)| {
+                let ordering = row[*i].compare(value);
+                match op {
+                    Op::Eq => ordering == Some(Ordering::Equal),
+                    Op::Ne => ordering.is_some_and(|o| o != Ordering::Equal),
+                    Op::Lt => ordering == Some(Ordering::Less),
+                    Op::Le => ordering.is_some_and(|o| o != Ordering::Greater),
+                    Op::Gt => ordering == Some(Ordering::Greater),
+                    Op::Ge => ordering.is_some_and(|o| o != Ordering::Less),
+                }
+            })
+        };
+
+        let plan = self.plan(filter)?;
+        let mut rows = Vec::new();
+        let mut visit = |key: Vec<u8>, record: Vec<u8>| {
+            let row = schema.decode_row(&key, &record);
+            if matches(&row) {
+                rows.push((key, row));
+            }
+        };
+
+        match &plan.access {
+            Access::Point(key) => {
+                if let Some(record) = self.table.search_record(key)? {
+                    visit(key.clone(), record);
+                }
+            }
+            Access::Range(lower, upper) => {
+                let range = (lower.as_ref().map(Vec::as_slice), upper.as_ref().map(Vec::as_slice));
+                for entry in self.table.scan_range(range)? {
+                    let (key, record) = entry?;
+                    visit(key, record);
+                }
+            }
+            Access::IndexScan { index, lower, upper } => {
+                let secondary = &mut self.indexes[*index];
+                let value_size = schema.columns[secondary.column].ty.size() as usize;
+                let range = (lower.as_ref().map(Vec::as_slice), upper.as_ref().map(Vec::as_slice));
+                let keys = secondary
+                    .index
+                    .range(range)?
+                    .map(|entry| entry.map(|(entry, _)| entry[value_size..].to_vec()))
+                    .collect::<Result<Vec<_>>>()?;
+
+                for key in keys {
+                    let record = self.table.search_record(&key)?.ok_or_else(|| {
+                        Error::Corruption(format!("index {} points at a missing row", secondary.info.name))
+                    })?;
+                    visit(key, record);
+                }
+            }
+            Access::FullScan => {
+                for entry in self.table.scan()? {
+                    let (key, record) = entry?;
+                    visit(key, record);
+                }
+            }
+            Access::Empty => {}
+        }
+
+        Ok((plan, rows))
+    }
+}
+
+// Secondary index entries are the column value followed by the primary key, which keeps them
+// unique and ordered by value.
+fn secondary_key(schema: &Schema, column: usize, row: &[Value], key: &[u8]) -> Result<Vec<u8>> {
+    let mut entry = schema.encode_column(column, &row[column])?;
+    entry.extend_from_slice(key);
+    Ok(entry)
+}
+
+// Predicates compare their literal as written rather than coerced to the column's type, so that
+// `id > 2.5` on an INT column or a text longer than its column filter rows instead of failing.
+// Only literals that cannot be compared with the column at all are rejected.
+fn check_literal(value: &Value, ty: ColumnType) -> Result<()> {
+    match (value, ty) {
+        (Value::Int(_) | Value::Float(_), ColumnType::Int | ColumnType::Float)
+        | (Value::Bool(_), ColumnType::Bool)
+        | (Value::Text(_), ColumnType::Text(_)) => Ok(()),
+        (value, ty) => Err(Error::Sql(format!("{} is not a valid {}", value, ty))),
+    }
+}
+
+fn column_index(schema: &Schema, name: &str) -> Result<usize> {
+    schema
+        .column(name)
+        .ok_or_else(|| Error::Sql(format!("unknown column {}", name)))
+}
+
+// For each column of the table, where its value is in an INSERT row with the given columns.
+fn insert_order(schema: &Schema, columns: &[String]) -> Result<Vec<usize>> {
+    for name in columns {
+        column_index(schema, name)?;
+    }
+
+    schema
+        .columns
+        .iter()
+        .map(|column| {
+            let mut positions = columns.iter().enumerate().filter(|(_, name)| **name == column.name);
+            match (positions.next(), positions.next()) {
+                (Some((i, _)), None) => Ok(i),
+                (None, _) => Err(Error::Sql(format!("missing value for column {}", column.name))),
+                (Some(_), Some(_)) => Err(Error::Sql(format!("column {} is given twice", column.name))),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,420 @@
+use super::lexer::{Token, tokenize};
+use crate::error::{Error, Result};
+use crate::schema::{Column, ColumnType, Schema, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// `column op value`; BETWEEN is parsed into a `>=` and a `<=` predicate.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Predicate {
+    pub(crate) column: String,
+    pub(crate) op: Op,
+    pub(crate) value: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Function {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SelectItem {
+    Column(String),
+    // The column is `None` for `COUNT(*)`.
+    Aggregate(Function, Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Statement {
+    CreateTable {
+        name: String,
+        schema: Schema,
+    },
+    CreateIndex {
+        name: String,
+        table: String,
+        column: String,
+    },
+    Insert {
+        table: String,
+        columns: Option<Vec<String>>,
+        rows: Vec<Vec<Value>>,
+    },
+    Select {
+        table: String,
+        columns: Option<Vec<SelectItem>>,
+        filter: Vec<Predicate>,
+        group_by: Option<String>,
+    },
+    Update {
+        table: String,
+        assignments: Vec<(String, Value)>,
+        filter: Vec<Predicate>,
+    },
+    Delete {
+        table: String,
+        filter: Vec<Predicate>,
+    },
+    Explain(Box<Statement>),
+}
+
+pub(crate) fn parse(sql: &str) -> Result<Statement> {
+    let mut parser = Parser {
+        tokens: tokenize(sql)?,
+        pos: 0,
+    };
+
+    let statement = parser.statement()?;
+    parser.eat_symbol(";");
+    if let Some(token) = parser.peek() {
+        return Err(Error::Sql(format!("unexpected {:?} after the statement", token)));
+    }
+    Ok(statement)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| Error::Sql("unexpected end of statement".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if !self.eat_keyword(keyword) {
+            return Err(Error::Sql(format!("expected {}", keyword)));
+        }
+        Ok(())
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<()> {
+        if !self.eat_symbol(symbol) {
+            return Err(Error::Sql(format!("expected {}", symbol)));
+        }
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Word(word) => Ok(word),
+            token => Err(Error::Sql(format!("expected a name, found {:?}", token))),
+        }
+    }
+
+    // Parses `item (, item)*`.
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let mut items = vec![item(self)?];
+        while self.eat_symbol(",") {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn statement(&mut self) -> Result<Statement> {
+        if self.eat_keyword("EXPLAIN") {
+            Ok(Statement::Explain(Box::new(self.statement()?)))
+        } else if self.eat_keyword("CREATE") {
+            if self.eat_keyword("INDEX") {
+                self.create_index()
+            } else {
+                self.create_table()
+            }
+        } else if self.eat_keyword("INSERT") {
+            self.insert()
+        } else if self.eat_keyword("SELECT") {
+            self.select()
+        } else if self.eat_keyword("UPDATE") {
+            self.update()
+        } else if self.eat_keyword("DELETE") {
+            self.delete()
+        } else {
+            Err(Error::Sql(
+                "expected CREATE TABLE, CREATE INDEX, INSERT, SELECT, UPDATE, DELETE or EXPLAIN".to_string(),
+            ))
+        }
+    }
+
+    fn create_table(&mut self) -> Result<Statement> {
+        self.expect_keyword("TABLE")?;
+        let name = self.identifier()?;
+        self.expect_symbol("(")?;
+
+        let mut columns = Vec::new();
+        let mut key = None;
+        loop {
+            if self.eat_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                self.expect_symbol("(")?;
+                key = Some(self.identifier()?);
+                self.expect_symbol(")")?;
+            } else {
+                let column = Column {
+                    name: self.identifier()?,
+                    ty: self.column_type()?,
+                };
+                if self.eat_keyword("PRIMARY") {
+                    self.expect_keyword("KEY")?;
+                    key = Some(column.name.clone());
+                }
+                columns.push(column);
+            }
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(")")?;
+
+        if columns.is_empty() {
+            return Err(Error::Sql("a table needs at least one column".to_string()));
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i].iter().any(|other| other.name == column.name) {
+                return Err(Error::Sql(format!("column {} is defined twice", column.name)));
+            }
+        }
+
+        // Without a PRIMARY KEY the first column is the key.
+        let key = match key {
+            Some(key) => columns
+                .iter()
+                .position(|column| column.name == key)
+                .ok_or_else(|| Error::Sql(format!("primary key {} is not a column", key)))?,
+            None => 0,
+        };
+
+        Ok(Statement::CreateTable {
+            name,
+            schema: Schema { columns, key },
+        })
+    }
+
+    fn create_index(&mut self) -> Result<Statement> {
+        let name = self.identifier()?;
+        self.expect_keyword("ON")?;
+        let table = self.identifier()?;
+        self.expect_symbol("(")?;
+        let column = self.identifier()?;
+        self.expect_symbol(")")?;
+
+        Ok(Statement::CreateIndex { name, table, column })
+    }
+
+    fn column_type(&mut self) -> Result<ColumnType> {
+        let name = self.identifier()?.to_ascii_uppercase();
+        match name.as_str() {
+            "INT" | "INTEGER" | "BIGINT" => Ok(ColumnType::Int),
+            "FLOAT" | "REAL" | "DOUBLE" => Ok(ColumnType::Float),
+            "BOOL" | "BOOLEAN" => Ok(ColumnType::Bool),
+            "TEXT" | "CHAR" | "VARCHAR" => {
+                self.expect_symbol("(")?;
+                let len = match self.next()? {
+                    Token::Int(len) if (1..=u16::MAX as i64).contains(&len) => len as u16,
+                    _ => return Err(Error::Sql(format!("{} needs a length between 1 and {}", name, u16::MAX))),
+                };
+                self.expect_symbol(")")?;
+                Ok(ColumnType::Text(len))
+            }
+            _ => Err(Error::Sql(format!("unknown column type {}", name))),
+        }
+    }
+
+    fn insert(&mut self) -> Result<Statement> {
+        self.expect_keyword("INTO")?;
+        let table = self.identifier()?;
+
+        let columns = if self.eat_symbol("(") {
+            let columns = self.list(Self::identifier)?;
+            self.expect_symbol(")")?;
+            Some(columns)
+        } else {
+            None
+        };
+
+        self.expect_keyword("VALUES")?;
+        let rows = self.list(|parser| {
+            parser.expect_symbol("(")?;
+            let row = parser.list(Self::value)?;
+            parser.expect_symbol(")")?;
+            Ok(row)
+        })?;
+
+        Ok(Statement::Insert { table, columns, rows })
+    }
+
+    fn select(&mut self) -> Result<Statement> {
+        let columns = if self.eat_symbol("*") {
+            None
+        } else {
+            Some(self.list(Self::select_item)?)
+        };
+
+        self.expect_keyword("FROM")?;
+        let table = self.identifier()?;
+        let filter = self.filter()?;
+        let group_by = if self.eat_keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            Some(self.identifier()?)
+        } else {
+            None
+        };
+
+        Ok(Statement::Select {
+            table,
+            columns,
+            filter,
+            group_by,
+        })
+    }
+
+    // A column, or `function(column)`; only COUNT takes `*`.
+    fn select_item(&mut self) -> Result<SelectItem> {
+        let name = self.identifier()?;
+        if !self.eat_symbol("(") {
+            return Ok(SelectItem::Column(name));
+        }
+
+        let function = match name.to_ascii_uppercase().as_str() {
+            "COUNT" => Function::Count,
+            "SUM" => Function::Sum,
+            "AVG" => Function::Avg,
+            "MIN" => Function::Min,
+            "MAX" => Function::Max,
+            _ => return Err(Error::Sql(format!("unknown function {}", name))),
+        };
+        let column = if function == Function::Count && self.eat_symbol("*") {
+            None
+        } else {
+            Some(self.identifier()?)
+        };
+        self.expect_symbol(")")?;
+        Ok(SelectItem::Aggregate(function, column))
+    }
+
+    fn update(&mut self) -> Result<Statement> {
+        let table = self.identifier()?;
+        self.expect_keyword("SET")?;
+        let assignments = self.list(|parser| {
+            let column = parser.identifier()?;
+            parser.expect_symbol("=")?;
+            Ok((column, parser.value()?))
+        })?;
+        let filter = self.filter()?;
+
+        Ok(Statement::Update {
+            table,
+            assignments,
+            filter,
+        })
+    }
+
+    fn delete(&mut self) -> Result<Statement> {
+        self.expect_keyword("FROM")?;
+        let table = self.identifier()?;
+        let filter = self.filter()?;
+
+        Ok(Statement::Delete { table, filter })
+    }
+
+    // An optional `WHERE predicate (AND predicate)*`.
+    fn filter(&mut self) -> Result<Vec<Predicate>> {
+        let mut filter = Vec::new();
+        if !self.eat_keyword("WHERE") {
+            return Ok(filter);
+        }
+
+        loop {
+            let column = self.identifier()?;
+            if self.eat_keyword("BETWEEN") {
+                let low = self.value()?;
+                self.expect_keyword("AND")?;
+                let high = self.value()?;
+                filter.push(Predicate {
+                    column: column.clone(),
+                    op: Op::Ge,
+                    value: low,
+                });
+                filter.push(Predicate {
+                    column,
+                    op: Op::Le,
+                    value: high,
+                });
+            } else {
+                let op = match self.next()? {
+                    Token::Symbol("=") => Op::Eq,
+                    Token::Symbol("!=" | "<>") => Op::Ne,
+                    Token::Symbol("<") => Op::Lt,
+                    Token::Symbol("<=") => Op::Le,
+                    Token::Symbol(">") => Op::Gt,
+                    Token::Symbol(">=") => Op::Ge,
+                    token => return Err(Error::Sql(format!("expected a comparison, found {:?}", token))),
+                };
+                filter.push(Predicate {
+                    column,
+                    op,
+                    value: self.value()?,
+                });
+            }
+
+            if !self.eat_keyword("AND") {
+                return Ok(filter);
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        let negative = self.eat_symbol("-");
+        match self.next()? {
+            Token::Int(v) => Ok(Value::Int(if negative { -v } else { v })),
+            Token::Float(v) => Ok(Value::Float(if negative { -v } else { v })),
+            Token::Str(v) if !negative => Ok(Value::Text(v)),
+            Token::Word(word) if !negative && word.eq_ignore_ascii_case("TRUE") => Ok(Value::Bool(true)),
+            Token::Word(word) if !negative && word.eq_ignore_ascii_case("FALSE") => Ok(Value::Bool(false)),
+            token => Err(Error::Sql(format!("expected a value, found {:?}", token))),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use super::parser::{Op, Predicate};
+use crate::error::Result;
+use crate::schema::{ColumnType, Schema, Value};
+use std::ops::Bound;
+
+// How the rows a statement touches are found. Every predicate is still checked against each
+// row afterwards, so an access path only has to narrow the search down, never to be exact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Access {
+    Point(Vec<u8>),
+    Range(Bound<Vec<u8>>, Bound<Vec<u8>>),
+    // Range over the entries of secondary index `index`, whose keys are the column value
+    // followed by the primary key.
+    IndexScan {
+        index: usize,
+        lower: Bound<Vec<u8>>,
+        upper: Bound<Vec<u8>>,
+    },
+    FullScan,
+    // Some equality compares a column with a literal it cannot store, so no row matches.
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Plan {
+    pub(crate) access: Access,
+    pub(crate) description: String,
+    pub(crate) estimated_rows: u64,
+    pub(crate) estimated_reads: u64,
+}
+
+/// What the planner knows about a B-tree: its entry count and order.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stats {
+    pub(crate) rows: u64,
+    pub(crate) t: u32,
+}
+
+impl Stats {
+    // Nodes are assumed to be three quarters full.
+    fn keys_per_node(self) -> u64 {
+        ((2 * self.t as u64 - 1) * 3 / 4).max(1)
+    }
+
+    fn leaves(self) -> u64 {
+        self.rows.div_ceil(self.keys_per_node()).max(1)
+    }
+
+    fn height(self) -> u64 {
+        let mut level = self.leaves();
+        let mut height = 1;
+        while level > 1 {
+            level = level.div_ceil(self.keys_per_node() + 1);
+            height += 1;
+        }
+        height
+    }
+
+    fn nodes(self) -> u64 {
+        let mut level = self.leaves();
+        let mut nodes = level;
+        while level > 1 {
+            level = level.div_ceil(self.keys_per_node() + 1);
+            nodes += level;
+        }
+        nodes
+    }
+
+    // Reads to descend to the first match and walk the leaves holding `rows` entries.
+    fn range_reads(self, rows: u64) -> u64 {
+        self.height() + rows.div_ceil(self.keys_per_node()).saturating_sub(1)
+    }
+}
+
+pub(crate) struct SecondaryIndex<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) column: usize,
+    pub(crate) stats: Stats,
+}
+
+// Constraints the predicates put on one column, in encoded form.
+struct Bounds {
+    equal: Option<Vec<u8>>,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+}
+
+impl Bounds {
+    fn collect(schema: &Schema, column: usize, filter: &[Predicate]) -> Result<Option<Bounds>> {
+        let mut bounds = Bounds {
+            equal: None,
+            lower: Bound::Unbounded,
+            upper: Bound::Unbounded,
+        };
+
+        let name = &schema.columns[column].name;
+        for predicate in filter.iter().filter(|p| &p.column == name) {
+            let Some((op, value)) = stored_bound(schema.columns[column].ty, predicate.op, &predicate.value) else {
+                continue;
+            };
+            let value = schema.encode_column(column, &value)?;
+            match op {
+                Op::Eq => bounds.equal = Some(value),
+                Op::Gt => bounds.lower = tighter(bounds.lower, Bound::Excluded(value), true),
+                Op::Ge => bounds.lower = tighter(bounds.lower, Bound::Included(value), true),
+                Op::Lt => bounds.upper = tighter(bounds.upper, Bound::Excluded(value), false),
+                Op::Le => bounds.upper = tighter(bounds.upper, Bound::Included(value), false),
+                Op::Ne => {}
+            }
+        }
+
+        let usable = bounds.equal.is_some()
+            || bounds.lower != Bound::Unbounded
+            || bounds.upper != Bound::Unbounded;
+        Ok(usable.then_some(bounds))
+    }
+
+    // Default selectivities in the absence of value statistics: an equality on a non-unique
+    // column matches ten rows, a one-sided range a third of them, a two-sided one a quarter.
+    fn estimate_rows(&self, rows: u64, unique: bool) -> u64 {
+        let estimate = if self.equal.is_some() {
+            if unique { 1 } else { 10 }
+        } else if self.lower != Bound::Unbounded && self.upper != Bound::Unbounded {
+            rows / 4
+        } else {
+            rows / 3
+        };
+        estimate.clamp(1, rows.max(1))
+    }
+}
+
+/// Picks the access path with the fewest estimated node reads, preferring the primary key and
+/// then secondary indexes on ties.
+pub(crate) fn plan(
+    schema: &Schema,
+    filter: &[Predicate],
+    primary: Stats,
+    indexes: &[SecondaryIndex],
+) -> Result<Plan> {
+    if let Some(predicate) = filter.iter().find(|predicate| never_matches(schema, predicate)) {
+        return Ok(Plan {
+            access: Access::Empty,
+            description: format!("no rows: {} cannot hold {}", predicate.column, predicate.value),
+            estimated_rows: 0,
+            estimated_reads: 0,
+        });
+    }
+
+    let key_name = &schema.columns[schema.key].name;
+    let mut candidates = Vec::new();
+
+    if let Some(bounds) = Bounds::collect(schema, schema.key, filter)? {
+        let estimated_rows = bounds.estimate_rows(primary.rows, true);
+        match bounds.equal {
+            Some(key) => candidates.push(Plan {
+                access: Access::Point(key),
+                description: format!("point lookup on {}", key_name),
+                estimated_rows,
+                estimated_reads: primary.height(),
+            }),
+            None => candidates.push(Plan {
+                access: Access::Range(bounds.lower, bounds.upper),
+                description: format!("range scan on {}", key_name),
+                estimated_rows,
+                estimated_reads: primary.range_reads(estimated_rows),
+            }),
+        }
+    }
+
+    let key_size = schema.key_size() as usize;
+    for (i, index) in indexes.iter().enumerate() {
+        let Some(bounds) = Bounds::collect(schema, index.column, filter)? else {
+            continue;
+        };
+
+        let estimated_rows = bounds.estimate_rows(primary.rows, false);
+        let (lower, upper) = match bounds.equal {
+            Some(value) => (Bound::Included(value.clone()), Bound::Included(value)),
+            None => (bounds.lower, bounds.upper),
+        };
+        candidates.push(Plan {
+            access: Access::IndexScan {
+                index: i,
+                lower: composite_bound(lower, key_size, true),
+                upper: composite_bound(upper, key_size, false),
+            },
+            description: format!(
+                "index scan using {} on {}",
+                index.name, schema.columns[index.column].name
+            ),
+            estimated_rows,
+            estimated_reads: index.stats.range_reads(estimated_rows) + estimated_rows * primary.height(),
+        });
+    }
+
+    candidates.push(Plan {
+        access: Access::FullScan,
+        description: "full scan".to_string(),
+        estimated_rows: primary.rows,
+        estimated_reads: primary.nodes(),
+    });
+    Ok(candidates.into_iter().min_by_key(|plan| plan.estimated_reads).unwrap())
+}
+
+// `op value` as a bound on the values a column of type `ty` stores, if it narrows them. Literals
+// are compared as written, so a float against an INT column is rounded inwards (`> 2.5` becomes
+// `>= 3`), and a text too long for its column gives no bound and equals no row.
+fn stored_bound(ty: ColumnType, op: Op, value: &Value) -> Option<(Op, Value)> {
+    match (ty, value) {
+        (ColumnType::Int, Value::Float(v)) => match op {
+            Op::Gt | Op::Ge => Some((Op::Ge, Value::Int(v.ceil() as i64))),
+            Op::Lt | Op::Le => Some((Op::Le, Value::Int(v.floor() as i64))),
+            Op::Eq if v.fract() == 0.0 => Some((Op::Eq, Value::Int(*v as i64))),
+            Op::Eq | Op::Ne => None,
+        },
+        (ColumnType::Text(_), Value::Text(_)) if value.clone().coerce(ty).is_err() => None,
+        _ => Some((op, value.clone())),
+    }
+}
+
+fn never_matches(schema: &Schema, predicate: &Predicate) -> bool {
+    let Some(column) = schema.columns.iter().find(|column| column.name == predicate.column) else {
+        return false;
+    };
+    predicate.op == Op::Eq && stored_bound(column.ty, Op::Eq, &predicate.value).is_none()
+}
+
+// Turns a bound on a column value into one on (value, primary key) entries, so that it takes
+// in or leaves out every entry with that value.
+fn composite_bound(bound: Bound<Vec<u8>>, key_size: usize, is_lower: bool) -> Bound<Vec<u8>> {
+    let extend = |mut value: Vec<u8>, byte: u8| {
+        value.resize(value.len() + key_size, byte);
+        value
+    };
+
+    match (bound, is_lower) {
+        (Bound::Included(value), true) => Bound::Included(extend(value, 0x00)),
+        (Bound::Excluded(value), true) => Bound::Excluded(extend(value, 0xff)),
+        (Bound::Included(value), false) => Bound::Included(extend(value, 0xff)),
+        (Bound::Excluded(value), false) => Bound::Excluded(extend(value, 0x00)),
+        (Bound::Unbounded, _) => Bound::Unbounded,
+    }
+}
+
+// Picks the more restrictive of two lower (or upper) bounds.
+fn tighter(current: Bound<Vec<u8>>, new: Bound<Vec<u8>>, is_lower: bool) -> Bound<Vec<u8>> {
+    let key = |bound: &Bound<Vec<u8>>| match bound {
+        Bound::Included(key) | Bound::Excluded(key) => Some(key.clone()),
+        Bound::Unbounded => None,
+    };
+
+    match (key(&current), key(&new)) {
+        (None, _) => new,
+        (_, None) => current,
+        (Some(a), Some(b)) if a == b => {
+            if matches!(new, Bound::Excluded(_)) { new } else { current }
+        }
+        (Some(a), Some(b)) => {
+            if (b > a) == is_lower { new } else { current }
+        }
+    }
+}
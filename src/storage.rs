@@ -0,0 +1,210 @@
+use crate::mmap::MmapStorage;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    File,
+    Mmap,
+}
+
+impl Backend {
+    pub fn storage(self, file: File) -> io::Result<Box<dyn Storage>> {
+        Ok(match self {
+            Backend::File => Box::new(FileStorage::new(file)),
+            Backend::Mmap => Box::new(MmapStorage::new(file)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Leave everything to the OS.
+    None,
+    /// Push buffered writes to the OS at every commit.
+    #[default]
+    FlushOnCommit,
+    /// fdatasync at every commit.
+    SyncData,
+    /// fsync at every commit.
+    SyncAll,
+    /// fdatasync at most once per interval. Writes committed within the interval of the last sync
+    /// are held back and synced at the first call on the table, read or write, once the interval
+    /// has passed, or by `Table::sync` or dropping the table. Nothing runs in the background, so
+    /// a table left alone keeps them unsynced until one of those happens.
+    GroupCommit(Duration),
+}
+
+pub trait Storage: Send {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()>;
+
+    fn append(&mut self, buf: &[u8]) -> io::Result<u64> {
+        let offset = self.len()?;
+        self.write_at(offset, buf)?;
+        Ok(offset)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sync_data(&mut self) -> io::Result<()> {
+        self.sync()
+    }
+
+    fn sync(&mut self) -> io::Result<()>;
+
+    fn commit(&mut self, durability: Durability) -> io::Result<()> {
+        match durability {
+            Durability::None => Ok(()),
+            Durability::FlushOnCommit => self.flush(),
+            Durability::SyncData | Durability::GroupCommit(_) => self.sync_data(),
+            Durability::SyncAll => self.sync(),
+        }
+    }
+
+    fn len(&self) -> io::Result<u64>;
+
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+
+    // Backends that keep the bytes addressable (mmap) can hand them out without a copy.
+    fn mapped(&self, _offset: u64, _len: usize) -> Option<&[u8]> {
+        None
+    }
+}
+
+// Makes a rename into, or a file created in, the directory holding `path` durable. Syncing the
+// file itself does not cover its directory entry.
+#[cfg(unix)]
+pub(crate) fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+pub struct FileStorage {
+    file: File,
+}
+
+impl FileStorage {
+    pub fn new(file: File) -> Self {
+        FileStorage { file }
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Self::new(file))
+    }
+}
+
+impl Storage for FileStorage {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(buf)
+    }
+
+    fn append(&mut self, buf: &[u8]) -> io::Result<u64> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(buf)?;
+        Ok(offset)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn sync_data(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)
+    }
+}
+
+// Clones share the same bytes, so a table can be dropped and reopened over the same MemStorage.
+#[derive(Clone, Default)]
+pub struct MemStorage {
+    data: Arc<Mutex<Vec<u8>>>,
+}
+
+impl MemStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemStorage {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let data = self.data.lock().unwrap();
+        let start = offset as usize;
+        if start + buf.len() > data.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Read past end of storage"));
+        }
+        buf.copy_from_slice(&data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let mut data = self.data.lock().unwrap();
+        let start = offset as usize;
+        if start + buf.len() > data.len() {
+            data.resize(start + buf.len(), 0);
+        }
+        data[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.data.lock().unwrap().len() as u64)
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.data.lock().unwrap().resize(len as usize, 0);
+        Ok(())
+    }
+}
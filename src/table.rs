@@ -1,175 +1,2217 @@
-use crate::btree::Index;
-use std::path::Path;
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use crate::bloom::{BloomFilter, FilterStats};
+use crate::btree::{Index, Range, DEFAULT_PAGE_SIZE};
+use crate::compression::Compression;
+use crate::encryption::{Cipher, EncryptionKey, SEAL_SIZE};
+use crate::error::{Error, Result};
+use crate::hash::HashIndex;
+use crate::lsm::{Files, LsmScan, LsmTable};
+use crate::storage::{Backend, Durability, FileStorage, MemStorage, Storage};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
 
+const MAGIC: &[u8; 4] = b"RDBD";
+const VERSION: u16 = 2;
+
+// Records start right after the header, so entry offsets in the index stay absolute.
+const HEADER_SIZE: u64 = 32;
+const COUNTS_OFFSET: u64 = 14;
+// Compressed tables follow the header with the end of their entries at the last checkpoint.
+const END_OFFSET: u64 = HEADER_SIZE;
+
+// Bits of the header's flags byte.
+const ORDER_STATISTICS: u8 = 1;
+const ENCRYPTED: u8 = 2;
+const HASH_INDEX: u8 = 4;
+
+// Every entry starts with a flag byte; deleting a record only flips it.
+const LIVE: u8 = 0;
+const DELETED: u8 = 1;
+
+const DEFAULT_MEMTABLE_SIZE: usize = 1 << 20;
+
+/// A table of fixed-size records under fixed-size keys, stored by the engine it was created
+/// with.
 pub struct Table {
+    engine: TableEngine,
+}
+
+// The table behind a `Table`, of either engine. Everything is forwarded to it; what an engine
+// cannot do fails with `Error::Unsupported`.
+enum TableEngine {
+    Indexed(Box<IndexedTable>),
+    Lsm(LsmTable),
+}
+
+// The indexed engine: records appended to a datafile in arrival order and found through an
+// index that is updated in place.
+struct IndexedTable {
+    keysize: u16,
+    recordsize: u16,
+    compression: Compression,
+    cipher: Option<Cipher>,
+    records: u64,
+    deleted: u64,
+    // Where the next entry is appended.
+    end: u64,
+    synced_counts: (u64, u64),
+    synced_end: u64,
+    datafile: Box<dyn Storage>,
+    index: TableIndex,
+    filter: Option<BloomFilter>,
+    filterfile: Box<dyn Storage>,
+    durability: Durability,
+    last_commit: Instant,
+    // Writes whose sync group commit held back.
+    held_back: bool,
+}
+
+/// Options for opening a table, in the style of `std::fs::OpenOptions`.
+#[derive(Debug, Clone)]
+pub struct TableOptions {
+    keysize: u16,
+    recordsize: u16,
+    page_size: u32,
+    order_statistics: bool,
+    index_kind: IndexKind,
+    compression: Compression,
+    encryption_key: Option<EncryptionKey>,
+    bloom_filter: Option<u32>,
+    engine: Engine,
+    memtable_size: usize,
+    backend: Backend,
+    durability: Durability,
+    index_path: Option<PathBuf>,
+}
+
+/// Sizes and counters reported by `Table::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableStats {
     pub keysize: u16,
     pub recordsize: u16,
-    pub datafile: File,
-    pub index: Index,
+    pub engine: Engine,
+    pub index_kind: IndexKind,
+    pub page_size: u32,
+    /// The order of a B-tree index, or 0 for a hash index.
+    pub t: u32,
+    pub records: u64,
+    pub deleted: u64,
+    pub compression: Compression,
+    pub encrypted: bool,
+    pub datafile_bytes: u64,
+    pub index_pages: u64,
+    pub filter: Option<FilterStats>,
+    /// Runs per level of an LSM table, from level 0 down; empty for an indexed table.
+    pub runs: Vec<usize>,
 }
 
-impl Table {
-    pub fn create(path: &str, recordsize: u16, keysize: u16) -> io::Result<Self> {
-        let indexfile = format!("{}.ndx", path);
-
-        let entry_size = (keysize as u32) + 8 + (2 * (keysize as u32 + 8 + 8));
-
-        //TODO recheck benchmarks because from my results 4 seems to be better everytime
-        //But almost every major source I could find online uses delimeters similar to these
-        let t = if entry_size <= 128 {
-            32
-        } else if entry_size <= 256 {
-            16
-        } else if entry_size <= 512 {
-            8
-        } else {
-            4
+/// How a table stores its records. Like the sizes, this is fixed when the table is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Records are appended to a datafile and found through an index updated in place.
+    #[default]
+    Indexed,
+    /// A log-structured merge tree: writes go to a write-ahead log and an in-memory memtable,
+    /// which is flushed to sorted immutable runs that are compacted level by level. Writes never
+    /// touch existing files in place, at the cost of lookups that may read one block per level.
+    Lsm,
+}
+
+/// Which index a table keeps its keys in. Like the sizes, this is fixed when the table is
+/// created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexKind {
+    /// A B-tree, which keeps keys in order for scans, ranks and min/max.
+    #[default]
+    BTree,
+    /// An extendible hash index: a lookup reads a single bucket, but keys have no order, so
+    /// scans and everything else that needs it fail with `Error::Unsupported`.
+    Hash,
+}
+
+// The index of a table, of either kind. Point operations go to whichever it is; the ones that
+// need key order take the B-tree from `btree`.
+enum TableIndex {
+    BTree(Index),
+    Hash(HashIndex),
+}
+
+/// What a conditional write did, returned by `Table::upsert`, `Table::insert_if_absent` and
+/// `Table::compare_and_swap`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteOutcome {
+    /// The key was new and the record was added.
+    Inserted,
+    /// The record stored under the key was overwritten.
+    Replaced,
+    /// Nothing was written; holds the record stored under the key.
+    Kept(Vec<u8>),
+    /// `compare_and_swap` found no record under the key.
+    Missing,
+}
+
+/// Writes collected for `Table::write_batch`. Writes to the same key apply in the order they
+/// were added.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    pub(crate) writes: Vec<(Vec<u8>, BatchWrite)>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum BatchWrite {
+    Put(Vec<u8>),
+    Update(Vec<u8>),
+    Delete,
+}
+
+// Where a key of the batch stands: `offset` is its entry before the batch, `present` whether it
+// exists after the writes seen so far, and `record` the record to store, if it changed.
+struct BatchChange {
+    offset: Option<u64>,
+    present: bool,
+    record: Option<Vec<u8>>,
+}
+
+// What a batch does to the index entry of a key: a new entry, one moved to a new offset, or
+// one deleted.
+enum IndexChange {
+    Insert(u64),
+    Move(u64),
+    Delete,
+}
+
+// Datafile header: magic (4) + version (2) + keysize (2) + recordsize (2) + page_size (4)
+// + entry count (8) + deleted count (8) + flags (1) + compression codec (1). Compressed tables
+// add the end of their entries (8), and encrypted ones then a key check (SEAL_SIZE) sealing
+// the sizes, flags and codec. The counts and the end are only rewritten at checkpoints, so
+// entries appended after the last one are still found past them.
+struct Header {
+    keysize: u16,
+    recordsize: u16,
+    page_size: u32,
+    records: u64,
+    deleted: u64,
+    order_statistics: bool,
+    index_kind: IndexKind,
+    compression: Compression,
+    end: u64,
+    encrypted: bool,
+    key_check: Vec<u8>,
+}
+
+// Where and how entries sit in the datafile. Every entry starts with its flag (1), followed by
+// its body. Uncompressed tables have fixed-size bodies: key + record. Compressed ones give each
+// record a slot sized to its compressed form when it was written: key + slot size (2) + stored
+// length (2) + codec flag (1) + slot. A record that does not get smaller is stored as it is.
+// Encrypted tables seal the body with the entry's offset as associated data, and keep a copy of
+// the slot size in front of it.
+#[derive(Clone)]
+struct Layout {
+    keysize: u16,
+    recordsize: u16,
+    compression: Compression,
+    cipher: Option<Cipher>,
+}
+
+impl Header {
+    fn read(storage: &mut dyn Storage) -> Result<Self> {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        storage.read_at(0, &mut header)?;
+
+        if &header[0..4] != MAGIC {
+            return Err(Error::Corruption("not a table datafile".to_string()));
+        }
+        let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::VersionMismatch {
+                found: version,
+                expected: VERSION,
+            });
+        }
+
+        let mut header = Header {
+            keysize: u16::from_le_bytes(header[6..8].try_into().unwrap()),
+            recordsize: u16::from_le_bytes(header[8..10].try_into().unwrap()),
+            page_size: u32::from_le_bytes(header[10..14].try_into().unwrap()),
+            records: u64::from_le_bytes(header[14..22].try_into().unwrap()),
+            deleted: u64::from_le_bytes(header[22..30].try_into().unwrap()),
+            order_statistics: header[30] & ORDER_STATISTICS != 0,
+            index_kind: if header[30] & HASH_INDEX != 0 { IndexKind::Hash } else { IndexKind::BTree },
+            compression: Compression::from_id(header[31])?,
+            end: HEADER_SIZE,
+            encrypted: header[30] & ENCRYPTED != 0,
+            key_check: Vec::new(),
         };
+        if header.keysize == 0 || header.recordsize == 0 {
+            return Err(Error::Corruption("datafile header has a zero key or record size".to_string()));
+        }
+        if header.compression != Compression::None {
+            let mut end = [0u8; 8];
+            storage.read_at(END_OFFSET, &mut end)?;
+            header.end = u64::from_le_bytes(end);
+        }
+        if header.encrypted {
+            header.key_check = vec![0u8; SEAL_SIZE];
+            storage.read_at(Self::key_check_offset(header.compression), &mut header.key_check)?;
+        }
+        Ok(header)
+    }
 
-        Self::open_table(path, recordsize, keysize, &indexfile, t)
+    fn key_check_offset(compression: Compression) -> u64 {
+        if compression != Compression::None { END_OFFSET + 8 } else { HEADER_SIZE }
     }
 
-    pub fn create_benchmark(path: &str, recordsize: u16, keysize: u16, indexfile: &str, t: u32) -> io::Result<Self> {
-        Self::open_table(path, recordsize, keysize, indexfile, t)
+    // How far the header of a datafile reaches, or None if not even its fixed part is there.
+    fn len(storage: &mut dyn Storage) -> Result<Option<u64>> {
+        if storage.len()? < HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut header = [0u8; HEADER_SIZE as usize];
+        storage.read_at(0, &mut header)?;
+        // Anything else wrong with the header is for `read` to report.
+        let compression = Compression::from_id(header[31]).unwrap_or_default();
+        let check = if header[30] & ENCRYPTED != 0 { SEAL_SIZE as u64 } else { 0 };
+        Ok(Some(Self::key_check_offset(compression) + check))
     }
 
-    fn open_table(datafile_path: &str, recordsize: u16, keysize: u16, indexfile: &str, t: u32) -> io::Result<Self> {
-        let datafile = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(datafile_path)?;
+    // The fields the key check seals: everything but the counts.
+    fn sealed_fields(header: &[u8]) -> Vec<u8> {
+        [&header[0..14], &header[30..32]].concat()
+    }
 
-        let index = if Path::new(indexfile).exists() {
-            Index::open(indexfile)?
-        } else {
-            let mut idx = Index::create(indexfile, t, keysize)?;
-            Self::create_index(datafile_path, keysize, recordsize, &mut idx)?;
-            idx
+    fn write(&self, storage: &mut dyn Storage) -> Result<()> {
+        let mut header = self.fixed();
+        if self.compression != Compression::None {
+            header.extend_from_slice(&self.end.to_le_bytes());
+        }
+        if self.encrypted {
+            header.extend_from_slice(&self.key_check);
+        }
+        storage.write_at(0, &header)?;
+        Ok(())
+    }
+
+    // The fixed part of the header, without any extension.
+    fn fixed(&self) -> Vec<u8> {
+        let mut header = vec![0u8; HEADER_SIZE as usize];
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6..8].copy_from_slice(&self.keysize.to_le_bytes());
+        header[8..10].copy_from_slice(&self.recordsize.to_le_bytes());
+        header[10..14].copy_from_slice(&self.page_size.to_le_bytes());
+        header[14..22].copy_from_slice(&self.records.to_le_bytes());
+        header[22..30].copy_from_slice(&self.deleted.to_le_bytes());
+        header[30] = Self::flags(self.order_statistics, self.index_kind, self.encrypted);
+        header[31] = self.compression.id();
+        header
+    }
+
+    fn flags(order_statistics: bool, index_kind: IndexKind, encrypted: bool) -> u8 {
+        (if order_statistics { ORDER_STATISTICS } else { 0 })
+            | if index_kind == IndexKind::Hash { HASH_INDEX } else { 0 }
+            | if encrypted { ENCRYPTED } else { 0 }
+    }
+
+    // The cipher for `key`, once the key is known to be the one the table was created with.
+    fn cipher(&self, key: Option<&EncryptionKey>) -> Result<Option<Cipher>> {
+        match (self.encrypted, key) {
+            (true, None) => Err(Error::InvalidOptions("the table is encrypted; a key is required".to_string())),
+            (false, Some(_)) => Err(Error::InvalidOptions("the table is not encrypted".to_string())),
+            (false, None) => Ok(None),
+            (true, Some(key)) => {
+                let cipher = Cipher::new(key);
+                cipher.verify_key(&self.key_check, &Self::sealed_fields(&self.fixed()))?;
+                Ok(Some(cipher))
+            }
+        }
+    }
+
+    fn layout(&self, cipher: Option<Cipher>) -> Layout {
+        Layout {
+            keysize: self.keysize,
+            recordsize: self.recordsize,
+            compression: self.compression,
+            cipher,
+        }
+    }
+}
+
+impl Layout {
+    fn compressed(&self) -> bool {
+        self.compression != Compression::None
+    }
+
+    fn data_start(&self) -> u64 {
+        let check = if self.cipher.is_some() { SEAL_SIZE as u64 } else { 0 };
+        Header::key_check_offset(self.compression) + check
+    }
+
+    // Where the slot size of a compressed entry is kept.
+    fn slot_at(&self) -> u64 {
+        if self.cipher.is_some() { 1 } else { 1 + self.keysize as u64 }
+    }
+
+    // Entry length for a slot of `slot` bytes; uncompressed entries have no slot.
+    fn stored_len(&self, slot: usize) -> u64 {
+        let mut body = self.keysize as usize + if self.compressed() { 5 + slot } else { self.recordsize as usize };
+        if self.cipher.is_some() {
+            body += SEAL_SIZE + if self.compressed() { 2 } else { 0 };
+        }
+        1 + body as u64
+    }
+
+    // Bytes needed to tell how long an entry is.
+    fn min_len(&self) -> u64 {
+        if self.compressed() { self.slot_at() + 2 } else { self.stored_len(0) }
+    }
+
+    // A live entry at `offset`, holding `key` and `record`, which may be shorter than the sizes.
+    fn entry(&self, offset: u64, key: &[u8], record: &[u8]) -> Vec<u8> {
+        let body = self.body(key, record, None).expect("a record fits in a slot of its own size");
+        let mut entry = vec![LIVE];
+        entry.extend_from_slice(&self.seal(offset, body));
+        entry
+    }
+
+    // The unsealed body of an entry. A compressed record goes in a slot of `slot` bytes, or of
+    // its own size if not given; None if it does not fit.
+    fn body(&self, key: &[u8], record: &[u8], slot: Option<usize>) -> Option<Vec<u8>> {
+        let keysize = self.keysize as usize;
+        let mut body = vec![0u8; keysize];
+        body[..key.len()].copy_from_slice(key);
+
+        if !self.compressed() {
+            body.resize(keysize + self.recordsize as usize, 0);
+            body[keysize..keysize + record.len()].copy_from_slice(record);
+            return Some(body);
+        }
+        let (packed, payload) = self.pack(record);
+        let slot = slot.unwrap_or(payload.len());
+        if payload.len() > slot {
+            return None;
+        }
+        body.extend_from_slice(&(slot as u16).to_le_bytes());
+        body.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        body.push(packed as u8);
+        body.extend_from_slice(&payload);
+        body.resize(keysize + 5 + slot, 0);
+        Some(body)
+    }
+
+    // The body as stored at `offset`, after the flag.
+    fn seal(&self, offset: u64, body: Vec<u8>) -> Vec<u8> {
+        let Some(cipher) = &self.cipher else {
+            return body;
         };
+        let keysize = self.keysize as usize;
+        let mut sealed = Vec::with_capacity(2 + SEAL_SIZE + body.len());
+        if self.compressed() {
+            sealed.extend_from_slice(&body[keysize..keysize + 2]);
+        }
+        sealed.extend_from_slice(&cipher.seal(&body, &offset.to_le_bytes()));
+        sealed
+    }
 
-        Ok(Self {
-            keysize,
-            recordsize,
-            datafile,
-            index,
+    // The record without its zero padding, compressed if that makes it smaller.
+    fn pack(&self, record: &[u8]) -> (bool, Vec<u8>) {
+        let len = record.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        let packed = self.compression.compress(&record[..len]);
+        if packed.len() < len { (true, packed) } else { (false, record[..len].to_vec()) }
+    }
+
+    fn slot(&self, storage: &mut dyn Storage, offset: u64) -> Result<usize> {
+        let mut slot = [0u8; 2];
+        storage.read_at(offset + self.slot_at(), &mut slot)?;
+        Ok(u16::from_le_bytes(slot) as usize)
+    }
+
+    fn entry_len(&self, storage: &mut dyn Storage, offset: u64) -> Result<u64> {
+        let slot = if self.compressed() { self.slot(storage, offset)? } else { 0 };
+        Ok(self.stored_len(slot))
+    }
+
+    // The flag and unsealed body of the entry at `offset`.
+    fn read_entry(&self, storage: &mut dyn Storage, offset: u64) -> Result<(u8, Vec<u8>)> {
+        let mut entry = vec![0u8; self.entry_len(storage, offset)? as usize];
+        storage.read_at(offset, &mut entry)?;
+        let body = match &self.cipher {
+            None => entry.split_off(1),
+            Some(cipher) => {
+                let sealed = if self.compressed() { &entry[3..] } else { &entry[1..] };
+                cipher.open(sealed, &offset.to_le_bytes()).map_err(|_| {
+                    Error::Corruption(format!("entry at offset {} fails authentication", offset))
+                })?
+            }
+        };
+        Ok((entry[0], body))
+    }
+
+    // The flag and key of the entry at `offset`. Only live entries of an encrypted table are
+    // opened for their key; the key of any other is left empty.
+    fn read_head(&self, storage: &mut dyn Storage, offset: u64) -> Result<(u8, Vec<u8>)> {
+        let keysize = self.keysize as usize;
+        if self.cipher.is_none() {
+            let mut head = vec![0u8; 1 + keysize];
+            storage.read_at(offset, &mut head)?;
+            return Ok((head[0], head.split_off(1)));
+        }
+
+        let mut flag = [0u8; 1];
+        storage.read_at(offset, &mut flag)?;
+        if flag[0] != LIVE {
+            return Ok((flag[0], Vec::new()));
+        }
+        let (flag, mut body) = self.read_entry(storage, offset)?;
+        body.truncate(keysize);
+        Ok((flag, body))
+    }
+
+    fn read_record(&self, storage: &mut dyn Storage, offset: u64) -> Result<Vec<u8>> {
+        let (_, body) = self.read_entry(storage, offset)?;
+        let keysize = self.keysize as usize;
+        let recordsize = self.recordsize as usize;
+        if !self.compressed() {
+            return Ok(body[keysize..].to_vec());
+        }
+
+        let slot = u16::from_le_bytes(body[keysize..keysize + 2].try_into().unwrap()) as usize;
+        let len = u16::from_le_bytes(body[keysize + 2..keysize + 4].try_into().unwrap()) as usize;
+        if len > slot || len > recordsize {
+            return Err(Error::Corruption(format!("entry at offset {} overflows its slot", offset)));
+        }
+        let payload = &body[keysize + 5..keysize + 5 + len];
+
+        let mut record =
+            if body[keysize + 4] != 0 { self.compression.decompress(payload, recordsize)? } else { payload.to_vec() };
+        record.resize(recordsize, 0);
+        Ok(record)
+    }
+
+    // Overwrites the record of `key`, whose entry is at `offset`. Returns false without writing
+    // anything if the record no longer fits in a compressed entry's slot, and always for an
+    // encrypted entry: a torn rewrite would leave one that fails authentication, key and all.
+    fn write_record(&self, storage: &mut dyn Storage, offset: u64, key: &[u8], record: &[u8]) -> Result<bool> {
+        let Some(body) = self.rewrite(storage, offset, key, record)? else {
+            return Ok(false);
+        };
+        storage.write_at(offset + 1, &body)?;
+        Ok(true)
+    }
+
+    // The bytes `write_record` would write after the flag of the entry at `offset`, if it can
+    // rewrite the entry in place.
+    fn rewrite(&self, storage: &mut dyn Storage, offset: u64, key: &[u8], record: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.cipher.is_some() {
+            return Ok(None);
+        }
+        let slot = if self.compressed() { Some(self.slot(storage, offset)?) } else { None };
+        Ok(self.body(key, record, slot).map(|body| self.seal(offset, body)))
+    }
+}
+
+impl fmt::Display for IndexKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexKind::BTree => write!(f, "btree"),
+            IndexKind::Hash => write!(f, "hash"),
+        }
+    }
+}
+
+impl FromStr for IndexKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "btree" => Ok(IndexKind::BTree),
+            "hash" => Ok(IndexKind::Hash),
+            _ => Err(Error::InvalidOptions(format!("unknown index kind {:?}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Indexed => write!(f, "indexed"),
+            Engine::Lsm => write!(f, "lsm"),
+        }
+    }
+}
+
+impl FromStr for Engine {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "indexed" => Ok(Engine::Indexed),
+            "lsm" => Ok(Engine::Lsm),
+            _ => Err(Error::InvalidOptions(format!("unknown engine {:?}", s))),
+        }
+    }
+}
+
+impl TableIndex {
+    fn clean_len(kind: IndexKind, storage: &mut dyn Storage) -> Result<Option<u64>> {
+        match kind {
+            IndexKind::BTree => Index::clean_len(storage),
+            IndexKind::Hash => HashIndex::clean_len(storage),
+        }
+    }
+
+    fn open(kind: IndexKind, storage: Box<dyn Storage>, cipher: Option<Cipher>) -> Result<Self> {
+        Ok(match kind {
+            IndexKind::BTree => TableIndex::BTree(Index::open_sealed(storage, cipher)?),
+            IndexKind::Hash => TableIndex::Hash(HashIndex::open_sealed(storage, cipher)?),
         })
     }
 
+    fn create(
+        kind: IndexKind,
+        storage: Box<dyn Storage>,
+        keysize: u16,
+        page_size: u32,
+        counted: bool,
+        cipher: Option<Cipher>,
+    ) -> Result<Self> {
+        Ok(match kind {
+            IndexKind::BTree => TableIndex::BTree(Index::create_sealed(storage, keysize, page_size, counted, cipher)?),
+            IndexKind::Hash => TableIndex::Hash(HashIndex::create_sealed(storage, keysize, page_size, cipher)?),
+        })
+    }
 
-    fn create_index(path: &str, keysize: u16, recordsize: u16, index: &mut Index) -> io::Result<()> {
-        let mut file = File::open(path)?;
-        let entry_size = keysize as u64 + recordsize as u64;
+    fn kind(&self) -> IndexKind {
+        match self {
+            TableIndex::BTree(_) => IndexKind::BTree,
+            TableIndex::Hash(_) => IndexKind::Hash,
+        }
+    }
 
-        let mut offset = 0u64;
-        let mut key_buf = vec![0u8; keysize as usize];
+    fn btree(&mut self) -> Result<&mut Index> {
+        match self {
+            TableIndex::BTree(index) => Ok(index),
+            TableIndex::Hash(_) => Err(Error::Unsupported("a hash index keeps no key order".to_string())),
+        }
+    }
 
-        loop {
-            file.seek(SeekFrom::Start(offset))?;
+    fn keysize(&self) -> u16 {
+        match self {
+            TableIndex::BTree(index) => index.keysize(),
+            TableIndex::Hash(index) => index.keysize(),
+        }
+    }
 
-            match file.read_exact(&mut key_buf) {
-                Ok(_) => {
-                    index.insert(key_buf.clone(), offset)?;
-                    offset += entry_size;
-                }
-                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
-                    break;
-                }
-                Err(err) => {
-                    return Err(err);
-                }
-            }
+    fn page_size(&self) -> u32 {
+        match self {
+            TableIndex::BTree(index) => index.page_size(),
+            TableIndex::Hash(index) => index.page_size(),
         }
+    }
 
-        Ok(())
+    fn page_count(&self) -> u64 {
+        match self {
+            TableIndex::BTree(index) => index.page_count(),
+            TableIndex::Hash(index) => index.page_count(),
+        }
     }
 
-    pub fn add_record(&mut self, key: &[u8], record: &[u8]) -> io::Result<()> {
-        if key.len() > self.keysize as usize || record.len() > self.recordsize as usize {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Key or record too large"));
+    fn t(&self) -> u32 {
+        match self {
+            TableIndex::BTree(index) => index.t(),
+            TableIndex::Hash(_) => 0,
         }
+    }
 
-        if let Some(_) = self.index.search(key)? {
-            println!("Warning: Key already exists. Use update_record instead.");
-            return Ok(());
+    fn len(&self) -> u64 {
+        match self {
+            TableIndex::BTree(index) => index.len(),
+            TableIndex::Hash(index) => index.len(),
+        }
+    }
+
+    fn is_counted(&self) -> bool {
+        match self {
+            TableIndex::BTree(index) => index.is_counted(),
+            TableIndex::Hash(_) => false,
         }
+    }
 
-        let offset = self.datafile.seek(SeekFrom::End(0))?;
+    fn sync(&mut self) -> Result<()> {
+        match self {
+            TableIndex::BTree(index) => index.sync(),
+            TableIndex::Hash(index) => index.sync(),
+        }
+    }
 
-        let mut fixed_key = vec![0u8; self.keysize as usize];
-        fixed_key[..key.len()].copy_from_slice(key);
-        self.datafile.write_all(&fixed_key)?;
+    fn commit(&mut self, durability: Durability) -> Result<()> {
+        match self {
+            TableIndex::BTree(index) => index.commit(durability),
+            TableIndex::Hash(index) => index.commit(durability),
+        }
+    }
 
-        let mut fixed_record = vec![0u8; self.recordsize as usize];
-        fixed_record[..record.len()].copy_from_slice(record);
-        self.datafile.write_all(&fixed_record)?;
+    fn verify(&mut self) -> Result<()> {
+        match self {
+            TableIndex::BTree(index) => index.verify(),
+            TableIndex::Hash(index) => index.verify(),
+        }
+    }
 
-        self.datafile.flush()?;
+    fn search(&mut self, key: &[u8]) -> Result<Option<u64>> {
+        match self {
+            TableIndex::BTree(index) => index.search(key),
+            TableIndex::Hash(index) => index.search(key),
+        }
+    }
 
-        self.index.insert(fixed_key, offset)?;
+    fn search_sorted(&mut self, keys: &[Vec<u8>]) -> Result<Vec<Option<u64>>> {
+        match self {
+            TableIndex::BTree(index) => index.search_sorted(keys),
+            TableIndex::Hash(index) => index.search_many(keys),
+        }
+    }
 
-        Ok(())
+    fn insert(&mut self, key: Vec<u8>, value: u64) -> Result<()> {
+        match self {
+            TableIndex::BTree(index) => index.insert(key, value),
+            TableIndex::Hash(index) => index.insert(key, value),
+        }
     }
 
-    pub fn update_record(&mut self, key: &[u8], new_record: &[u8]) -> io::Result<()> {
-        if key.len() > self.keysize as usize || new_record.len() > self.recordsize as usize {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Key or record too large"));
+    fn insert_if_absent(&mut self, key: Vec<u8>, value: u64) -> Result<Option<u64>> {
+        match self {
+            TableIndex::BTree(index) => index.insert_if_absent(key, value),
+            TableIndex::Hash(index) => index.insert_if_absent(key, value),
         }
+    }
 
-        if let Some(offset) = self.index.search(key)? {
-            self.datafile.seek(SeekFrom::Start(offset))?;
+    fn update(&mut self, key: &[u8], value: u64) -> Result<Option<u64>> {
+        match self {
+            TableIndex::BTree(index) => index.update(key, value),
+            TableIndex::Hash(index) => index.update(key, value),
+        }
+    }
 
-            self.datafile.seek(SeekFrom::Current(self.keysize as i64))?;
+    fn delete(&mut self, key: &[u8]) -> Result<Option<u64>> {
+        match self {
+            TableIndex::BTree(index) => index.delete(key),
+            TableIndex::Hash(index) => index.delete(key),
+        }
+    }
 
-            let mut fixed_record = vec![0u8; self.recordsize as usize];
-            fixed_record[..new_record.len()].copy_from_slice(new_record);
-            self.datafile.write_all(&fixed_record)?;
-            self.datafile.flush()?;
+    fn take_storage(&mut self) -> Box<dyn Storage> {
+        match self {
+            TableIndex::BTree(index) => index.take_storage(),
+            TableIndex::Hash(index) => index.take_storage(),
+        }
+    }
 
-            println!("Record for key updated successfully.");
-            Ok(())
-        } else {
-            println!("Warning: Key not found. Cannot update non-existing record.");
-            Ok(())
+    // In key order for a B-tree, in bucket order for a hash index.
+    fn traverse<F>(&mut self, visit: F) -> Result<()>
+    where
+        F: FnMut(&[u8], u64) -> Result<()>,
+    {
+        match self {
+            TableIndex::BTree(index) => index.traverse_inorder(visit),
+            TableIndex::Hash(index) => index.traverse(visit),
         }
     }
+}
 
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    pub fn search_record(&mut self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
-        if let Some(offset) = self.index.search(key)? {
-            self.datafile.seek(SeekFrom::Start(offset))?;
+    /// Adds the record, or replaces the one stored under `key`.
+    pub fn put(&mut self, key: &[u8], record: &[u8]) -> &mut Self {
+        self.writes.push((key.to_vec(), BatchWrite::Put(record.to_vec())));
+        self
+    }
 
-            let mut key_buf = vec![0u8; self.keysize as usize];
-            self.datafile.read_exact(&mut key_buf)?;
+    /// Replaces the record stored under `key`, which has to exist.
+    pub fn update(&mut self, key: &[u8], record: &[u8]) -> &mut Self {
+        self.writes.push((key.to_vec(), BatchWrite::Update(record.to_vec())));
+        self
+    }
 
-            let mut record_buf = vec![0u8; self.recordsize as usize];
-            self.datafile.read_exact(&mut record_buf)?;
+    /// Removes the record stored under `key`, which has to exist.
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        self.writes.push((key.to_vec(), BatchWrite::Delete));
+        self
+    }
 
-            Ok(Some(record_buf))
-        } else {
-            Ok(None)
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        TableOptions {
+            keysize: 0,
+            recordsize: 0,
+            page_size: DEFAULT_PAGE_SIZE,
+            order_statistics: false,
+            index_kind: IndexKind::BTree,
+            compression: Compression::None,
+            encryption_key: None,
+            bloom_filter: None,
+            engine: Engine::Indexed,
+            memtable_size: DEFAULT_MEMTABLE_SIZE,
+            backend: Backend::File,
+            durability: Durability::default(),
+            index_path: None,
         }
     }
+}
+
+impl TableOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key_size(&mut self, keysize: u16) -> &mut Self {
+        self.keysize = keysize;
+        self
+    }
 
-    pub fn list_records(&mut self) -> io::Result<()> {
-        self.index.traverse_inorder(|key, offset| {
-            self.datafile.seek(SeekFrom::Start(offset))?;
+    pub fn record_size(&mut self, recordsize: u16) -> &mut Self {
+        self.recordsize = recordsize;
+        self
+    }
 
-            let mut key_buf = vec![0u8; self.keysize as usize];
-            self.datafile.read_exact(&mut key_buf)?;
+    // Sizes only matter when the table is created; an existing datafile brings its own, and
+    // a key or record size that contradicts them is rejected.
+    pub fn page_size(&mut self, page_size: u32) -> &mut Self {
+        self.page_size = page_size;
+        self
+    }
 
-            let mut record_buf = vec![0u8; self.recordsize as usize];
-            self.datafile.read_exact(&mut record_buf)?;
+    /// Keeps subtree key counts in the index so `Table::rank` and `Table::record_at` take
+    /// O(log n). Like the sizes, this is fixed when the table is created.
+    pub fn order_statistics(&mut self, order_statistics: bool) -> &mut Self {
+        self.order_statistics = order_statistics;
+        self
+    }
 
-            let key_str = String::from_utf8_lossy(key).trim_end_matches(char::from(0)).to_string();
-            let record_str = String::from_utf8_lossy(&record_buf).trim_end_matches(char::from(0)).to_string();
+    /// Keeps the keys in a hash index instead of a B-tree, for tables that are only ever
+    /// accessed by exact key. Fixed when the table is created, and the datafile remembers it.
+    pub fn index_kind(&mut self, index_kind: IndexKind) -> &mut Self {
+        self.index_kind = index_kind;
+        self
+    }
 
-            println!("Key: {}, Record: {}", key_str, record_str);
+    /// Compresses every record with `compression`. Also fixed when the table is created; the
+    /// codec is kept in the datafile header, so reads and scans decompress on their own.
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
 
-            Ok(())
+    /// Encrypts the records and index pages with `key`. A table created with a key has to be
+    /// opened with the same one; any other fails with `Error::WrongKey`.
+    pub fn encryption_key(&mut self, key: impl Into<EncryptionKey>) -> &mut Self {
+        self.encryption_key = Some(key.into());
+        self
+    }
+
+    /// Keeps a Bloom filter of `bits_per_key` bits per key in front of the index, so that most
+    /// lookups of missing keys do not read it. Around 10 bits per key let through 1% of them.
+    /// Unlike the sizes, this can change from one open to the next; file tables save the filter
+    /// next to the datafile with `.blm` appended, others rebuild it from the index when opened.
+    pub fn bloom_filter(&mut self, bits_per_key: u32) -> &mut Self {
+        self.bloom_filter = Some(bits_per_key);
+        self
+    }
+
+    /// Stores the table with `engine`. Fixed when the table is created: an existing table is
+    /// opened with the engine it has, whatever this says.
+    pub fn engine(&mut self, engine: Engine) -> &mut Self {
+        self.engine = engine;
+        self
+    }
+
+    /// How many bytes of writes an LSM table collects in its memtable before flushing them to
+    /// a run. Larger memtables mean fewer, larger runs and less compaction. Can change from one
+    /// open to the next.
+    pub fn memtable_size(&mut self, bytes: usize) -> &mut Self {
+        self.memtable_size = bytes;
+        self
+    }
+
+    pub fn backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn durability(&mut self, durability: Durability) -> &mut Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Where to keep the index. Defaults to the datafile path with `.ndx` appended.
+    pub fn index_path(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.index_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Opens the table at `path`, creating the datafile and index if they do not exist yet. An
+    /// LSM table keeps its manifest at `path`, and its WAL and runs next to it.
+    pub fn open(&self, path: impl AsRef<Path>) -> Result<Table> {
+        let path = path.as_ref();
+        if !path.exists() {
+            self.validate()?;
+        }
+        if LsmTable::is_lsm(path)? || (self.engine == Engine::Lsm && !path.exists()) {
+            return self.open_lsm(Files::Disk(path.to_path_buf()));
+        }
+
+        let datafile = FileStorage::create(path)?;
+        let indexfile = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.index_file(path))?;
+        let filterfile: Box<dyn Storage> = match self.bloom_filter {
+            Some(_) => Box::new(FileStorage::create(with_suffix(path, ".blm"))?),
+            None => Box::new(MemStorage::new()),
+        };
+
+        self.open_with_filter(Box::new(datafile), self.backend.storage(indexfile)?, filterfile)
+    }
+
+    /// The files that make up the table at `path`, whichever of them exist: the datafile, the
+    /// index and the saved Bloom filter, or for an LSM table the manifest, WAL and runs. Renaming
+    /// or dropping the table has to move all of them.
+    pub fn files(&self, path: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        if LsmTable::is_lsm(path)? || (self.engine == Engine::Lsm && !path.exists()) {
+            return LsmTable::files(path);
+        }
+        let files = [path.to_path_buf(), self.index_file(path), with_suffix(path, ".blm")];
+        Ok(files.into_iter().filter(|file| file.exists()).collect())
+    }
+
+    fn index_file(&self, path: &Path) -> PathBuf {
+        match &self.index_path {
+            Some(index_path) => index_path.clone(),
+            None => with_suffix(path, ".ndx"),
+        }
+    }
+
+    pub fn open_in_memory(&self) -> Result<Table> {
+        if self.engine == Engine::Lsm {
+            self.validate()?;
+            return self.open_lsm(Files::Memory);
+        }
+        self.open_with_storage(Box::new(MemStorage::new()), Box::new(MemStorage::new()))
+    }
+
+    /// Opens the table on the given datafile and index storage. A Bloom filter is kept in memory
+    /// only. LSM tables manage their own files, so they are opened with `open` or
+    /// `open_in_memory` instead.
+    pub fn open_with_storage(&self, datafile: Box<dyn Storage>, indexfile: Box<dyn Storage>) -> Result<Table> {
+        if self.engine == Engine::Lsm {
+            return Err(Error::InvalidOptions("an LSM table cannot be opened on given storage".to_string()));
+        }
+        self.open_with_filter(datafile, indexfile, Box::new(MemStorage::new()))
+    }
+
+    fn open_lsm(&self, files: Files) -> Result<Table> {
+        let unsupported = [
+            (self.order_statistics, "order statistics"),
+            (self.index_kind != IndexKind::BTree, "a hash index"),
+            (self.compression != Compression::None, "compression"),
+            (self.encryption_key.is_some(), "encryption"),
+            (self.bloom_filter.is_some(), "a Bloom filter"),
+        ];
+        if let Some((_, option)) = unsupported.iter().find(|(set, _)| *set) {
+            return Err(Error::InvalidOptions(format!("the LSM engine does not support {}", option)));
+        }
+
+        let mut table = LsmTable::open(files, self.keysize, self.recordsize, self.memtable_size)?;
+        self.check_matches(table.keysize(), table.recordsize())?;
+        table.set_durability(self.durability);
+        Ok(Table {
+            engine: TableEngine::Lsm(table),
+        })
+    }
+
+    fn open_with_filter(
+        &self,
+        datafile: Box<dyn Storage>,
+        indexfile: Box<dyn Storage>,
+        filterfile: Box<dyn Storage>,
+    ) -> Result<Table> {
+        if self.bloom_filter == Some(0) {
+            return Err(Error::InvalidOptions("a Bloom filter needs at least one bit per key".to_string()));
+        }
+        let mut table = IndexedTable::with_storage(datafile, indexfile, filterfile, self)?;
+        table.set_durability(self.durability);
+        Ok(Table {
+            engine: TableEngine::Indexed(Box::new(table)),
         })
     }
 
+    fn validate(&self) -> Result<()> {
+        if self.keysize == 0 {
+            return Err(Error::InvalidOptions("key size must be set".to_string()));
+        }
+        if self.recordsize == 0 {
+            return Err(Error::InvalidOptions("record size must be set".to_string()));
+        }
+        if self.order_statistics && self.index_kind == IndexKind::Hash {
+            return Err(Error::InvalidOptions("order statistics need a B-tree index".to_string()));
+        }
+        Ok(())
+    }
+
+    fn check_matches(&self, keysize: u16, recordsize: u16) -> Result<()> {
+        if self.keysize != 0 && self.keysize != keysize {
+            return Err(Error::InvalidOptions(format!(
+                "key size {} does not match the table's key size {}",
+                self.keysize, keysize
+            )));
+        }
+        if self.recordsize != 0 && self.recordsize != recordsize {
+            return Err(Error::InvalidOptions(format!(
+                "record size {} does not match the table's record size {}",
+                self.recordsize, recordsize
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl IndexedTable {
+    fn with_storage(
+        mut datafile: Box<dyn Storage>,
+        mut indexfile: Box<dyn Storage>,
+        mut filterfile: Box<dyn Storage>,
+        options: &TableOptions,
+    ) -> Result<Self> {
+        let header_len = Header::len(datafile.as_mut())?;
+        if header_len.is_none_or(|header_len| datafile.len().is_ok_and(|len| len < header_len)) {
+            // A new table, or one whose creation was torn before the header reached the disk.
+            options.validate()?;
+            datafile.truncate(0)?;
+            let mut header = Header {
+                keysize: options.keysize,
+                recordsize: options.recordsize,
+                page_size: options.page_size,
+                records: 0,
+                deleted: 0,
+                order_statistics: options.order_statistics,
+                index_kind: options.index_kind,
+                compression: options.compression,
+                end: 0,
+                encrypted: options.encryption_key.is_some(),
+                key_check: Vec::new(),
+            };
+            if let Some(key) = &options.encryption_key {
+                header.key_check = Cipher::new(key).key_check(&Header::sealed_fields(&header.fixed()));
+            }
+            header.end = header.layout(None).data_start() + header.key_check.len() as u64;
+            header.write(datafile.as_mut())?;
+            datafile.sync()?;
+        }
+
+        let header = Header::read(datafile.as_mut())?;
+        options.check_matches(header.keysize, header.recordsize)?;
+        let Header {
+            keysize,
+            recordsize,
+            page_size,
+            ..
+        } = header;
+        let cipher = header.cipher(options.encryption_key.as_ref())?;
+        let layout = header.layout(cipher.clone());
+
+        let len = datafile.len()?;
+        let (records, end) = if layout.compressed() {
+            if header.end < layout.data_start() || header.end > len {
+                return Err(Error::Corruption(format!(
+                    "datafile is {} bytes but its entries end at {}",
+                    len, header.end
+                )));
+            }
+            // Entries appended since the last checkpoint, up to a torn one if any.
+            let (mut records, mut end) = (header.records, header.end);
+            while end + layout.min_len() <= len {
+                let entry_len = layout.entry_len(datafile.as_mut(), end)?;
+                if end + entry_len > len {
+                    break;
+                }
+                end += entry_len;
+                records += 1;
+            }
+            if end < len {
+                datafile.truncate(end)?;
+            }
+            (records, end)
+        } else {
+            let entry_size = layout.stored_len(0);
+            let start = layout.data_start();
+            let len = len - start;
+            if !len.is_multiple_of(entry_size) {
+                // A torn append left part of a record at the end of the datafile.
+                datafile.truncate(start + len - len % entry_size)?;
+            }
+
+            let records = len / entry_size;
+            if records < header.records {
+                return Err(Error::Corruption(format!(
+                    "datafile holds {} records but its header counts {}",
+                    records, header.records
+                )));
+            }
+            (records, start + records * entry_size)
+        };
+        let (records, end) = if layout.cipher.is_some() {
+            let checkpoint = if layout.compressed() {
+                header.end
+            } else {
+                layout.data_start() + header.records * layout.stored_len(0)
+            };
+            Self::drop_unsealed_tail(datafile.as_mut(), &layout, checkpoint, header.records, end)?
+        } else {
+            (records, end)
+        };
+
+        let mut deleted = header.deleted;
+        let kind = header.index_kind;
+        let (mut index, rebuilt) = match TableIndex::clean_len(kind, indexfile.as_mut())? {
+            Some(n) if Some(n) == records.checked_sub(deleted) => {
+                (TableIndex::open(kind, indexfile, cipher.clone())?, false)
+            }
+            _ => {
+                indexfile.truncate(0)?;
+                let counted = header.order_statistics;
+                let mut idx = TableIndex::create(kind, indexfile, keysize, page_size, counted, cipher.clone())?;
+                deleted = Self::create_index(datafile.as_mut(), &layout, end, &mut idx)?;
+                (idx, true)
+            }
+        };
+        if index.keysize() != keysize {
+            return Err(Error::Corruption(format!(
+                "index key size {} does not match the datafile's {}",
+                index.keysize(),
+                keysize
+            )));
+        }
+        if index.is_counted() != header.order_statistics {
+            return Err(Error::Corruption(
+                "index and datafile disagree on keeping subtree counts".to_string(),
+            ));
+        }
+
+        // A saved filter is only used along with the index it was saved with.
+        let filter = match options.bloom_filter {
+            Some(bits_per_key) => {
+                let saved = if rebuilt {
+                    None
+                } else {
+                    BloomFilter::load(filterfile.as_mut(), cipher.as_ref(), bits_per_key, (records, deleted))?
+                };
+                match saved {
+                    Some(filter) => Some(filter),
+                    None => Some(Self::build_filter(&mut index, bits_per_key)?),
+                }
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            keysize,
+            recordsize,
+            compression: header.compression,
+            cipher,
+            records,
+            deleted,
+            end,
+            synced_counts: (header.records, header.deleted),
+            // Uncompressed tables find their end from the file length alone.
+            synced_end: if layout.compressed() { header.end } else { end },
+            datafile,
+            index,
+            filter,
+            filterfile,
+            durability: Durability::default(),
+            last_commit: Instant::now(),
+            held_back: false,
+        })
+    }
+
+    fn keysize(&self) -> u16 {
+        self.keysize
+    }
+
+    fn recordsize(&self) -> u16 {
+        self.recordsize
+    }
+
+    fn index(&self) -> Option<&Index> {
+        match &self.index {
+            TableIndex::BTree(index) => Some(index),
+            TableIndex::Hash(_) => None,
+        }
+    }
+
+    fn hash_index(&self) -> Option<&HashIndex> {
+        match &self.index {
+            TableIndex::BTree(_) => None,
+            TableIndex::Hash(index) => Some(index),
+        }
+    }
+
+    fn index_kind(&self) -> IndexKind {
+        self.index.kind()
+    }
+
+    fn count(&self) -> u64 {
+        self.index.len()
+    }
+
+    fn rank(&mut self, key: &[u8]) -> Result<u64> {
+        self.index.btree()?.rank(key)
+    }
+
+    fn record_at(&mut self, position: u64) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        match self.index.btree()?.select(position)? {
+            Some((key, offset)) => Ok(Some((key, self.read_record(offset)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn min_key(&mut self) -> Result<Option<Vec<u8>>> {
+        self.index.btree()?.min_key()
+    }
+
+    fn max_key(&mut self) -> Result<Option<Vec<u8>>> {
+        self.index.btree()?.max_key()
+    }
+
+    // Returns the number of deleted entries. If a crash lost the tombstone of a record that was
+    // deleted and added again, the later entry wins and the earlier one is deleted now.
+    // Entries appended since the last checkpoint may have reached the disk only in part, or not
+    // at all while a later write did, which leaves zeros in their place. Both read as garbage
+    // in a plain table but fail to open in an encrypted one, so the datafile is cut off at the
+    // first such entry. A sync makes everything written before it durable, so none of the
+    // entries after a lost one were synced either.
+    fn drop_unsealed_tail(
+        datafile: &mut dyn Storage,
+        layout: &Layout,
+        checkpoint: u64,
+        mut records: u64,
+        end: u64,
+    ) -> Result<(u64, u64)> {
+        let mut offset = checkpoint;
+        while offset < end {
+            match layout.read_entry(datafile, offset) {
+                Ok((LIVE | DELETED, _)) => {}
+                Ok(_) | Err(Error::Corruption(_)) => {
+                    datafile.truncate(offset)?;
+                    return Ok((records, offset));
+                }
+                Err(err) => return Err(err),
+            }
+            offset += layout.entry_len(datafile, offset)?;
+            records += 1;
+        }
+        Ok((records, end))
+    }
+
+    fn create_index(datafile: &mut dyn Storage, layout: &Layout, end: u64, index: &mut TableIndex) -> Result<u64> {
+        let mut offset = layout.data_start();
+        let mut deleted = 0;
+
+        while offset < end {
+            let (flag, key) = layout.read_head(datafile, offset)?;
+            if flag == LIVE {
+                if let Some(stale) = index.delete(&key)? {
+                    datafile.write_at(stale, &[DELETED])?;
+                    deleted += 1;
+                }
+                index.insert(key, offset)?;
+            } else {
+                deleted += 1;
+            }
+            offset += layout.entry_len(datafile, offset)?;
+        }
+
+        Ok(deleted)
+    }
+
+    // Starts the index over from the datafile, as `open` does with one left dirty.
+    fn rebuild_index(&mut self) -> Result<()> {
+        let kind = self.index.kind();
+        let page_size = self.index.page_size();
+        let counted = self.index.is_counted();
+        let mut storage = self.index.take_storage();
+        storage.truncate(0)?;
+        self.index = TableIndex::create(kind, storage, self.keysize, page_size, counted, self.cipher.clone())?;
+        let layout = self.layout();
+        self.deleted = Self::create_index(self.datafile.as_mut(), &layout, self.end, &mut self.index)?;
+        Ok(())
+    }
+
+    // A filter of every key in `index`, sized for twice as many.
+    fn build_filter(index: &mut TableIndex, bits_per_key: u32) -> Result<BloomFilter> {
+        let mut filter = BloomFilter::new(bits_per_key, index.len() * 2);
+        index.traverse(|key, _| {
+            filter.insert(key);
+            Ok(())
+        })?;
+        Ok(filter)
+    }
+
+    fn stats(&self) -> Result<TableStats> {
+        Ok(TableStats {
+            keysize: self.keysize,
+            recordsize: self.recordsize,
+            engine: Engine::Indexed,
+            index_kind: self.index.kind(),
+            page_size: self.index.page_size(),
+            t: self.index.t(),
+            records: self.index.len(),
+            deleted: self.deleted,
+            compression: self.compression,
+            encrypted: self.cipher.is_some(),
+            datafile_bytes: self.datafile.len()?,
+            index_pages: self.index.page_count(),
+            filter: self.filter.as_ref().map(BloomFilter::stats),
+            runs: Vec::new(),
+        })
+    }
+
+    fn verify(&mut self) -> Result<()> {
+        self.index.verify()?;
+
+        let layout = self.layout();
+        let (mut live, mut deleted) = (0u64, 0u64);
+
+        let mut offset = layout.data_start();
+        while offset < self.end {
+            let (flag, key) = layout.read_head(self.datafile.as_mut(), offset)?;
+            match flag {
+                LIVE => {
+                    if self.index.search(&key)? != Some(offset) {
+                        return Err(Error::Corruption(format!(
+                            "entry at offset {} is not the one the index points to",
+                            offset
+                        )));
+                    }
+                    live += 1;
+                }
+                DELETED => deleted += 1,
+                flag => {
+                    return Err(Error::Corruption(format!(
+                        "entry at offset {} has unknown flag {}",
+                        offset, flag
+                    )));
+                }
+            }
+            offset += layout.entry_len(self.datafile.as_mut(), offset)?;
+        }
+
+        if live != self.index.len() || deleted != self.deleted {
+            return Err(Error::Corruption(format!(
+                "datafile holds {} live and {} deleted entries, expected {} and {}",
+                live,
+                deleted,
+                self.index.len(),
+                self.deleted
+            )));
+        }
+        Ok(())
+    }
+
+    fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    // Checkpoint: everything is synced and the index is marked clean, so reopening skips the rebuild.
+    fn sync(&mut self) -> Result<()> {
+        self.datafile.sync()?;
+        if (self.records, self.deleted) != self.synced_counts || self.end != self.synced_end {
+            // Only count entries that are already on disk. Compressed tables write their end
+            // along with the counts, rewriting the two flag bytes between them unchanged.
+            let mut counts = Vec::with_capacity(26);
+            counts.extend_from_slice(&self.records.to_le_bytes());
+            counts.extend_from_slice(&self.deleted.to_le_bytes());
+            if self.layout().compressed() {
+                let flags = Header::flags(self.index.is_counted(), self.index.kind(), self.cipher.is_some());
+                counts.extend_from_slice(&[flags, self.compression.id()]);
+                counts.extend_from_slice(&self.end.to_le_bytes());
+            }
+            self.datafile.write_at(COUNTS_OFFSET, &counts)?;
+            self.datafile.sync()?;
+            self.synced_counts = (self.records, self.deleted);
+            self.synced_end = self.end;
+        }
+        self.index.sync()?;
+        if let Some(filter) = &mut self.filter {
+            filter.save(self.filterfile.as_mut(), self.cipher.as_ref(), (self.records, self.deleted))?;
+        }
+        self.last_commit = Instant::now();
+        self.held_back = false;
+        Ok(())
+    }
+
+    // Called at the end of every write. The datafile goes first so the index never points
+    // at a record that is less durable than itself.
+    fn commit(&mut self) -> Result<()> {
+        self.refresh_filter()?;
+        if let Durability::GroupCommit(interval) = self.durability
+            && self.last_commit.elapsed() < interval
+        {
+            self.held_back = true;
+            return Ok(());
+        }
+
+        self.datafile.commit(self.durability)?;
+        self.index.commit(self.durability)?;
+        self.last_commit = Instant::now();
+        self.held_back = false;
+        Ok(())
+    }
+
+    // Called at the start of every call on the table, reads included, so writes that group
+    // commit held back are synced once the interval has passed even if no write follows them.
+    fn commit_due(&mut self) -> Result<()> {
+        if let Durability::GroupCommit(interval) = self.durability
+            && self.held_back
+            && self.last_commit.elapsed() >= interval
+        {
+            self.datafile.commit(self.durability)?;
+            self.index.commit(self.durability)?;
+            self.last_commit = Instant::now();
+            self.held_back = false;
+        }
+        Ok(())
+    }
+
+    // Deleted keys stay in the Bloom filter, and past its capacity it lets through more and more
+    // misses. Either way it is rebuilt from the index, which stands in for compacting it.
+    fn refresh_filter(&mut self) -> Result<()> {
+        if let Some(filter) = &self.filter
+            && filter.is_stale(self.index.len())
+        {
+            let mut fresh = Self::build_filter(&mut self.index, filter.bits_per_key())?;
+            fresh.take_counters(filter);
+            self.filter = Some(fresh);
+        }
+        Ok(())
+    }
+
+    fn check_sizes(&self, key: &[u8], record: &[u8]) -> Result<()> {
+        check_sizes(self.keysize, self.recordsize, key, record)
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            keysize: self.keysize,
+            recordsize: self.recordsize,
+            compression: self.compression,
+            cipher: self.cipher.clone(),
+        }
+    }
+
+    fn read_record(&mut self, offset: u64) -> Result<Vec<u8>> {
+        self.layout().read_record(self.datafile.as_mut(), offset)
+    }
+
+    // `key` as the index keeps it: zero-padded, or cut, to the key size.
+    fn fixed_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut fixed_key = key[..key.len().min(self.keysize as usize)].to_vec();
+        fixed_key.resize(self.keysize as usize, 0);
+        fixed_key
+    }
+
+    // The entry of `key`. The Bloom filter is asked first, so most missing keys never reach the
+    // index.
+    fn lookup(&mut self, key: &[u8]) -> Result<Option<u64>> {
+        let fixed_key = self.fixed_key(key);
+        let Some(filter) = &mut self.filter else {
+            return self.index.search(key);
+        };
+        if !filter.may_contain(&fixed_key) {
+            filter.count_miss(false);
+            return Ok(None);
+        }
+        let offset = self.index.search(key)?;
+        if offset.is_none() {
+            filter.count_miss(true);
+        }
+        Ok(offset)
+    }
+
+    // `lookup` for fixed keys in ascending order, sharing index reads as `Index::search_sorted`.
+    fn lookup_sorted(&mut self, keys: &[Vec<u8>]) -> Result<Vec<Option<u64>>> {
+        let Some(filter) = &mut self.filter else {
+            return self.index.search_sorted(keys);
+        };
+        let candidates: Vec<Vec<u8>> = keys.iter().filter(|key| filter.may_contain(key)).cloned().collect();
+        for _ in candidates.len()..keys.len() {
+            filter.count_miss(false);
+        }
+        let offsets = self.index.search_sorted(&candidates)?;
+        for _ in offsets.iter().filter(|offset| offset.is_none()) {
+            filter.count_miss(true);
+        }
+
+        let mut found = candidates.into_iter().zip(offsets).peekable();
+        Ok(keys
+            .iter()
+            .map(|key| found.next_if(|(candidate, _)| candidate == key).and_then(|(_, offset)| offset))
+            .collect())
+    }
+
+    // Claims the key in the index for the entry about to be appended, so that finding out
+    // whether it exists and adding it take one descent. Returns the offset of the existing
+    // entry instead if there is one. A failed append takes the key back out; if even that
+    // fails, the index was already marked dirty and is rebuilt on the next open.
+    fn insert_or_find(&mut self, key: &[u8], record: &[u8]) -> Result<Option<u64>> {
+        let offset = self.end;
+        let entry = self.layout().entry(offset, key, record);
+
+        // A key the Bloom filter has never seen is new for sure.
+        let fixed_key = self.fixed_key(key);
+        let filtered = self.filter.as_ref().is_some_and(|filter| !filter.may_contain(&fixed_key));
+        if filtered {
+            self.index.insert(fixed_key.clone(), offset)?;
+        } else if let Some(existing) = self.index.insert_if_absent(fixed_key.clone(), offset)? {
+            return Ok(Some(existing));
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.count_miss(!filtered);
+            filter.insert(&fixed_key);
+        }
+
+        if let Err(err) = self.datafile.write_at(offset, &entry) {
+            let _ = self.index.delete(key);
+            return Err(err.into());
+        }
+        self.records += 1;
+        self.end += entry.len() as u64;
+        Ok(None)
+    }
+
+    // Overwrites the record of `key`, whose entry is at `offset`. A compressed record that
+    // outgrew its slot, or any record of an encrypted table, moves to a new entry at the end
+    // instead. The new entry is made durable before the old one is deleted, whatever the
+    // durability mode, so a crash in between leaves two live entries, of which a rebuild keeps
+    // the later, rather than none.
+    fn write_record(&mut self, key: &[u8], offset: u64, record: &[u8]) -> Result<()> {
+        let layout = self.layout();
+        if layout.write_record(self.datafile.as_mut(), offset, key, record)? {
+            return Ok(());
+        }
+
+        let moved = self.end;
+        let entry = layout.entry(moved, key, record);
+        self.datafile.write_at(moved, &entry)?;
+        self.records += 1;
+        self.end += entry.len() as u64;
+        self.datafile.sync_data()?;
+
+        self.datafile.write_at(offset, &[DELETED])?;
+        self.deleted += 1;
+        self.index.update(key, moved)?;
+        Ok(())
+    }
+
+    fn add_record(&mut self, key: &[u8], record: &[u8]) -> Result<()> {
+        self.check_sizes(key, record)?;
+
+        if self.insert_or_find(key, record)?.is_some() {
+            return Err(Error::DuplicateKey);
+        }
+        self.commit()
+    }
+
+    fn update_record(&mut self, key: &[u8], new_record: &[u8]) -> Result<()> {
+        self.check_sizes(key, new_record)?;
+
+        let offset = self.lookup(key)?.ok_or(Error::KeyNotFound)?;
+        self.write_record(key, offset, new_record)?;
+
+        self.commit()
+    }
+
+    fn upsert(&mut self, key: &[u8], record: &[u8]) -> Result<WriteOutcome> {
+        self.check_sizes(key, record)?;
+
+        let outcome = match self.insert_or_find(key, record)? {
+            Some(offset) => {
+                self.write_record(key, offset, record)?;
+                WriteOutcome::Replaced
+            }
+            None => WriteOutcome::Inserted,
+        };
+        self.commit()?;
+        Ok(outcome)
+    }
+
+    fn insert_if_absent(&mut self, key: &[u8], record: &[u8]) -> Result<WriteOutcome> {
+        self.check_sizes(key, record)?;
+
+        match self.insert_or_find(key, record)? {
+            Some(offset) => Ok(WriteOutcome::Kept(self.read_record(offset)?)),
+            None => {
+                self.commit()?;
+                Ok(WriteOutcome::Inserted)
+            }
+        }
+    }
+
+    fn compare_and_swap(&mut self, key: &[u8], expected: &[u8], new: &[u8]) -> Result<WriteOutcome> {
+        self.check_sizes(key, new)?;
+        self.check_sizes(key, expected)?;
+
+        let Some(offset) = self.lookup(key)? else {
+            return Ok(WriteOutcome::Missing);
+        };
+        let current = self.read_record(offset)?;
+        let mut fixed_expected = vec![0u8; self.recordsize as usize];
+        fixed_expected[..expected.len()].copy_from_slice(expected);
+        if current != fixed_expected {
+            return Ok(WriteOutcome::Kept(current));
+        }
+
+        self.write_record(key, offset, new)?;
+        self.commit()?;
+        Ok(WriteOutcome::Replaced)
+    }
+
+    // The tombstone goes to the datafile before the key leaves the index, so a rebuild after a
+    // crash in between drops the record too.
+    fn delete_record(&mut self, key: &[u8]) -> Result<()> {
+        self.check_sizes(key, &[])?;
+
+        let offset = self.lookup(key)?.ok_or(Error::KeyNotFound)?;
+        self.datafile.write_at(offset, &[DELETED])?;
+        self.index.delete(key)?;
+        self.deleted += 1;
+        if let Some(filter) = &mut self.filter {
+            filter.remove();
+        }
+
+        self.commit()
+    }
+
+    fn write_batch(&mut self, batch: &WriteBatch) -> Result<()> {
+        let mut keys = Vec::with_capacity(batch.writes.len());
+        for (key, write) in &batch.writes {
+            let record = match write {
+                BatchWrite::Put(record) | BatchWrite::Update(record) => record.as_slice(),
+                BatchWrite::Delete => &[],
+            };
+            self.check_sizes(key, record)?;
+            keys.push(self.fixed_key(key));
+        }
+
+        // One sorted pass over the index finds every key the batch touches.
+        let mut sorted = keys.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        let offsets = self.lookup_sorted(&sorted)?;
+        let mut changes: BTreeMap<Vec<u8>, BatchChange> = sorted
+            .into_iter()
+            .zip(offsets)
+            .map(|(key, offset)| {
+                let change = BatchChange {
+                    offset,
+                    present: offset.is_some(),
+                    record: None,
+                };
+                (key, change)
+            })
+            .collect();
+        for ((_, write), key) in batch.writes.iter().zip(&keys) {
+            let change = changes.get_mut(key).unwrap();
+            match write {
+                BatchWrite::Put(record) => {
+                    change.present = true;
+                    change.record = Some(record.clone());
+                }
+                BatchWrite::Update(record) if change.present => change.record = Some(record.clone()),
+                BatchWrite::Delete if change.present => {
+                    change.present = false;
+                    change.record = None;
+                }
+                _ => return Err(Error::KeyNotFound),
+            }
+        }
+
+        // New records, and rewrites that cannot go in place, are appended together. Everything
+        // else is a write over an existing entry: a rewritten record or a tombstone.
+        let layout = self.layout();
+        let old_end = self.end;
+        let mut appended = Vec::new();
+        let mut overwrites = Vec::new();
+        let mut index_changes = Vec::new();
+        for (key, change) in &changes {
+            match (change.offset, change.present, &change.record) {
+                (None, true, Some(record)) => {
+                    let offset = old_end + appended.len() as u64;
+                    appended.extend_from_slice(&layout.entry(offset, key, record));
+                    index_changes.push((key, IndexChange::Insert(offset)));
+                }
+                (Some(offset), true, Some(record)) => {
+                    match layout.rewrite(self.datafile.as_mut(), offset, key, record)? {
+                        Some(body) => overwrites.push((offset + 1, body)),
+                        None => {
+                            let moved = old_end + appended.len() as u64;
+                            appended.extend_from_slice(&layout.entry(moved, key, record));
+                            overwrites.push((offset, vec![DELETED]));
+                            index_changes.push((key, IndexChange::Move(moved)));
+                        }
+                    }
+                }
+                (Some(offset), false, _) => {
+                    overwrites.push((offset, vec![DELETED]));
+                    index_changes.push((key, IndexChange::Delete));
+                }
+                _ => {}
+            }
+        }
+        let count = |f: fn(&IndexChange) -> bool| index_changes.iter().filter(|(_, change)| f(change)).count() as u64;
+        let moved = count(|change| matches!(change, IndexChange::Move(_)));
+        let added = count(|change| !matches!(change, IndexChange::Delete));
+        let tombstones = count(|change| !matches!(change, IndexChange::Insert(_)));
+
+        // As in `write_record`, moved records are made durable, with a single sync, before
+        // their old entries are deleted.
+        if !appended.is_empty() {
+            let result = self.datafile.write_at(old_end, &appended).and_then(|()| {
+                if moved > 0 { self.datafile.sync_data() } else { Ok(()) }
+            });
+            if let Err(err) = result {
+                let _ = self.datafile.truncate(old_end);
+                return Err(err.into());
+            }
+        }
+
+        // As in `delete_record`, tombstones reach the datafile before their keys leave the index.
+        // The bytes each write replaces are kept, so that a failure anywhere from here on can put
+        // the table back the way it was.
+        let mut overwritten = Vec::with_capacity(overwrites.len());
+        let mut result = Ok(());
+        for (offset, bytes) in &overwrites {
+            let mut old = vec![0u8; bytes.len()];
+            result = self.datafile.read_at(*offset, &mut old).and_then(|()| self.datafile.write_at(*offset, bytes));
+            if result.is_err() {
+                break;
+            }
+            overwritten.push((*offset, old));
+        }
+        let mut result: Result<()> = result.map_err(Error::from);
+        let overwritten_all = result.is_ok();
+        if overwritten_all {
+            for (key, change) in &index_changes {
+                result = match *change {
+                    IndexChange::Insert(offset) => self.index.insert(key.to_vec(), offset),
+                    IndexChange::Move(moved) => self.index.update(key, moved).map(drop),
+                    IndexChange::Delete => self.index.delete(key).map(drop),
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+        if let Err(err) = result {
+            for (offset, old) in overwritten.iter().rev() {
+                let _ = self.datafile.write_at(*offset, old);
+            }
+            let _ = self.datafile.truncate(old_end);
+            // An index write that failed halfway can leave the index broken, so it is built
+            // again from the restored datafile. If that fails too, the next open rebuilds it.
+            if overwritten_all {
+                let _ = self.rebuild_index();
+            }
+            return Err(err);
+        }
+
+        self.records += added;
+        self.deleted += tombstones;
+        self.end += appended.len() as u64;
+        if let Some(filter) = &mut self.filter {
+            for (key, change) in &index_changes {
+                match change {
+                    IndexChange::Insert(_) => filter.insert(key),
+                    IndexChange::Delete => filter.remove(),
+                    IndexChange::Move(_) => {}
+                }
+            }
+        }
+        self.commit()
+    }
+
+    fn search_record(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(offset) = self.lookup(key)? {
+            Ok(Some(self.read_record(offset)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn multi_get(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut unique = Vec::with_capacity(keys.len());
+        for key in keys {
+            self.check_sizes(key, &[])?;
+            let mut fixed_key = key.to_vec();
+            fixed_key.resize(self.keysize as usize, 0);
+            unique.push(fixed_key);
+        }
+        unique.sort_unstable();
+        unique.dedup();
+
+        let offsets = self.lookup_sorted(&unique)?;
+        let mut by_offset: Vec<(u64, usize)> = offsets
+            .iter()
+            .enumerate()
+            .filter_map(|(i, offset)| offset.map(|offset| (offset, i)))
+            .collect();
+        by_offset.sort_unstable();
+
+        let mut records = vec![None; unique.len()];
+        for (offset, i) in by_offset {
+            records[i] = Some(self.read_record(offset)?);
+        }
+
+        keys.iter()
+            .map(|key| {
+                let mut fixed_key = key.to_vec();
+                fixed_key.resize(self.keysize as usize, 0);
+                let i = unique.binary_search(&fixed_key).expect("every key was looked up");
+                Ok(records[i].clone())
+            })
+            .collect()
+    }
+
+    fn scan_range<'k>(&mut self, range: impl RangeBounds<&'k [u8]>) -> Result<Scan<'_>> {
+        let layout = self.layout();
+        Ok(Scan {
+            source: ScanSource::Indexed {
+                range: self.index.btree()?.range(range)?,
+                layout,
+                datafile: self.datafile.as_mut(),
+            },
+        })
+    }
+
+    fn scan_prefix(&mut self, prefix: &[u8]) -> Result<Scan<'_>> {
+        let layout = self.layout();
+        Ok(Scan {
+            source: ScanSource::Indexed {
+                range: self.index.btree()?.prefix_scan(prefix)?,
+                layout,
+                datafile: self.datafile.as_mut(),
+            },
+        })
+    }
+
+    fn for_each_record<F>(&mut self, mut visit: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        let layout = self.layout();
+        let datafile = &mut self.datafile;
+
+        self.index.traverse(|key, offset| {
+            let record = layout.read_record(datafile.as_mut(), offset)?;
+            visit(key, &record)
+        })
+    }
+}
+
+impl Table {
+    /// Opens an existing table; its sizes are read from the datafile header, or from the
+    /// manifest of an LSM table.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        std::fs::metadata(path)?;
+        TableOptions::new().open(path)
+    }
+
+    pub fn create(path: impl AsRef<Path>, recordsize: u16, keysize: u16) -> Result<Self> {
+        TableOptions::new().record_size(recordsize).key_size(keysize).open(path)
+    }
+
+    pub fn in_memory(recordsize: u16, keysize: u16) -> Result<Self> {
+        TableOptions::new().record_size(recordsize).key_size(keysize).open_in_memory()
+    }
+
+    pub fn engine(&self) -> Engine {
+        match &self.engine {
+            TableEngine::Indexed(_) => Engine::Indexed,
+            TableEngine::Lsm(_) => Engine::Lsm,
+        }
+    }
+
+    pub fn keysize(&self) -> u16 {
+        match &self.engine {
+            TableEngine::Indexed(table) => table.keysize(),
+            TableEngine::Lsm(table) => table.keysize(),
+        }
+    }
+
+    pub fn recordsize(&self) -> u16 {
+        match &self.engine {
+            TableEngine::Indexed(table) => table.recordsize(),
+            TableEngine::Lsm(table) => table.recordsize(),
+        }
+    }
+
+    /// The B-tree index, if the table has one: a table with a hash index has
+    /// `Table::hash_index` instead, and an LSM table has no index at all.
+    pub fn index(&self) -> Option<&Index> {
+        match &self.engine {
+            TableEngine::Indexed(table) => table.index(),
+            TableEngine::Lsm(_) => None,
+        }
+    }
+
+    /// The hash index, if the table was created with `IndexKind::Hash`.
+    pub fn hash_index(&self) -> Option<&HashIndex> {
+        match &self.engine {
+            TableEngine::Indexed(table) => table.hash_index(),
+            TableEngine::Lsm(_) => None,
+        }
+    }
+
+    /// The kind of index of an indexed table; LSM tables report the default.
+    pub fn index_kind(&self) -> IndexKind {
+        match &self.engine {
+            TableEngine::Indexed(table) => table.index_kind(),
+            TableEngine::Lsm(_) => IndexKind::default(),
+        }
+    }
+
+    /// The number of live records, kept by the index, or the LSM manifest and WAL, so no
+    /// records are read.
+    pub fn count(&self) -> u64 {
+        match &self.engine {
+            TableEngine::Indexed(table) => table.count(),
+            TableEngine::Lsm(table) => table.count(),
+        }
+    }
+
+    /// The number of records with keys less than `key`. Needs `TableOptions::order_statistics`.
+    pub fn rank(&mut self, key: &[u8]) -> Result<u64> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.rank(key),
+            TableEngine::Lsm(_) => Err(lsm_keeps_no_order_statistics()),
+        }
+    }
+
+    /// The record at `position` in key order, counting from 0. Needs
+    /// `TableOptions::order_statistics`.
+    pub fn record_at(&mut self, position: u64) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.record_at(position),
+            TableEngine::Lsm(_) => Err(lsm_keeps_no_order_statistics()),
+        }
+    }
+
+    pub fn min_key(&mut self) -> Result<Option<Vec<u8>>> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.min_key(),
+            TableEngine::Lsm(table) => table.min_key(),
+        }
+    }
+
+    pub fn max_key(&mut self) -> Result<Option<Vec<u8>>> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.max_key(),
+            TableEngine::Lsm(table) => table.max_key(),
+        }
+    }
+
+    pub fn stats(&self) -> Result<TableStats> {
+        match &self.engine {
+            TableEngine::Indexed(table) => table.stats(),
+            TableEngine::Lsm(table) => Ok(TableStats {
+                keysize: table.keysize(),
+                recordsize: table.recordsize(),
+                engine: Engine::Lsm,
+                index_kind: IndexKind::default(),
+                page_size: 0,
+                t: 0,
+                records: table.count(),
+                deleted: table.tombstones(),
+                compression: Compression::None,
+                encrypted: false,
+                datafile_bytes: table.bytes()?,
+                index_pages: 0,
+                filter: None,
+                runs: table.runs(),
+            }),
+        }
+    }
+
+    /// Checks the index invariants and that the index and the live datafile entries agree, or
+    /// for an LSM table that its runs are sorted and the live records add up to the count.
+    pub fn verify(&mut self) -> Result<()> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.verify(),
+            TableEngine::Lsm(table) => table.verify(),
+        }
+    }
+
+    pub fn durability(&self) -> Durability {
+        match &self.engine {
+            TableEngine::Indexed(table) => table.durability(),
+            TableEngine::Lsm(table) => table.durability(),
+        }
+    }
+
+    pub fn set_durability(&mut self, durability: Durability) {
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.set_durability(durability),
+            TableEngine::Lsm(table) => table.set_durability(durability),
+        }
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.sync(),
+            TableEngine::Lsm(table) => table.sync(),
+        }
+    }
+
+    fn commit_due(&mut self) -> Result<()> {
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.commit_due(),
+            TableEngine::Lsm(table) => table.commit_due(),
+        }
+    }
+
+    pub fn add_record(&mut self, key: &[u8], record: &[u8]) -> Result<()> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.add_record(key, record),
+            TableEngine::Lsm(table) => table.add_record(key, record),
+        }
+    }
+
+    pub fn update_record(&mut self, key: &[u8], new_record: &[u8]) -> Result<()> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.update_record(key, new_record),
+            TableEngine::Lsm(table) => table.update_record(key, new_record),
+        }
+    }
+
+    /// Adds the record, or overwrites the one already stored under `key`.
+    pub fn upsert(&mut self, key: &[u8], record: &[u8]) -> Result<WriteOutcome> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.upsert(key, record),
+            TableEngine::Lsm(table) => table.upsert(key, record),
+        }
+    }
+
+    /// Adds the record unless `key` is taken, in which case the stored record is returned.
+    pub fn insert_if_absent(&mut self, key: &[u8], record: &[u8]) -> Result<WriteOutcome> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.insert_if_absent(key, record),
+            TableEngine::Lsm(table) => table.insert_if_absent(key, record),
+        }
+    }
+
+    /// Overwrites the record under `key` with `new` only if it currently equals `expected`
+    /// (both compared zero-padded to the record size).
+    pub fn compare_and_swap(&mut self, key: &[u8], expected: &[u8], new: &[u8]) -> Result<WriteOutcome> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.compare_and_swap(key, expected, new),
+            TableEngine::Lsm(table) => table.compare_and_swap(key, expected, new),
+        }
+    }
+
+    pub fn delete_record(&mut self, key: &[u8]) -> Result<()> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.delete_record(key),
+            TableEngine::Lsm(table) => table.delete_record(key),
+        }
+    }
+
+    /// Applies all writes of `batch` with a single commit. Keys are sorted and looked up in one
+    /// pass over the index, new and moved records go to the datafile in one sequential append
+    /// and the index is updated in key order; an LSM table logs the whole batch in one WAL
+    /// append. The whole batch is checked first, so an oversized write or an update or delete
+    /// of a missing key fails without changing the table, and a batch that fails on I/O
+    /// partway is rolled back.
+    pub fn write_batch(&mut self, batch: &WriteBatch) -> Result<()> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.write_batch(batch),
+            TableEngine::Lsm(table) => table.write_batch(batch),
+        }
+    }
+
+    pub fn search_record(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.search_record(key),
+            TableEngine::Lsm(table) => table.search_record(key),
+        }
+    }
+
+    /// Looks up many keys at once and returns their records in the order of `keys`. The keys are
+    /// sorted and deduplicated so that they share index reads, and the records are read in
+    /// datafile order.
+    pub fn multi_get(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.multi_get(keys),
+            TableEngine::Lsm(table) => table.multi_get(keys),
+        }
+    }
+
+    /// Iterates over the records whose keys fall in `range`, in key order.
+    pub fn scan_range<'k>(&mut self, range: impl RangeBounds<&'k [u8]>) -> Result<Scan<'_>> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.scan_range(range),
+            TableEngine::Lsm(table) => Ok(Scan {
+                source: ScanSource::Lsm(table.scan_range(range)),
+            }),
+        }
+    }
+
+    /// Iterates over the records whose keys start with `prefix`, in key order.
+    pub fn scan_prefix(&mut self, prefix: &[u8]) -> Result<Scan<'_>> {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.scan_prefix(prefix),
+            TableEngine::Lsm(table) => Ok(Scan {
+                source: ScanSource::Lsm(table.scan_prefix(prefix)),
+            }),
+        }
+    }
+
+    pub fn scan(&mut self) -> Result<Scan<'_>> {
+        self.scan_range((Bound::Unbounded, Bound::<&[u8]>::Unbounded))
+    }
+
+    /// Visits every record: in key order with a B-tree index or the LSM engine, in bucket order
+    /// with a hash index.
+    pub fn for_each_record<F>(&mut self, visit: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        self.commit_due()?;
+        match &mut self.engine {
+            TableEngine::Indexed(table) => table.for_each_record(visit),
+            TableEngine::Lsm(table) => table.for_each_record(visit),
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn lsm_keeps_no_order_statistics() -> Error {
+    Error::Unsupported("an LSM table keeps no order statistics".to_string())
+}
+
+pub(crate) fn check_sizes(keysize: u16, recordsize: u16, key: &[u8], record: &[u8]) -> Result<()> {
+    if key.len() > keysize as usize {
+        return Err(Error::KeyTooLarge {
+            len: key.len(),
+            max: keysize as usize,
+        });
+    }
+    if record.len() > recordsize as usize {
+        return Err(Error::RecordTooLarge {
+            len: record.len(),
+            max: recordsize as usize,
+        });
+    }
+    Ok(())
+}
+
+/// Key and record pairs in key order, returned by `Table::scan_range`.
+pub struct Scan<'a> {
+    source: ScanSource<'a>,
+}
+
+// Where a scan gets its entries: an index range whose records are read from the datafile, or
+// the merged memtable and runs of an LSM table.
+enum ScanSource<'a> {
+    Indexed {
+        range: Range<'a>,
+        layout: Layout,
+        datafile: &'a mut dyn Storage,
+    },
+    Lsm(LsmScan<'a>),
+}
+
+fn with_record(
+    layout: &Layout,
+    datafile: &mut dyn Storage,
+    entry: Result<(Vec<u8>, u64)>,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let (key, offset) = entry?;
+    let record = layout.read_record(datafile, offset)?;
+    Ok((key, record))
+}
+
+impl Iterator for Scan<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.source {
+            ScanSource::Indexed { range, layout, datafile } => {
+                let entry = range.next()?;
+                Some(with_record(layout, *datafile, entry))
+            }
+            ScanSource::Lsm(scan) => scan.next(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for Scan<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match &mut self.source {
+            ScanSource::Indexed { range, layout, datafile } => {
+                let entry = range.next_back()?;
+                Some(with_record(layout, *datafile, entry))
+            }
+            ScanSource::Lsm(scan) => scan.next_back(),
+        }
+    }
+}
+
+impl Drop for IndexedTable {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
 }
@@ -0,0 +1,59 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use tempfile::tempdir;
+
+fn rustdb(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rustdb"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn runs_single_commands() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("t.dat");
+    let path = path.to_str().unwrap();
+
+    assert!(rustdb(&["create", path, "4", "8"], "").status.success());
+    assert!(rustdb(&["put", path, "k1", "hello"], "").status.success());
+    assert!(rustdb(&["put", path, "0x6b32", "0x00ff"], "").status.success());
+
+    assert_eq!(stdout(&rustdb(&["get", path, "k1"], "")), "hello\n");
+    assert_eq!(stdout(&rustdb(&["scan", path], "")), "k1\thello\nk2\t\\x00\\xff\n");
+    assert_eq!(stdout(&rustdb(&["--hex", "get", path, "k2"], "")), "00ff000000000000\n");
+    assert_eq!(stdout(&rustdb(&["count", path], "")), "2\n");
+    assert_eq!(stdout(&rustdb(&["min", path], "")), "k1\n");
+    assert_eq!(stdout(&rustdb(&["max", path], "")), "k2\n");
+
+    let missing = rustdb(&["get", path, "k3"], "");
+    assert!(!missing.status.success());
+    assert_eq!(String::from_utf8_lossy(&missing.stderr), "error: key not found\n");
+}
+
+#[test]
+fn shell_keeps_going_after_errors() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("t.dat");
+    let script = format!(
+        "create {} 4 16\nput a \"two words\"\nput a again\ndelete a\nget a\nput b x\ncount\nverify\nquit\n",
+        path.display()
+    );
+
+    let output = rustdb(&[], &script);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("error: key already exists"), "{}", out);
+    assert!(out.contains("error: key not found"), "{}", out);
+    assert!(out.contains("rustdb> 1\n"), "{}", out);
+    assert!(out.contains("rustdb> ok\n"), "{}", out);
+}
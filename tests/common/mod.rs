@@ -0,0 +1,149 @@
+use rustdb::Storage;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct State {
+    durable: Vec<u8>,
+    volatile: Vec<u8>,
+    epoch: u64,
+    dead: bool,
+    writes: u64,
+    reads: u64,
+    fail_write_at: Option<u64>,
+    tear_write_at: Option<(u64, usize)>,
+    fail_read_at: Option<u64>,
+}
+
+// Simulated disk: writes land in a volatile image and only reach the durable one on sync,
+// so `crash` can throw away everything that was never synced. Handles taken before a crash
+// stop working, which keeps a dropped `Table` from quietly syncing after the power went out.
+#[derive(Clone, Default)]
+pub struct FaultyStorage {
+    state: Arc<Mutex<State>>,
+    epoch: u64,
+}
+
+impl FaultyStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Fail the nth write from now (1-based) without applying it.
+    pub fn fail_write(&self, n: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.fail_write_at = Some(state.writes + n);
+    }
+
+    // Apply only the first `at` bytes of the nth write from now straight to the durable image,
+    // as if power was lost halfway through it. Every later operation fails until `crash`.
+    pub fn tear_write(&self, n: u64, at: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.tear_write_at = Some((state.writes + n, at));
+    }
+
+    // Fail the nth read from now (1-based).
+    pub fn fail_read(&self, n: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.fail_read_at = Some(state.reads + n);
+    }
+
+    // Lose every unsynced write and disarm pending faults. Returns a fresh handle to reopen with.
+    pub fn crash(&self) -> FaultyStorage {
+        let mut state = self.state.lock().unwrap();
+        state.volatile = state.durable.clone();
+        state.epoch += 1;
+        state.dead = false;
+        state.fail_write_at = None;
+        state.tear_write_at = None;
+        state.fail_read_at = None;
+
+        FaultyStorage {
+            state: self.state.clone(),
+            epoch: state.epoch,
+        }
+    }
+
+    fn check(&self, state: &State) -> io::Result<()> {
+        if state.epoch != self.epoch || state.dead {
+            return Err(io::Error::other("Simulated device is down"));
+        }
+        Ok(())
+    }
+}
+
+fn apply(image: &mut Vec<u8>, offset: u64, buf: &[u8]) {
+    let start = offset as usize;
+    if start + buf.len() > image.len() {
+        image.resize(start + buf.len(), 0);
+    }
+    image[start..start + buf.len()].copy_from_slice(buf);
+}
+
+impl Storage for FaultyStorage {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.check(&state)?;
+
+        state.reads += 1;
+        if state.fail_read_at == Some(state.reads) {
+            state.fail_read_at = None;
+            return Err(io::Error::other("Injected read failure"));
+        }
+
+        let start = offset as usize;
+        if start + buf.len() > state.volatile.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Read past end of storage"));
+        }
+        buf.copy_from_slice(&state.volatile[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.check(&state)?;
+
+        state.writes += 1;
+        if state.fail_write_at == Some(state.writes) {
+            state.fail_write_at = None;
+            return Err(io::Error::other("Injected write failure"));
+        }
+
+        if let Some((n, at)) = state.tear_write_at
+            && n == state.writes
+        {
+            let torn = &buf[..at.min(buf.len())];
+            apply(&mut state.durable, offset, torn);
+            apply(&mut state.volatile, offset, torn);
+            state.dead = true;
+            return Err(io::Error::other("Injected torn write"));
+        }
+
+        apply(&mut state.volatile, offset, buf);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.check(&state)?;
+
+        let State { durable, volatile, .. } = &mut *state;
+        durable.clone_from(volatile);
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        let state = self.state.lock().unwrap();
+        self.check(&state)?;
+
+        Ok(state.volatile.len() as u64)
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.check(&state)?;
+
+        state.volatile.resize(len as usize, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+mod common;
+
+use common::FaultyStorage;
+use rustdb::{Compression, Durability, Error, IndexKind, Storage, Table, TableOptions, WriteBatch};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+const KEYSIZE: u16 = 8;
+const RECORDSIZE: u16 = 24;
+const PAGE_SIZE: u32 = 256;
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+fn padded(record: &[u8]) -> Vec<u8> {
+    let mut fixed = vec![0u8; RECORDSIZE as usize];
+    fixed[..record.len()].copy_from_slice(record);
+    fixed
+}
+
+fn options(durability: Durability) -> TableOptions {
+    let mut options = TableOptions::new();
+    options.key_size(KEYSIZE).record_size(RECORDSIZE).page_size(PAGE_SIZE).durability(durability);
+    options
+}
+
+fn open_with(data: &FaultyStorage, index: &FaultyStorage, options: &TableOptions) -> Table {
+    options
+        .open_with_storage(Box::new(data.clone()), Box::new(index.clone()))
+        .expect("reopen after crash")
+}
+
+fn open(data: &FaultyStorage, index: &FaultyStorage) -> Table {
+    open_with(data, index, &options(Durability::default()))
+}
+
+struct Harness {
+    rng: Rng,
+    data: FaultyStorage,
+    index: FaultyStorage,
+    table: Table,
+    options: TableOptions,
+    durability: Durability,
+    committed: BTreeMap<Vec<u8>, Vec<u8>>,
+    current: BTreeMap<Vec<u8>, Vec<u8>>,
+    touched: BTreeSet<Vec<u8>>,
+    removed: BTreeSet<Vec<u8>>,
+    crashes: u64,
+}
+
+impl Harness {
+    fn new(seed: u64, options: TableOptions) -> Self {
+        let data = FaultyStorage::new();
+        let index = FaultyStorage::new();
+        let table = open_with(&data, &index, &options);
+        let durability = table.durability();
+        Harness {
+            rng: Rng(seed),
+            data,
+            index,
+            table,
+            options,
+            durability,
+            committed: BTreeMap::new(),
+            current: BTreeMap::new(),
+            touched: BTreeSet::new(),
+            removed: BTreeSet::new(),
+            crashes: 0,
+        }
+    }
+
+    fn step(&mut self) {
+        let result = match self.rng.below(100) {
+            0..=44 => {
+                let key = format!("k{:07}", self.rng.below(100_000)).into_bytes();
+                let record = format!("add {}", self.rng.next()).into_bytes();
+                self.touched.insert(key.clone());
+                let exists = self.current.contains_key(&key);
+                let result = self.table.add_record(&key, &record);
+                if result.is_ok() && !exists {
+                    self.current.insert(key, padded(&record));
+                }
+                result
+            }
+            45..=74 if !self.current.is_empty() => {
+                let nth = self.rng.below(self.current.len() as u64) as usize;
+                let key = self.current.keys().nth(nth).unwrap().clone();
+                let record = format!("upd {}", self.rng.next()).into_bytes();
+                self.touched.insert(key.clone());
+                let result = self.table.update_record(&key, &record);
+                if result.is_ok() {
+                    self.current.insert(key, padded(&record));
+                }
+                result
+            }
+            75..=81 if !self.current.is_empty() => {
+                let nth = self.rng.below(self.current.len() as u64) as usize;
+                let key = self.current.keys().nth(nth).unwrap().clone();
+                self.touched.insert(key.clone());
+                self.removed.insert(key.clone());
+                let result = self.table.delete_record(&key);
+                if result.is_ok() {
+                    self.current.remove(&key);
+                }
+                result
+            }
+            82..=89 => {
+                let result = self.table.sync();
+                if result.is_ok() {
+                    self.committed = self.current.clone();
+                    self.touched.clear();
+                    self.removed.clear();
+                }
+                result
+            }
+            90..=94 => {
+                self.arm_fault();
+                Ok(())
+            }
+            _ => {
+                self.crash_and_verify();
+                Ok(())
+            }
+        };
+
+        match result {
+            Ok(()) if matches!(self.durability, Durability::SyncData | Durability::SyncAll) => {
+                self.committed = self.current.clone();
+                self.touched.clear();
+                self.removed.clear();
+            }
+            Ok(()) | Err(Error::DuplicateKey) => {}
+            Err(_) => self.crash_and_verify(),
+        }
+    }
+
+    fn arm_fault(&mut self) {
+        let target = if self.rng.below(2) == 0 { &self.data } else { &self.index };
+        let n = 1 + self.rng.below(40);
+        match self.rng.below(3) {
+            0 => target.fail_write(n),
+            1 => target.tear_write(n, self.rng.below(PAGE_SIZE as u64) as usize),
+            _ => target.fail_read(n),
+        }
+    }
+
+    fn crash_and_verify(&mut self) {
+        self.crashes += 1;
+        self.data = self.data.crash();
+        self.index = self.index.crash();
+        self.table = open_with(&self.data, &self.index, &self.options);
+        self.table.verify().expect("table is consistent after recovery");
+
+        for (key, record) in &self.committed {
+            let found = self.table.search_record(key).unwrap();
+            if self.removed.contains(key) {
+                continue;
+            } else if self.touched.contains(key) {
+                assert!(found.is_some(), "committed key {:?} lost", key);
+            } else {
+                assert_eq!(found.as_ref(), Some(record), "committed key {:?} changed", key);
+            }
+        }
+
+        // Whatever happened to keys written after the last sync is now the truth.
+        self.removed.clear();
+        for key in std::mem::take(&mut self.touched) {
+            match self.table.search_record(&key).unwrap() {
+                Some(record) => self.committed.insert(key, record),
+                None => self.committed.remove(&key),
+            };
+        }
+        self.current = self.committed.clone();
+    }
+}
+
+#[test]
+fn crash_drops_unsynced_writes() {
+    let data = FaultyStorage::new();
+    let index = FaultyStorage::new();
+
+    let mut table = open(&data, &index);
+    table.add_record(b"synced", b"kept").unwrap();
+    table.sync().unwrap();
+    table.add_record(b"unsynced", b"lost").unwrap();
+
+    let data = data.crash();
+    let index = index.crash();
+    drop(table);
+
+    let mut table = open(&data, &index);
+    assert_eq!(table.search_record(b"synced").unwrap(), Some(padded(b"kept")));
+    assert_eq!(table.search_record(b"unsynced").unwrap(), None);
+}
+
+#[test]
+fn torn_append_is_discarded_on_reopen() {
+    let data = FaultyStorage::new();
+    let index = FaultyStorage::new();
+
+    let mut table = open(&data, &index);
+    table.add_record(b"first", b"one").unwrap();
+    table.sync().unwrap();
+    let synced_len = data.len().unwrap();
+
+    data.tear_write(1, 5);
+    assert!(table.add_record(b"second", b"two").is_err());
+
+    let data = data.crash();
+    let index = index.crash();
+    drop(table);
+
+    let mut table = open(&data, &index);
+    assert_eq!(data.len().unwrap(), synced_len);
+    assert_eq!(table.search_record(b"first").unwrap(), Some(padded(b"one")));
+    assert_eq!(table.search_record(b"second").unwrap(), None);
+}
+
+#[test]
+fn read_errors_are_returned() {
+    let data = FaultyStorage::new();
+    let index = FaultyStorage::new();
+
+    let mut table = open(&data, &index);
+    table.add_record(b"key", b"value").unwrap();
+
+    index.fail_read(1);
+    assert!(table.search_record(b"key").is_err());
+    assert_eq!(table.search_record(b"key").unwrap(), Some(padded(b"value")));
+}
+
+#[test]
+fn failed_write_batches_are_rolled_back() {
+    let check = |table: &mut Table| {
+        assert_eq!(table.count(), 20);
+        assert_eq!(table.search_record(b"key05").unwrap(), Some(padded(b"old")));
+        assert_eq!(table.search_record(b"key10").unwrap(), Some(padded(b"old")));
+        assert_eq!(table.search_record(b"key15").unwrap(), Some(padded(b"old")));
+        assert_eq!(table.search_record(b"new").unwrap(), None);
+    };
+
+    // Encrypted tables move every updated record to a new entry.
+    let plain = options(Durability::default());
+    let mut encrypted = options(Durability::default());
+    encrypted.encryption_key([7u8; 32]);
+    for options in [&plain, &encrypted] {
+        for faulty_index in [false, true] {
+            for n in 1.. {
+                let data = FaultyStorage::new();
+                let index = FaultyStorage::new();
+                let mut table = open_with(&data, &index, options);
+                for i in 0..20 {
+                    table.add_record(format!("key{:02}", i).as_bytes(), b"old").unwrap();
+                }
+
+                let mut batch = WriteBatch::new();
+                batch.put(b"new", b"added").update(b"key05", b"changed").delete(b"key10").put(b"key15", b"new");
+                if faulty_index { index.fail_write(n) } else { data.fail_write(n) }
+                if table.write_batch(&batch).is_ok() {
+                    break;
+                }
+
+                check(&mut table);
+                drop(table);
+                check(&mut open_with(&data, &index, options));
+            }
+        }
+    }
+}
+
+fn run_workloads(options: &TableOptions, seeds: u64, steps: u64) {
+    for seed in 1..=seeds {
+        let mut harness = Harness::new(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15), options.clone());
+        for _ in 0..steps {
+            harness.step();
+        }
+        harness.crash_and_verify();
+        assert!(harness.crashes > 0);
+    }
+}
+
+#[test]
+fn random_workloads_survive_crashes() {
+    run_workloads(&options(Durability::None), 8, 1_500);
+}
+
+#[test]
+fn compressed_tables_survive_crashes() {
+    // Updates that outgrow their entry move it, which must not lose the record in between.
+    run_workloads(options(Durability::None).compression(Compression::Lz4), 4, 1_500);
+}
+
+#[test]
+fn encrypted_tables_survive_crashes() {
+    // Every update moves its record, and unsynced entries lost to a crash no longer open. Each
+    // crash rebuilds the index, which decrypts every page it reads, so fewer steps keep this
+    // quick in debug builds.
+    run_workloads(options(Durability::None).encryption_key([7u8; 32]).compression(Compression::Lz4), 3, 400);
+}
+
+#[test]
+fn hash_indexed_tables_survive_crashes() {
+    // Bucket splits rewrite two pages and the directory only reaches the disk at a sync.
+    run_workloads(options(Durability::None).index_kind(IndexKind::Hash), 4, 1_500);
+}
+
+#[test]
+fn acknowledged_writes_survive_crashes_with_sync_data() {
+    run_workloads(&options(Durability::SyncData), 3, 1_500);
+}
+
+#[test]
+fn group_commit_with_no_interval_syncs_every_commit() {
+    let data = FaultyStorage::new();
+    let index = FaultyStorage::new();
+
+    let mut table = open_with(&data, &index, &options(Durability::GroupCommit(Duration::ZERO)));
+    table.add_record(b"first", b"one").unwrap();
+    table.add_record(b"second", b"two").unwrap();
+
+    let data = data.crash();
+    let index = index.crash();
+    drop(table);
+
+    let mut table = open(&data, &index);
+    assert_eq!(table.search_record(b"first").unwrap(), Some(padded(b"one")));
+    assert_eq!(table.search_record(b"second").unwrap(), Some(padded(b"two")));
+}
+
+#[test]
+fn group_commit_syncs_held_back_writes_at_the_next_read_once_the_interval_passes() {
+    let data = FaultyStorage::new();
+    let index = FaultyStorage::new();
+
+    let mut table = open_with(&data, &index, &options(Durability::GroupCommit(Duration::from_millis(20))));
+    table.add_record(b"idle", b"one").unwrap();
+    // However long the write took, the interval has passed by the time of the read.
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(table.search_record(b"other").unwrap(), None);
+
+    let data = data.crash();
+    let index = index.crash();
+    drop(table);
+
+    let mut table = open(&data, &index);
+    assert_eq!(table.search_record(b"idle").unwrap(), Some(padded(b"one")));
+}
+
+#[test]
+fn group_commit_defers_the_sync_until_the_table_is_synced_or_dropped() {
+    // The interval never passes during the test, so only an explicit sync or the drop makes writes durable.
+    let durability = Durability::GroupCommit(Duration::from_secs(3600));
+    let write = |data: &FaultyStorage, index: &FaultyStorage| {
+        let mut table = open_with(data, index, &options(durability));
+        table.add_record(b"synced", b"one").unwrap();
+        table.sync().unwrap();
+        table.add_record(b"pending", b"two").unwrap();
+        table
+    };
+
+    let data = FaultyStorage::new();
+    let index = FaultyStorage::new();
+    let table = write(&data, &index);
+    let data = data.crash();
+    let index = index.crash();
+    drop(table);
+
+    let mut table = open(&data, &index);
+    assert_eq!(table.search_record(b"synced").unwrap(), Some(padded(b"one")));
+    assert_eq!(table.search_record(b"pending").unwrap(), None);
+
+    // With no later commit, dropping the table is what makes the last write durable.
+    let data = FaultyStorage::new();
+    let index = FaultyStorage::new();
+    drop(write(&data, &index));
+    let data = data.crash();
+    let index = index.crash();
+
+    let mut table = open(&data, &index);
+    assert_eq!(table.search_record(b"synced").unwrap(), Some(padded(b"one")));
+    assert_eq!(table.search_record(b"pending").unwrap(), Some(padded(b"two")));
+}
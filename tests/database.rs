@@ -0,0 +1,172 @@
+use rustdb::{Backend, Database, Engine, Error, IndexKind, TableOptions};
+use tempfile::tempdir;
+
+fn options() -> TableOptions {
+    let mut options = TableOptions::new();
+    options.key_size(8).record_size(32);
+    options
+}
+
+#[test]
+fn creates_and_reopens_tables() {
+    let dir = tempdir().unwrap();
+
+    {
+        let mut db = Database::open(dir.path().join("db")).unwrap();
+        let mut users = db.create_table("users", &options()).unwrap();
+        users.add_record(b"alice", b"admin").unwrap();
+    }
+
+    let db = Database::open(dir.path().join("db")).unwrap();
+    let mut users = db.open_table("users").unwrap();
+    assert_eq!(users.keysize(), 8);
+    assert_eq!(users.recordsize(), 32);
+    assert_eq!(&users.search_record(b"alice").unwrap().unwrap()[..5], b"admin");
+
+    let mut mapped = TableOptions::new();
+    mapped.backend(Backend::Mmap);
+    drop(users);
+    let mut users = db.open_table_with("users", &mapped).unwrap();
+    assert_eq!(&users.search_record(b"alice").unwrap().unwrap()[..5], b"admin");
+}
+
+#[test]
+fn lists_renames_and_drops_tables() {
+    let dir = tempdir().unwrap();
+    let mut db = Database::open(dir.path()).unwrap();
+
+    db.create_table("users", &options()).unwrap().add_record(b"bob", b"user").unwrap();
+    db.create_table("orders", TableOptions::new().key_size(4).record_size(16)).unwrap();
+
+    let names: Vec<_> = db.tables().iter().map(|info| info.name.as_str()).collect();
+    assert_eq!(names, ["users", "orders"]);
+    assert_eq!(db.table_info("orders").unwrap().recordsize, 16);
+
+    db.rename_table("users", "people").unwrap();
+    assert!(db.table_info("users").is_none());
+    assert!(!dir.path().join("users.dat").exists());
+
+    let mut db = Database::open(dir.path()).unwrap();
+    let mut people = db.open_table("people").unwrap();
+    assert_eq!(&people.search_record(b"bob").unwrap().unwrap()[..4], b"user");
+    drop(people);
+
+    db.drop_table("people").unwrap();
+    assert!(!dir.path().join("people.dat").exists());
+    assert!(matches!(db.open_table("people"), Err(Error::TableNotFound(_))));
+    assert_eq!(Database::open(dir.path()).unwrap().tables().len(), 1);
+}
+
+#[test]
+fn reports_existing_missing_and_invalid_tables() {
+    let dir = tempdir().unwrap();
+    let mut db = Database::open(dir.path()).unwrap();
+
+    db.create_table("users", &options()).unwrap();
+    db.create_table("orders", &options()).unwrap();
+    assert!(matches!(db.create_table("users", &options()), Err(Error::TableExists(_))));
+    assert!(matches!(db.open_table("missing"), Err(Error::TableNotFound(_))));
+    assert!(matches!(db.rename_table("users", "orders"), Err(Error::TableExists(_))));
+    assert!(matches!(db.drop_table("missing"), Err(Error::TableNotFound(_))));
+    assert!(matches!(db.create_table("../escape", &options()), Err(Error::InvalidTableName(_))));
+}
+
+#[test]
+fn renames_and_drops_every_file_of_a_table() {
+    let dir = tempdir().unwrap();
+    let mut db = Database::open(dir.path()).unwrap();
+    let files = || {
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name != "catalog")
+            .collect();
+        names.sort();
+        names
+    };
+
+    let mut filtered = options();
+    filtered.bloom_filter(10);
+    db.create_table("users", &filtered).unwrap().add_record(b"carol", b"user").unwrap();
+    assert_eq!(files(), ["users.dat", "users.dat.blm", "users.ndx"]);
+
+    db.rename_table("users", "people").unwrap();
+    assert_eq!(files(), ["people.dat", "people.dat.blm", "people.ndx"]);
+    let mut people = db.open_table_with("people", &filtered).unwrap();
+    assert_eq!(&people.search_record(b"carol").unwrap().unwrap()[..4], b"user");
+    assert_eq!(people.search_record(b"dave").unwrap(), None);
+    drop(people);
+
+    db.drop_table("people").unwrap();
+    assert!(files().is_empty());
+}
+
+#[test]
+fn creates_reopens_and_drops_hash_indexed_tables() {
+    let dir = tempdir().unwrap();
+    let mut db = Database::open(dir.path()).unwrap();
+
+    let mut hashed = options();
+    hashed.index_kind(IndexKind::Hash);
+    let mut sessions = db.create_table("sessions", &hashed).unwrap();
+    sessions.add_record(b"s1", b"token").unwrap();
+    drop(sessions);
+    assert_eq!(db.table_info("sessions").unwrap().t, 0);
+
+    let mut db = Database::open(dir.path()).unwrap();
+    let mut sessions = db.open_table("sessions").unwrap();
+    assert_eq!(sessions.index_kind(), IndexKind::Hash);
+    assert_eq!(&sessions.search_record(b"s1").unwrap().unwrap()[..5], b"token");
+    drop(sessions);
+
+    db.drop_table("sessions").unwrap();
+    assert!(!dir.path().join("sessions.dat").exists());
+    assert!(!dir.path().join("sessions.ndx").exists());
+    db.create_table("sessions", &hashed).unwrap();
+}
+
+#[test]
+fn renames_and_drops_lsm_tables() {
+    let dir = tempdir().unwrap();
+    let mut db = Database::open(dir.path()).unwrap();
+    let key = |i: u32| format!("ev{:06}", i).into_bytes();
+    let files = |prefix: &str| {
+        std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with(prefix))
+            .count()
+    };
+
+    let mut lsm = options();
+    lsm.engine(Engine::Lsm).memtable_size(41 * 100);
+    let mut events = db.create_table("events", &lsm).unwrap();
+    for i in 0..450 {
+        events.add_record(&key(i), b"logged").unwrap();
+    }
+    assert!(events.stats().unwrap().runs.iter().sum::<usize>() > 0);
+    drop(events);
+    assert_eq!(db.table_info("events").unwrap().page_size, 0);
+    let runs_and_wal = files("events.");
+
+    // The runs and the WAL move along with the manifest.
+    db.rename_table("events", "history").unwrap();
+    assert_eq!(files("events."), 0);
+    assert_eq!(files("history."), runs_and_wal);
+    let mut history = db.open_table("history").unwrap();
+    assert_eq!(history.engine(), Engine::Lsm);
+    assert_eq!(history.count(), 450);
+    assert_eq!(&history.search_record(&key(449)).unwrap().unwrap()[..6], b"logged");
+    drop(history);
+
+    // A new table under the old name starts out empty.
+    let mut events = db.create_table("events", &lsm).unwrap();
+    assert_eq!(events.count(), 0);
+    assert_eq!(events.search_record(&key(1)).unwrap(), None);
+    drop(events);
+
+    db.drop_table("history").unwrap();
+    db.drop_table("events").unwrap();
+    assert_eq!(files("history."), 0);
+    assert_eq!(files("events."), 0);
+}
@@ -0,0 +1,281 @@
+use rustdb::{Database, Engine, Error, QueryResult, TableOptions, Value};
+use tempfile::tempdir;
+
+fn rows(db: &mut Database, sql: &str) -> Vec<Vec<Value>> {
+    match db.execute(sql).unwrap() {
+        QueryResult::Rows { rows, .. } => rows,
+        other => panic!("expected rows, got {:?}", other),
+    }
+}
+
+fn ids(db: &mut Database, sql: &str) -> Vec<i64> {
+    rows(db, sql)
+        .into_iter()
+        .map(|row| match row[0] {
+            Value::Int(id) => id,
+            ref other => panic!("expected an id, got {:?}", other),
+        })
+        .collect()
+}
+
+fn people() -> (tempfile::TempDir, Database) {
+    let dir = tempdir().unwrap();
+    let mut db = Database::open(dir.path()).unwrap();
+    db.execute("CREATE TABLE people (id INT PRIMARY KEY, name TEXT(16), score FLOAT, active BOOL)")
+        .unwrap();
+    let inserted = db
+        .execute(
+            "INSERT INTO people VALUES (3, 'carol', 7.5, TRUE), (-1, 'dave', 1, FALSE), \
+             (10, 'erin', 9.25, TRUE), (1, 'alice', 3.0, TRUE), (2, 'bob''s', -2.5, FALSE);",
+        )
+        .unwrap();
+    assert_eq!(inserted, QueryResult::Affected(5));
+    (dir, db)
+}
+
+#[test]
+fn selects_by_key_and_range() {
+    let (_dir, mut db) = people();
+
+    assert_eq!(
+        rows(&mut db, "SELECT * FROM people WHERE id = 2"),
+        [[Value::Int(2), Value::Text("bob's".into()), Value::Float(-2.5), Value::Bool(false)]]
+    );
+    assert_eq!(ids(&mut db, "SELECT id FROM people"), [-1, 1, 2, 3, 10]);
+    assert_eq!(ids(&mut db, "select id from people where id between 1 and 3"), [1, 2, 3]);
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE id > 1"), [2, 3, 10]);
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE id >= -1 AND id < 2"), [-1, 1]);
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE id > 1 AND active = TRUE"), [3, 10]);
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE score < 3"), [-1, 2]);
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE id = 4"), Vec::<i64>::new());
+
+    assert_eq!(
+        rows(&mut db, "SELECT name, id FROM people WHERE id = 10"),
+        [[Value::Text("erin".into()), Value::Int(10)]]
+    );
+}
+
+#[test]
+fn updates_and_deletes_matching_rows() {
+    let (dir, mut db) = people();
+
+    assert_eq!(
+        db.execute("UPDATE people SET score = 0, active = FALSE WHERE id >= 3").unwrap(),
+        QueryResult::Affected(2)
+    );
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE active = FALSE"), [-1, 2, 3, 10]);
+
+    assert_eq!(db.execute("DELETE FROM people WHERE name = 'dave'").unwrap(), QueryResult::Affected(1));
+    assert_eq!(db.execute("DELETE FROM people WHERE id BETWEEN 2 AND 3").unwrap(), QueryResult::Affected(2));
+
+    // The schema and the rows survive reopening the database.
+    drop(db);
+    let mut db = Database::open(dir.path()).unwrap();
+    assert_eq!(ids(&mut db, "SELECT id FROM people"), [1, 10]);
+    assert_eq!(
+        rows(&mut db, "SELECT score FROM people WHERE id = 10"),
+        [[Value::Float(0.0)]]
+    );
+}
+
+#[test]
+fn text_keys_and_named_insert_columns() {
+    let dir = tempdir().unwrap();
+    let mut db = Database::open(dir.path()).unwrap();
+    db.execute("CREATE TABLE users (age INT, name VARCHAR(8), PRIMARY KEY (name))").unwrap();
+    db.execute("INSERT INTO users (name, age) VALUES ('bob', 30), ('al', 25), ('carl', 41)").unwrap();
+
+    assert_eq!(
+        rows(&mut db, "SELECT name FROM users WHERE name > 'al'"),
+        [[Value::Text("bob".into())], [Value::Text("carl".into())]]
+    );
+    assert_eq!(rows(&mut db, "SELECT age FROM users WHERE name = 'al'"), [[Value::Int(25)]]);
+}
+
+#[test]
+fn compares_literals_the_column_cannot_store() {
+    let (_dir, mut db) = people();
+
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE id > 2.5"), [3, 10]);
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE id >= -1.5 AND id <= 2.0"), [-1, 1, 2]);
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE id = 3.0"), [3]);
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE id = 2.5"), Vec::<i64>::new());
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE name = 'carol is far too long'"), Vec::<i64>::new());
+    assert_eq!(ids(&mut db, "SELECT id FROM people WHERE name < 'carol is far too long'"), [1, 2, 3]);
+
+    db.execute("CREATE INDEX by_name ON people (name)").unwrap();
+    assert_eq!(sorted_ids(&mut db, "SELECT id FROM people WHERE name > 'bob is far too long'"), [-1, 2, 3, 10]);
+    let text = db.execute("EXPLAIN SELECT id FROM people WHERE name = 'carol is far too long'").unwrap();
+    assert!(text.to_string().starts_with("plan: no rows: "));
+}
+
+#[test]
+fn reports_bad_statements() {
+    let (_dir, mut db) = people();
+
+    let sql_error = |result: rustdb::Result<QueryResult>| matches!(result, Err(Error::Sql(_)));
+    assert!(sql_error(db.execute("SELEC * FROM people")));
+    assert!(sql_error(db.execute("SELECT nope FROM people")));
+    assert!(sql_error(db.execute("SELECT * FROM people WHERE id = 'x'")));
+    assert!(sql_error(db.execute("INSERT INTO people VALUES (4, 'name is far too long', 1, TRUE)")));
+    assert!(sql_error(db.execute("INSERT INTO people (id) VALUES (4)")));
+    assert!(sql_error(db.execute("UPDATE people SET id = 5 WHERE id = 1")));
+    assert!(sql_error(db.execute("SELECT * FROM people WHERE name = 'unterminated")));
+    assert!(matches!(db.execute("SELECT * FROM nobody"), Err(Error::TableNotFound(_))));
+    assert!(matches!(
+        db.execute("INSERT INTO people VALUES (1, 'again', 0, TRUE)"),
+        Err(Error::DuplicateKey)
+    ));
+}
+
+#[test]
+fn reports_tables_without_a_b_tree_index() {
+    let (dir, mut db) = people();
+
+    // Swaps the table's files for an LSM table of the same sizes, which has no index to plan with.
+    let info = db.table_info("people").unwrap().clone();
+    std::fs::remove_file(dir.path().join(&info.datafile)).unwrap();
+    std::fs::remove_file(dir.path().join(&info.indexfile)).unwrap();
+    let mut lsm = TableOptions::new();
+    lsm.key_size(info.keysize).record_size(info.recordsize).engine(Engine::Lsm);
+    drop(lsm.open(dir.path().join(&info.datafile)).unwrap());
+
+    assert!(matches!(db.execute("SELECT * FROM people"), Err(Error::Sql(_))));
+    assert!(matches!(db.execute("INSERT INTO people VALUES (4, 'fay', 1, TRUE)"), Err(Error::Sql(_))));
+}
+
+fn explain(db: &mut Database, sql: &str) -> (String, u64, u64) {
+    match db.execute(sql).unwrap() {
+        QueryResult::Explain {
+            plan,
+            actual_rows,
+            actual_reads,
+            ..
+        } => (plan, actual_rows, actual_reads),
+        other => panic!("expected a plan, got {:?}", other),
+    }
+}
+
+#[test]
+fn explain_picks_an_access_path() {
+    let dir = tempdir().unwrap();
+    let mut db = Database::open(dir.path()).unwrap();
+    db.execute("CREATE TABLE items (id INT, kind INT, price FLOAT)").unwrap();
+    let values: Vec<String> = (0..5000)
+        .map(|i| format!("({}, {}, {})", i, i % 500, i as f64 / 2.0))
+        .collect();
+    db.execute(&format!("INSERT INTO items VALUES {}", values.join(", "))).unwrap();
+
+    let (plan, rows, reads) = explain(&mut db, "EXPLAIN SELECT * FROM items WHERE id = 42");
+    assert_eq!((plan.as_str(), rows), ("point lookup on id", 1));
+    assert!(reads > 0);
+
+    let (plan, rows, _) = explain(&mut db, "EXPLAIN SELECT * FROM items WHERE id BETWEEN 10 AND 19");
+    assert_eq!((plan.as_str(), rows), ("range scan on id", 10));
+
+    let (plan, rows, full_reads) = explain(&mut db, "EXPLAIN DELETE FROM items WHERE kind = 7");
+    assert_eq!((plan.as_str(), rows), ("full scan", 10));
+
+    db.execute("CREATE INDEX by_kind ON items (kind)").unwrap();
+    let (plan, rows, index_reads) = explain(&mut db, "EXPLAIN UPDATE items SET price = 0 WHERE kind = 7");
+    assert_eq!((plan.as_str(), rows), ("index scan using by_kind on kind", 10));
+    assert!(index_reads < full_reads);
+
+    // EXPLAIN only looks the rows up.
+    assert_eq!(rows_of(&mut db, "SELECT id FROM items WHERE kind = 7 AND price = 0"), 0);
+    let text = db.execute("EXPLAIN SELECT id FROM items WHERE kind = 7").unwrap().to_string();
+    assert!(text.starts_with("plan: index scan using by_kind on kind\nestimated: "));
+    assert!(text.contains("\nactual: 10 row(s), "));
+
+    assert!(matches!(
+        db.execute("EXPLAIN INSERT INTO items VALUES (1000, 1, 1)"),
+        Err(Error::Sql(_))
+    ));
+}
+
+fn rows_of(db: &mut Database, sql: &str) -> usize {
+    rows(db, sql).len()
+}
+
+// Row order depends on the access path, which for tables this small is a full scan.
+fn sorted_ids(db: &mut Database, sql: &str) -> Vec<i64> {
+    let mut ids = ids(db, sql);
+    ids.sort();
+    ids
+}
+
+#[test]
+fn secondary_indexes_follow_changes() {
+    let (dir, mut db) = people();
+    db.execute("CREATE INDEX by_name ON people (name)").unwrap();
+    db.execute("CREATE INDEX by_score ON people (score)").unwrap();
+    assert!(matches!(
+        db.execute("CREATE INDEX by_name ON people (active)"),
+        Err(Error::TableExists(_))
+    ));
+    assert!(matches!(db.execute("CREATE INDEX nope ON people (missing)"), Err(Error::Sql(_))));
+
+    assert_eq!(sorted_ids(&mut db, "SELECT id FROM people WHERE name = 'carol'"), [3]);
+    assert_eq!(sorted_ids(&mut db, "SELECT id FROM people WHERE score >= 3 AND score < 9"), [1, 3]);
+
+    db.execute("INSERT INTO people VALUES (7, 'frank', 4.5, TRUE)").unwrap();
+    db.execute("UPDATE people SET name = 'caroline', score = 8 WHERE id = 3").unwrap();
+    db.execute("DELETE FROM people WHERE name = 'alice'").unwrap();
+
+    assert_eq!(sorted_ids(&mut db, "SELECT id FROM people WHERE name = 'carol'"), Vec::<i64>::new());
+    assert_eq!(sorted_ids(&mut db, "SELECT id FROM people WHERE name = 'caroline'"), [3]);
+    assert_eq!(sorted_ids(&mut db, "SELECT id FROM people WHERE name > 'd'"), [-1, 7, 10]);
+    assert_eq!(sorted_ids(&mut db, "SELECT id FROM people WHERE score BETWEEN 3 AND 9"), [3, 7]);
+
+    // The index files are kept with the table.
+    drop(db);
+    let mut db = Database::open(dir.path()).unwrap();
+    assert_eq!(sorted_ids(&mut db, "SELECT id FROM people WHERE name >= 'a'"), [-1, 2, 3, 7, 10]);
+    db.rename_table("people", "staff").unwrap();
+    assert_eq!(sorted_ids(&mut db, "SELECT id FROM staff WHERE score < 0"), [2]);
+    db.drop_table("staff").unwrap();
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn aggregates_and_groups() {
+    let (_dir, mut db) = people();
+
+    assert_eq!(
+        rows(&mut db, "SELECT COUNT(*), MIN(id), MAX(id) FROM people"),
+        [[Value::Int(5), Value::Int(-1), Value::Int(10)]]
+    );
+    assert_eq!(
+        rows(&mut db, "SELECT count(id), sum(id), avg(score), min(name), max(score) FROM people WHERE id > 0"),
+        [[
+            Value::Int(4),
+            Value::Int(16),
+            Value::Float(4.3125),
+            Value::Text("alice".into()),
+            Value::Float(9.25)
+        ]]
+    );
+    assert_eq!(
+        rows(&mut db, "SELECT active, COUNT(*), SUM(score) FROM people GROUP BY active"),
+        [
+            [Value::Bool(false), Value::Int(2), Value::Float(-1.5)],
+            [Value::Bool(true), Value::Int(3), Value::Float(19.75)]
+        ]
+    );
+
+    // Aggregates of nothing: COUNT is zero, the rest are NULL, and there are no groups.
+    assert_eq!(
+        rows(&mut db, "SELECT COUNT(*), SUM(score), MAX(id) FROM people WHERE id > 100"),
+        [[Value::Int(0), Value::Null, Value::Null]]
+    );
+    assert_eq!(rows_of(&mut db, "SELECT active FROM people WHERE id > 100 GROUP BY active"), 0);
+
+    let result = db.execute("SELECT active, count(*) FROM people GROUP BY active").unwrap();
+    assert_eq!(result.to_string(), "active | count(*)\nfalse | 2\ntrue | 3");
+
+    let sql_error = |result: rustdb::Result<QueryResult>| matches!(result, Err(Error::Sql(_)));
+    assert!(sql_error(db.execute("SELECT name, COUNT(*) FROM people")));
+    assert!(sql_error(db.execute("SELECT SUM(name) FROM people")));
+    assert!(sql_error(db.execute("SELECT * FROM people GROUP BY active")));
+    assert!(sql_error(db.execute("SELECT MEDIAN(score) FROM people")));
+}
@@ -0,0 +1,1113 @@
+use rustdb::{Backend, Compression, Engine, Error, IndexKind, Table, TableOptions, WriteBatch, WriteOutcome};
+use std::collections::BTreeMap;
+use tempfile::tempdir;
+
+fn padded(record: &[u8], recordsize: usize) -> Vec<u8> {
+    let mut fixed = vec![0u8; recordsize];
+    fixed[..record.len()].copy_from_slice(record);
+    fixed
+}
+
+#[test]
+fn add_search_and_update() {
+    let mut table = Table::in_memory(16, 4).unwrap();
+
+    table.add_record(b"0001", b"first").unwrap();
+    table.add_record(b"0002", b"second").unwrap();
+    assert_eq!(table.search_record(b"0001").unwrap(), Some(padded(b"first", 16)));
+
+    table.update_record(b"0001", b"changed").unwrap();
+    assert_eq!(table.search_record(b"0001").unwrap(), Some(padded(b"changed", 16)));
+    assert_eq!(table.search_record(b"0003").unwrap(), None);
+}
+
+#[test]
+fn rejects_duplicates_missing_keys_and_oversized_input() {
+    let mut table = Table::in_memory(8, 4).unwrap();
+    table.add_record(b"key", b"value").unwrap();
+
+    assert!(matches!(table.add_record(b"key", b"again"), Err(Error::DuplicateKey)));
+    assert!(matches!(table.update_record(b"nope", b"value"), Err(Error::KeyNotFound)));
+    assert!(matches!(
+        table.add_record(b"too long", b"value"),
+        Err(Error::KeyTooLarge { len: 8, max: 4 })
+    ));
+    assert!(matches!(
+        table.add_record(b"k2", b"much too long"),
+        Err(Error::RecordTooLarge { len: 13, max: 8 })
+    ));
+}
+
+#[test]
+fn options_require_sizes() {
+    assert!(matches!(
+        TableOptions::new().key_size(4).open_in_memory(),
+        Err(Error::InvalidOptions(_))
+    ));
+    assert!(matches!(
+        TableOptions::new().key_size(200).record_size(8).page_size(256).open_in_memory(),
+        Err(Error::PageTooSmall { .. })
+    ));
+}
+
+#[test]
+fn records_are_visited_in_key_order() {
+    let mut table = TableOptions::new().key_size(4).record_size(8).page_size(256).open_in_memory().unwrap();
+    for i in (0..500u32).rev() {
+        table.add_record(format!("{:04}", i).as_bytes(), b"v").unwrap();
+    }
+
+    let mut keys = Vec::new();
+    table
+        .for_each_record(|key, _| {
+            keys.push(key.to_vec());
+            Ok(())
+        })
+        .unwrap();
+
+    assert_eq!(keys.len(), 500);
+    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn reopens_from_disk_with_either_backend() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("items.dat");
+
+    for backend in [Backend::File, Backend::Mmap] {
+        let mut options = TableOptions::new();
+        options.key_size(4).record_size(16).page_size(512).backend(backend);
+
+        {
+            let mut table = options.open(&path).unwrap();
+            for i in 0..1000u32 {
+                let key = format!("{:04}", i);
+                if table.search_record(key.as_bytes()).unwrap().is_none() {
+                    table.add_record(key.as_bytes(), key.as_bytes()).unwrap();
+                }
+            }
+        }
+
+        let mut table = options.open(&path).unwrap();
+        assert_eq!(table.index().unwrap().len(), 1000);
+        for i in 0..1000u32 {
+            let key = format!("{:04}", i);
+            assert_eq!(table.search_record(key.as_bytes()).unwrap(), Some(padded(key.as_bytes(), 16)));
+        }
+    }
+}
+
+#[test]
+fn rebuilds_a_missing_index() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("items.dat");
+
+    {
+        let mut table = Table::create(&path, 8, 4).unwrap();
+        table.add_record(b"a", b"1").unwrap();
+        table.add_record(b"b", b"2").unwrap();
+    }
+    std::fs::remove_file(dir.path().join("items.dat.ndx")).unwrap();
+
+    let mut table = Table::create(&path, 8, 4).unwrap();
+    assert_eq!(table.search_record(b"b").unwrap(), Some(padded(b"2", 8)));
+}
+
+#[test]
+fn reopens_with_sizes_from_the_datafile_header() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("items.dat");
+
+    {
+        let mut table = TableOptions::new().key_size(4).record_size(12).page_size(512).open(&path).unwrap();
+        table.add_record(b"0001", b"one").unwrap();
+    }
+
+    let mut table = Table::open(&path).unwrap();
+    assert_eq!((table.keysize(), table.recordsize()), (4, 12));
+    assert_eq!(table.index().unwrap().page_size(), 512);
+    assert_eq!(table.search_record(b"0001").unwrap(), Some(padded(b"one", 12)));
+    drop(table);
+
+    assert!(matches!(Table::create(&path, 16, 4), Err(Error::InvalidOptions(_))));
+    assert!(matches!(Table::create(&path, 12, 8), Err(Error::InvalidOptions(_))));
+    assert!(matches!(Table::open(dir.path().join("missing.dat")), Err(Error::Io(_))));
+}
+
+#[test]
+fn rejects_datafiles_with_a_bad_header() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("items.dat");
+    drop(Table::create(&path, 8, 4).unwrap());
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[4] = 99;
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(matches!(Table::open(&path), Err(Error::VersionMismatch { found: 99, .. })));
+
+    bytes[0] = b'X';
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(matches!(Table::open(&path), Err(Error::Corruption(_))));
+}
+
+#[test]
+fn deletes_keep_the_tree_balanced() {
+    let mut table = TableOptions::new().key_size(4).record_size(4).page_size(128).open_in_memory().unwrap();
+    let mut expected = std::collections::BTreeMap::new();
+
+    let mut seed = 7u64;
+    for step in 0..4000 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let key = format!("{:04}", (seed >> 33) % 600).into_bytes();
+
+        if (seed >> 20).is_multiple_of(3) {
+            let result = table.delete_record(&key);
+            match expected.remove(&key) {
+                Some(_) => result.unwrap(),
+                None => assert!(matches!(result, Err(Error::KeyNotFound))),
+            }
+        } else if expected.insert(key.clone(), key.clone()).is_none() {
+            table.add_record(&key, &key).unwrap();
+        }
+
+        if step % 250 == 0 {
+            table.verify().unwrap();
+        }
+    }
+
+    table.verify().unwrap();
+    assert_eq!(table.count(), expected.len() as u64);
+    for (key, record) in &expected {
+        assert_eq!(table.search_record(key).unwrap().as_ref(), Some(record));
+    }
+
+    // Both ends of the tree are one root-to-leaf path away.
+    let reads = table.index().unwrap().node_reads();
+    assert_eq!(table.min_key().unwrap().as_ref(), expected.keys().next());
+    let min_reads = table.index().unwrap().node_reads() - reads;
+    assert_eq!(table.max_key().unwrap().as_ref(), expected.keys().next_back());
+    assert_eq!(table.index().unwrap().node_reads() - reads, 2 * min_reads);
+    assert!(min_reads < 8);
+}
+
+#[test]
+fn deleted_records_stay_deleted_after_reopening() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("items.dat");
+
+    {
+        let mut table = Table::create(&path, 8, 4).unwrap();
+        for key in [b"a", b"b", b"c"] {
+            table.add_record(key, b"v").unwrap();
+        }
+        table.delete_record(b"b").unwrap();
+        table.delete_record(b"a").unwrap();
+        table.add_record(b"a", b"again").unwrap();
+    }
+
+    let mut table = Table::open(&path).unwrap();
+    assert_eq!(table.search_record(b"a").unwrap(), Some(padded(b"again", 8)));
+    assert_eq!(table.search_record(b"b").unwrap(), None);
+    assert_eq!(table.stats().unwrap().records, 2);
+    assert_eq!(table.stats().unwrap().deleted, 2);
+    drop(table);
+
+    std::fs::remove_file(dir.path().join("items.dat.ndx")).unwrap();
+    let mut table = Table::open(&path).unwrap();
+    table.verify().unwrap();
+    assert_eq!(table.search_record(b"b").unwrap(), None);
+    assert_eq!(table.stats().unwrap().deleted, 2);
+}
+
+#[test]
+fn range_scans_respect_their_bounds() {
+    use std::ops::Bound::{Excluded, Included, Unbounded};
+
+    let mut table = TableOptions::new().key_size(4).record_size(4).page_size(128).open_in_memory().unwrap();
+    for i in (0..300u32).step_by(3) {
+        table.add_record(format!("{:04}", i).as_bytes(), b"v").unwrap();
+    }
+
+    let keys = |table: &mut rustdb::Table, lower: std::ops::Bound<&[u8]>, upper: std::ops::Bound<&[u8]>| {
+        table
+            .scan_range((lower, upper))
+            .unwrap()
+            .map(|entry| String::from_utf8(entry.unwrap().0).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(keys(&mut table, Included(b"0010"), Included(b"0021")), ["0012", "0015", "0018", "0021"]);
+    assert_eq!(keys(&mut table, Excluded(b"0012"), Excluded(b"0021")), ["0015", "0018"]);
+    assert_eq!(keys(&mut table, Excluded(b"0290"), Unbounded), ["0291", "0294", "0297"]);
+    assert_eq!(keys(&mut table, Unbounded, Excluded(b"0006")), ["0000", "0003"]);
+    assert_eq!(keys(&mut table, Included(b"0500"), Unbounded), Vec::<String>::new());
+    assert_eq!(table.scan().unwrap().count(), 100);
+}
+
+#[test]
+fn rank_and_select_follow_inserts_and_deletes() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("ranked.dat");
+    let mut options = TableOptions::new();
+    options.key_size(4).record_size(4).page_size(160).order_statistics(true);
+    let mut table = options.open(&path).unwrap();
+    let mut expected = std::collections::BTreeSet::new();
+
+    let mut seed = 11u64;
+    for step in 0..3000 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let key = format!("{:04}", (seed >> 33) % 800).into_bytes();
+
+        if (seed >> 20).is_multiple_of(3) {
+            if expected.remove(&key) {
+                table.delete_record(&key).unwrap();
+            }
+        } else if expected.insert(key.clone()) {
+            table.add_record(&key, &key).unwrap();
+        }
+
+        if step % 300 == 0 {
+            table.verify().unwrap();
+        }
+    }
+
+    let check = |table: &mut Table| {
+        table.verify().unwrap();
+        for (position, key) in expected.iter().enumerate() {
+            assert_eq!(table.rank(key).unwrap(), position as u64);
+            let (found, record) = table.record_at(position as u64).unwrap().unwrap();
+            assert_eq!((&found, &record), (key, key));
+        }
+        assert_eq!(table.record_at(expected.len() as u64).unwrap(), None);
+        assert_eq!(table.rank(b"9999").unwrap(), expected.len() as u64);
+        let missing = (0..800).map(|k| format!("{:04}", k).into_bytes()).find(|k| !expected.contains(k)).unwrap();
+        assert_eq!(table.rank(&missing).unwrap(), expected.range(..missing.clone()).count() as u64);
+    };
+    check(&mut table);
+
+    // A rebuilt index keeps the counts, since the datafile remembers they were asked for.
+    drop(table);
+    std::fs::remove_file(dir.path().join("ranked.dat.ndx")).unwrap();
+    let mut table = Table::open(&path).unwrap();
+    check(&mut table);
+
+    let mut plain = Table::in_memory(4, 4).unwrap();
+    plain.add_record(b"a", b"v").unwrap();
+    assert!(matches!(plain.rank(b"a"), Err(Error::InvalidOptions(_))));
+    assert!(matches!(plain.record_at(0), Err(Error::InvalidOptions(_))));
+}
+
+#[test]
+fn prefix_scans_stop_at_the_first_key_without_the_prefix() {
+    let mut table = TableOptions::new().key_size(8).record_size(4).page_size(128).open_in_memory().unwrap();
+    let keys: [&[u8]; 9] = [
+        b"acme/al", b"acme/bo", b"acme", b"acmex", b"beta/al", b"ac", b"a\xff\xff", b"b", b"\xff\xff/x",
+    ];
+    for key in keys {
+        table.add_record(key, b"v").unwrap();
+    }
+
+    let scan = |table: &mut Table, prefix: &[u8]| {
+        table
+            .scan_prefix(prefix)
+            .unwrap()
+            .map(|entry| {
+                let key = entry.unwrap().0;
+                let end = key.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+                key[..end].to_vec()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(scan(&mut table, b"acme/"), [b"acme/al".to_vec(), b"acme/bo".to_vec()]);
+    assert_eq!(scan(&mut table, b"acme"), [&b"acme"[..], b"acme/al", b"acme/bo", b"acmex"]);
+    assert_eq!(scan(&mut table, b"a\xff"), [b"a\xff\xff".to_vec()]);
+    assert_eq!(scan(&mut table, b"\xff\xff"), [b"\xff\xff/x".to_vec()]);
+    assert_eq!(scan(&mut table, b"zz").len(), 0);
+    assert_eq!(scan(&mut table, b"").len(), keys.len());
+
+    // Keys are zero-padded: "ac" is stored as "ac" followed by zeros, so it matches "ac\0".
+    assert_eq!(scan(&mut table, b"ac\0"), [b"ac".to_vec()]);
+    assert_eq!(scan(&mut table, b"acme/al\0\0"), [b"acme/al".to_vec()]);
+    assert_eq!(scan(&mut table, b"acme/al\0x").len(), 0);
+}
+
+#[test]
+fn scans_run_backwards_and_from_both_ends() {
+    use std::ops::Bound::{Excluded, Included, Unbounded};
+
+    let mut table = TableOptions::new().key_size(4).record_size(4).page_size(128).open_in_memory().unwrap();
+    let mut expected = std::collections::BTreeSet::new();
+    for i in (0..400u32).step_by(3) {
+        let key = format!("{:04}", i).into_bytes();
+        table.add_record(&key, b"v").unwrap();
+        expected.insert(key);
+    }
+
+    let latest: Vec<_> = table.scan().unwrap().rev().take(3).map(|entry| entry.unwrap().0).collect();
+    assert_eq!(latest, [b"0399".to_vec(), b"0396".to_vec(), b"0393".to_vec()]);
+
+    let key = |n: u64| format!("{:04}", n).into_bytes();
+    let mut seed = 5u64;
+    for _ in 0..200 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let (a, b) = (key((seed >> 40) % 420), key((seed >> 20) % 420));
+        let bound = |key: &Vec<u8>, pick: u64| match pick % 3 {
+            0 => Included(key.clone()),
+            1 => Excluded(key.clone()),
+            _ => Unbounded,
+        };
+        let (lower, upper) = (bound(&a, seed >> 10), bound(&b, seed >> 13));
+
+        // Empty or inverted ranges are skipped, since BTreeSet::range rejects some of them.
+        let valid = match (&lower, &upper) {
+            (Included(l) | Excluded(l), Included(u) | Excluded(u)) => l < u,
+            _ => true,
+        };
+        if !valid {
+            continue;
+        }
+        let want: Vec<_> = expected.range((lower.clone(), upper.clone())).cloned().collect();
+
+        // Take from the front or the back at random until the ends meet.
+        let range = (lower.as_ref().map(Vec::as_slice), upper.as_ref().map(Vec::as_slice));
+        let mut scan = table.scan_range(range).unwrap();
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        let mut pick = seed;
+        loop {
+            pick = pick.rotate_left(7) ^ 0x9e37;
+            let entry = if pick & 1 == 0 { scan.next() } else { scan.next_back() };
+            match entry {
+                Some(entry) if pick & 1 == 0 => front.push(entry.unwrap().0),
+                Some(entry) => back.push(entry.unwrap().0),
+                None => break,
+            }
+        }
+        assert!(scan.next().is_none() && scan.next_back().is_none());
+        back.reverse();
+        front.extend(back);
+        assert_eq!(front, want);
+    }
+}
+
+#[test]
+fn conditional_writes_report_what_they_did() {
+    let mut table = Table::in_memory(8, 4).unwrap();
+
+    assert_eq!(table.upsert(b"a", b"one").unwrap(), WriteOutcome::Inserted);
+    assert_eq!(table.upsert(b"a", b"two").unwrap(), WriteOutcome::Replaced);
+    assert_eq!(table.search_record(b"a").unwrap(), Some(padded(b"two", 8)));
+
+    assert_eq!(table.insert_if_absent(b"b", b"new").unwrap(), WriteOutcome::Inserted);
+    assert_eq!(
+        table.insert_if_absent(b"a", b"three").unwrap(),
+        WriteOutcome::Kept(padded(b"two", 8))
+    );
+    assert_eq!(table.search_record(b"a").unwrap(), Some(padded(b"two", 8)));
+
+    assert_eq!(table.compare_and_swap(b"c", b"", b"x").unwrap(), WriteOutcome::Missing);
+    assert_eq!(
+        table.compare_and_swap(b"a", b"one", b"four").unwrap(),
+        WriteOutcome::Kept(padded(b"two", 8))
+    );
+    assert_eq!(table.compare_and_swap(b"a", b"two", b"four").unwrap(), WriteOutcome::Replaced);
+    assert_eq!(table.search_record(b"a").unwrap(), Some(padded(b"four", 8)));
+
+    assert_eq!(table.count(), 2);
+    assert_eq!(table.stats().unwrap().records, 2);
+    table.verify().unwrap();
+}
+
+#[test]
+fn conditional_writes_descend_the_index_once() {
+    let mut options = TableOptions::new();
+    options.key_size(8).record_size(8).page_size(256).order_statistics(true);
+    let mut table = options.open_in_memory().unwrap();
+    for i in 0..2000u32 {
+        table.add_record(format!("{:08}", i * 2).as_bytes(), b"x").unwrap();
+    }
+
+    let reads = table.index().unwrap().node_reads();
+    table.search_record(b"00001000").unwrap();
+    let descent = table.index().unwrap().node_reads() - reads;
+
+    let reads = table.index().unwrap().node_reads();
+    assert_eq!(table.upsert(b"00001000", b"y").unwrap(), WriteOutcome::Replaced);
+    assert_eq!(table.index().unwrap().node_reads() - reads, descent);
+
+    // A missing key is looked for all the way down to a leaf, and so is a new key inserted.
+    let reads = table.index().unwrap().node_reads();
+    assert_eq!(table.search_record(b"00001001").unwrap(), None);
+    let descent = table.index().unwrap().node_reads() - reads;
+
+    let reads = table.index().unwrap().node_reads();
+    assert_eq!(table.insert_if_absent(b"00001001", b"z").unwrap(), WriteOutcome::Inserted);
+    assert!(table.index().unwrap().node_reads() - reads <= descent);
+
+    assert_eq!(table.rank(b"00001001").unwrap(), 501);
+    table.verify().unwrap();
+}
+
+#[test]
+fn write_batches_apply_in_key_order_and_survive_reopening() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("batch.dat");
+
+    {
+        let mut options = TableOptions::new();
+        options.key_size(4).record_size(8).page_size(128).order_statistics(true);
+        let mut table = options.open(&path).unwrap();
+        for key in [b"b", b"d", b"f"] {
+            table.add_record(key, b"old").unwrap();
+        }
+
+        let mut batch = WriteBatch::new();
+        for i in (0..200u32).rev() {
+            batch.put(format!("k{:03}", i).as_bytes(), b"new");
+        }
+        batch
+            .put(b"b", b"put")
+            .update(b"d", b"updated")
+            .delete(b"f")
+            .put(b"f", b"back")
+            .put(b"x", b"gone")
+            .delete(b"x")
+            .delete(b"b");
+        assert_eq!(batch.len(), 207);
+        table.write_batch(&batch).unwrap();
+
+        assert_eq!(table.count(), 202);
+        assert_eq!(table.search_record(b"b").unwrap(), None);
+        assert_eq!(table.search_record(b"d").unwrap(), Some(padded(b"updated", 8)));
+        assert_eq!(table.search_record(b"f").unwrap(), Some(padded(b"back", 8)));
+        assert_eq!(table.search_record(b"x").unwrap(), None);
+        assert_eq!(table.rank(b"k100").unwrap(), 102);
+        table.verify().unwrap();
+
+        // Only the 200 new keys were appended; "f" was rewritten in place.
+        let stats = table.stats().unwrap();
+        assert_eq!((stats.records, stats.deleted), (202, 1));
+        assert_eq!(stats.datafile_bytes, 32 + 203 * 13);
+    }
+
+    std::fs::remove_file(dir.path().join("batch.dat.ndx")).unwrap();
+    let mut table = Table::open(&path).unwrap();
+    table.verify().unwrap();
+    assert_eq!(table.count(), 202);
+    assert_eq!(table.record_at(2).unwrap().unwrap().0, b"k000");
+    assert_eq!(table.search_record(b"k199").unwrap(), Some(padded(b"new", 8)));
+}
+
+#[test]
+fn failed_write_batches_leave_the_table_untouched() {
+    let mut table = Table::in_memory(8, 4).unwrap();
+    table.add_record(b"a", b"one").unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"b", b"two").update(b"a", b"three").delete(b"c");
+    assert!(matches!(table.write_batch(&batch), Err(Error::KeyNotFound)));
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"b", b"two").put(b"c", b"much too long");
+    assert!(matches!(table.write_batch(&batch), Err(Error::RecordTooLarge { len: 13, max: 8 })));
+
+    assert_eq!(table.count(), 1);
+    assert_eq!(table.search_record(b"a").unwrap(), Some(padded(b"one", 8)));
+    assert_eq!(table.stats().unwrap().records, 1);
+    assert!(WriteBatch::new().is_empty());
+    table.write_batch(&WriteBatch::new()).unwrap();
+}
+
+#[test]
+fn multi_get_shares_index_reads_and_keeps_the_callers_order() {
+    let mut table = TableOptions::new().key_size(8).record_size(8).page_size(256).open_in_memory().unwrap();
+    for i in 0..3000u32 {
+        let key = format!("{:08}", i * 2);
+        table.add_record(key.as_bytes(), &key.as_bytes()[4..]).unwrap();
+    }
+
+    let keys: Vec<String> = (1000..1100u32).rev().map(|i| format!("{:08}", i)).collect();
+    let mut lookups: Vec<&[u8]> = keys.iter().map(|key| key.as_bytes()).collect();
+    lookups.push(b"00001000");
+    lookups.push(b"99999999");
+
+    let reads = table.index().unwrap().node_reads();
+    let records = table.multi_get(&lookups).unwrap();
+    let batched_reads = table.index().unwrap().node_reads() - reads;
+
+    assert_eq!(records.len(), lookups.len());
+    for (key, record) in lookups.iter().zip(&records) {
+        let expected = table.search_record(key).unwrap();
+        assert_eq!(record, &expected);
+    }
+    assert_eq!(records[0], None);
+    assert_eq!(records[1], Some(padded(b"1098", 8)));
+    assert_eq!(records[100], records[99]);
+    assert_eq!(records[101], None);
+
+    let reads = table.index().unwrap().node_reads();
+    for key in &lookups {
+        table.search_record(key).unwrap();
+    }
+    assert!(batched_reads * 5 < table.index().unwrap().node_reads() - reads);
+
+    assert!(table.multi_get(&[]).unwrap().is_empty());
+    assert!(matches!(
+        table.multi_get(&[b"000000001"]),
+        Err(Error::KeyTooLarge { len: 9, max: 8 })
+    ));
+}
+
+#[test]
+fn leaves_store_the_shared_prefix_of_their_keys_once() {
+    let mut options = TableOptions::new();
+    options.key_size(64).record_size(4).page_size(1024).order_statistics(true);
+    let mut table = options.open_in_memory().unwrap();
+    let t = table.stats().unwrap().t as u64;
+
+    let tenants = ["archive", "tenants/0001", "zone"];
+    let key = |tenant: u64, user: u64| format!("{}/users/{:08}/profile", tenants[tenant as usize], user).into_bytes();
+    // 52 of these keys fill a leaf. A key without their prefix then fits in neither half of a
+    // split down the middle.
+    let mut full = options.open_in_memory().unwrap();
+    for user in 0..52 {
+        full.add_record(&key(1, user), b"v").unwrap();
+    }
+    assert_eq!(full.stats().unwrap().index_pages, 2);
+    full.add_record(&key(0, 0), b"v").unwrap();
+    full.add_record(&key(2, 0), b"v").unwrap();
+    full.verify().unwrap();
+    assert_eq!(full.record_at(0).unwrap().unwrap().0, padded(&key(0, 0), 64));
+    assert_eq!(full.record_at(53).unwrap().unwrap().0, padded(&key(2, 0), 64));
+
+    let mut expected = std::collections::BTreeSet::new();
+    for user in 0..4000 {
+        expected.insert(key(1, user));
+        table.add_record(&key(1, user), b"v").unwrap();
+    }
+    table.verify().unwrap();
+
+    // Without compression each page would hold at most 2t - 1 keys; with it they hold at
+    // least half as many again, even with leaves split in the middle.
+    let pages = table.stats().unwrap().index_pages;
+    assert!(pages * (2 * t - 1) * 3 < 4000 * 2, "{} pages for t = {}", pages, t);
+
+    // Keys of other tenants land at both ends and drop the prefix of the leaves there.
+    let mut seed = 5u64;
+    for step in 0..3000 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let k = key((seed >> 40) % 3, (seed >> 20) % 5000);
+        if (seed >> 10).is_multiple_of(4) {
+            if expected.remove(&k) {
+                table.delete_record(&k).unwrap();
+            }
+        } else if expected.insert(k.clone()) {
+            table.add_record(&k, b"v").unwrap();
+        }
+        if step % 500 == 0 {
+            table.verify().unwrap();
+        }
+    }
+
+    table.verify().unwrap();
+    let keys: Vec<Vec<u8>> = table.scan().unwrap().map(|entry| entry.unwrap().0).collect();
+    assert_eq!(keys, expected.iter().map(|key| padded(key, 64)).collect::<Vec<_>>());
+    let middle = expected.iter().nth(2500).unwrap();
+    assert_eq!(table.rank(middle).unwrap(), 2500);
+}
+
+#[test]
+fn compressed_records_are_read_back_transparently() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("notes.dat");
+    let note = |i: u32| format!("note {} of the weekly report: nothing to report, nothing to report", i).into_bytes();
+
+    let plain_bytes = {
+        let mut table = TableOptions::new().key_size(8).record_size(256).open_in_memory().unwrap();
+        for i in 0..200 {
+            table.add_record(format!("{:08}", i).as_bytes(), &note(i)).unwrap();
+        }
+        table.stats().unwrap().datafile_bytes
+    };
+
+    {
+        let mut options = TableOptions::new();
+        options.key_size(8).record_size(256).compression(Compression::Lz4);
+        let mut table = options.open(&path).unwrap();
+        for i in 0..200 {
+            table.add_record(format!("{:08}", i).as_bytes(), &note(i)).unwrap();
+        }
+        let stats = table.stats().unwrap();
+        assert_eq!(stats.compression, Compression::Lz4);
+        assert!(stats.datafile_bytes * 3 < plain_bytes, "{} vs {}", stats.datafile_bytes, plain_bytes);
+
+        // A record that no longer fits its entry moves to the end of the datafile.
+        let long: Vec<u8> = (0..=255u8).map(|b| b.wrapping_mul(151)).collect();
+        table.update_record(b"00000007", &long).unwrap();
+        table.update_record(b"00000008", b"short").unwrap();
+        assert_eq!(table.upsert(b"00000009", &long).unwrap(), WriteOutcome::Replaced);
+        table.delete_record(b"00000010").unwrap();
+        assert_eq!(table.stats().unwrap().deleted, 3);
+        table.verify().unwrap();
+
+        assert_eq!(table.search_record(b"00000007").unwrap(), Some(long.clone()));
+        assert_eq!(table.search_record(b"00000008").unwrap(), Some(padded(b"short", 256)));
+        let scan = table.scan_range(&b"00000006"[..]..&b"00000012"[..]).unwrap();
+        let records: Vec<_> = scan.map(|entry| entry.unwrap()).collect();
+        let keys: Vec<_> = records.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(keys, [&b"00000006"[..], b"00000007", b"00000008", b"00000009", b"00000011"]);
+        assert_eq!(records[0].1, padded(&note(6), 256));
+        assert_eq!(records[3].1, long);
+    }
+
+    // The codec comes from the datafile, and a rebuilt index keeps the moved records.
+    std::fs::remove_file(dir.path().join("notes.dat.ndx")).unwrap();
+    let mut table = Table::open(&path).unwrap();
+    table.verify().unwrap();
+    assert_eq!(table.stats().unwrap().compression, Compression::Lz4);
+    assert_eq!(table.stats().unwrap().records, 199);
+    assert_eq!(table.search_record(b"00000009").unwrap().unwrap()[..4], [0, 151, 46, 197]);
+    assert_eq!(table.search_record(b"00000199").unwrap(), Some(padded(&note(199), 256)));
+    assert_eq!(table.search_record(b"00000010").unwrap(), None);
+}
+
+#[test]
+fn encrypted_tables_hide_their_contents_and_need_their_key() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("people.dat");
+    let index_path = dir.path().join("people.dat.ndx");
+    let key = [42u8; 32];
+    let email = |i: u32| format!("person{:04}@example.com", i).into_bytes();
+
+    let mut options = TableOptions::new();
+    options.key_size(16).record_size(32).page_size(512).encryption_key(key);
+    {
+        let mut table = options.open(&path).unwrap();
+        for i in 0..300 {
+            table.add_record(format!("user{:04}", i).as_bytes(), &email(i)).unwrap();
+        }
+        table.update_record(b"user0007", b"changed@example.com").unwrap();
+        table.delete_record(b"user0008").unwrap();
+        table.verify().unwrap();
+        assert!(table.stats().unwrap().encrypted);
+        assert_eq!(table.search_record(b"user0007").unwrap(), Some(padded(b"changed@example.com", 32)));
+    }
+
+    for file in [&path, &index_path] {
+        let bytes = std::fs::read(file).unwrap();
+        for needle in [&b"example.com"[..], b"user00"] {
+            assert!(!bytes.windows(needle.len()).any(|w| w == needle), "{:?} in {}", needle, file.display());
+        }
+    }
+
+    let mut wrong = options.clone();
+    wrong.encryption_key([43u8; 32]);
+    assert!(matches!(wrong.open(&path), Err(Error::WrongKey)));
+    assert!(matches!(Table::open(&path), Err(Error::InvalidOptions(_))));
+
+    // A rebuilt index is encrypted too, and a moved record keeps its latest value.
+    std::fs::remove_file(&index_path).unwrap();
+    let mut table = options.open(&path).unwrap();
+    table.verify().unwrap();
+    assert_eq!(table.stats().unwrap().records, 299);
+    assert_eq!(table.search_record(b"user0007").unwrap(), Some(padded(b"changed@example.com", 32)));
+    assert_eq!(table.search_record(b"user0008").unwrap(), None);
+    let records: Vec<_> = table.scan_prefix(b"user029").unwrap().map(|entry| entry.unwrap().1).collect();
+    assert_eq!(records, (290..300).map(|i| padded(&email(i), 32)).collect::<Vec<_>>());
+    drop(table);
+    let bytes = std::fs::read(&index_path).unwrap();
+    assert!(!bytes.windows(6).any(|w| w == b"user00"));
+
+    // Encryption and compression combine, and a plain table refuses a key.
+    let mut both = options.clone();
+    both.compression(Compression::Lz4);
+    let mut table = both.open(dir.path().join("both.dat")).unwrap();
+    table.add_record(b"k", &[b'a'; 32]).unwrap();
+    table.update_record(b"k", b"b").unwrap();
+    assert_eq!(table.search_record(b"k").unwrap(), Some(padded(b"b", 32)));
+    Table::create(dir.path().join("plain.dat"), 8, 4).unwrap();
+    let keyed = TableOptions::new().encryption_key(key).open(dir.path().join("plain.dat"));
+    assert!(matches!(keyed, Err(Error::InvalidOptions(msg)) if msg.contains("not encrypted")));
+}
+
+#[test]
+fn bloom_filters_keep_most_missing_keys_away_from_the_index() {
+    let key = |i: u32| format!("key{:05}", i).into_bytes();
+    let mut plain = TableOptions::new().key_size(8).record_size(8).page_size(512).open_in_memory().unwrap();
+    let mut filtered = TableOptions::new()
+        .key_size(8)
+        .record_size(8)
+        .page_size(512)
+        .bloom_filter(10)
+        .open_in_memory()
+        .unwrap();
+    for i in (0..4000).step_by(2) {
+        plain.add_record(&key(i), b"v").unwrap();
+        filtered.add_record(&key(i), b"v").unwrap();
+    }
+
+    let reads = |table: &mut Table| {
+        let before = table.index().unwrap().node_reads();
+        for i in (1..4000).step_by(2) {
+            assert_eq!(table.search_record(&key(i)).unwrap(), None);
+        }
+        table.index().unwrap().node_reads() - before
+    };
+    let (plain_reads, filtered_reads) = (reads(&mut plain), reads(&mut filtered));
+    assert!(filtered_reads * 10 < plain_reads, "{} vs {}", filtered_reads, plain_reads);
+
+    // About 1% at 10 bits per key. Adding a key looked it up as missing too.
+    let stats = filtered.stats().unwrap().filter.unwrap();
+    assert_eq!(stats.negatives + stats.false_positives, 4000);
+    assert!(stats.false_positive_rate() < 0.03, "{:?}", stats);
+    assert_eq!(plain.stats().unwrap().filter, None);
+
+    for i in (0..4000).step_by(2) {
+        assert!(filtered.search_record(&key(i)).unwrap().is_some());
+    }
+    assert!(matches!(filtered.add_record(&key(0), b"again"), Err(Error::DuplicateKey)));
+    assert!(matches!(filtered.update_record(&key(1), b"v"), Err(Error::KeyNotFound)));
+    assert!(matches!(filtered.delete_record(&key(1)), Err(Error::KeyNotFound)));
+    assert_eq!(filtered.compare_and_swap(&key(1), b"v", b"w").unwrap(), WriteOutcome::Missing);
+    let records = filtered.multi_get(&[&key(3), &key(2), &key(5)]).unwrap();
+    assert_eq!(records, vec![None, Some(padded(b"v", 8)), None]);
+    filtered.verify().unwrap();
+}
+
+#[test]
+fn bloom_filters_are_saved_with_the_index_and_rebuilt_when_stale() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("filtered.dat");
+    let key = |i: u32| format!("key{:05}", i).into_bytes();
+    let mut options = TableOptions::new();
+    options.key_size(8).record_size(8).bloom_filter(10);
+    {
+        let mut table = options.open(&path).unwrap();
+        for i in 0..600 {
+            table.add_record(&key(i), b"v").unwrap();
+        }
+    }
+    assert!(dir.path().join("filtered.dat.blm").exists());
+
+    // Reopening loads the saved filter instead of reading every key out of the index.
+    let mut table = options.open(&path).unwrap();
+    assert_eq!(table.index().unwrap().node_reads(), 0);
+    for i in 0..600 {
+        assert!(table.search_record(&key(i)).unwrap().is_some());
+    }
+
+    // Once half the keys are deleted, the filter is rebuilt without them.
+    for i in 0..300 {
+        table.delete_record(&key(i)).unwrap();
+    }
+    let before = table.stats().unwrap().filter.unwrap();
+    for i in 0..300 {
+        assert_eq!(table.search_record(&key(i)).unwrap(), None);
+    }
+    let after = table.stats().unwrap().filter.unwrap();
+    assert!(after.negatives - before.negatives > 290, "{:?}", after);
+    drop(table);
+
+    // Keys added while the table was opened without a filter make the saved one stale.
+    let mut table = Table::open(&path).unwrap();
+    for i in 1000..1100 {
+        table.add_record(&key(i), b"v").unwrap();
+    }
+    drop(table);
+    let mut table = options.open(&path).unwrap();
+    for i in 1000..1100 {
+        assert!(table.search_record(&key(i)).unwrap().is_some());
+    }
+    table.verify().unwrap();
+
+    assert!(matches!(
+        TableOptions::new().bloom_filter(0).open(&path),
+        Err(Error::InvalidOptions(_))
+    ));
+}
+
+#[test]
+fn hash_index_tables_answer_point_operations_with_one_bucket_read() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("hashed.dat");
+    let key = |i: u32| format!("key{:05}", i).into_bytes();
+    let mut options = TableOptions::new();
+    options.key_size(8).record_size(8).page_size(256).index_kind(IndexKind::Hash);
+    {
+        let mut table = options.open(&path).unwrap();
+        for i in 0..3000 {
+            table.add_record(&key(i), b"v").unwrap();
+        }
+        table.update_record(&key(7), b"changed").unwrap();
+        table.delete_record(&key(8)).unwrap();
+        assert_eq!(table.upsert(&key(9), b"upserted").unwrap(), WriteOutcome::Replaced);
+        let mut batch = WriteBatch::new();
+        batch.put(&key(5000), b"batched").delete(&key(10));
+        table.write_batch(&batch).unwrap();
+        assert!(matches!(table.add_record(&key(0), b"again"), Err(Error::DuplicateKey)));
+        table.verify().unwrap();
+        assert!(table.hash_index().unwrap().global_depth() > 4);
+        assert!(table.index().is_none());
+    }
+
+    // The kind comes from the datafile, and the index is reused.
+    let mut table = Table::open(&path).unwrap();
+    assert_eq!(table.index_kind(), IndexKind::Hash);
+    let stats = table.stats().unwrap();
+    assert_eq!((stats.index_kind, stats.records), (IndexKind::Hash, 2999));
+    for i in [0, 1234, 2999] {
+        let reads = table.hash_index().unwrap().bucket_reads();
+        assert_eq!(table.search_record(&key(i)).unwrap(), Some(padded(b"v", 8)));
+        assert_eq!(table.hash_index().unwrap().bucket_reads() - reads, 1);
+    }
+    assert_eq!(table.search_record(&key(7)).unwrap(), Some(padded(b"changed", 8)));
+    assert_eq!(table.search_record(&key(8)).unwrap(), None);
+    let records = table.multi_get(&[&key(5000), &key(10), &key(9)]).unwrap();
+    assert_eq!(records, vec![Some(padded(b"batched", 8)), None, Some(padded(b"upserted", 8))]);
+
+    // Keys that fall in the same bucket share its read.
+    let keys: Vec<Vec<u8>> = (100..1100).map(key).collect();
+    let key_refs: Vec<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+    let reads = table.hash_index().unwrap().bucket_reads();
+    let records = table.multi_get(&key_refs).unwrap();
+    assert!(records.iter().all(|record| record == &Some(padded(b"v", 8))));
+    let buckets = table.hash_index().unwrap().page_count();
+    assert!(table.hash_index().unwrap().bucket_reads() - reads < buckets);
+
+    // Anything that needs key order is refused; visiting every record is not.
+    assert!(matches!(table.scan(), Err(Error::Unsupported(_))));
+    assert!(matches!(table.min_key(), Err(Error::Unsupported(_))));
+    assert!(matches!(table.rank(&key(1)), Err(Error::Unsupported(_))));
+    let mut visited = 0;
+    table
+        .for_each_record(|_, _| {
+            visited += 1;
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(visited, 2999);
+    drop(table);
+
+    std::fs::remove_file(dir.path().join("hashed.dat.ndx")).unwrap();
+    let mut table = Table::open(&path).unwrap();
+    table.verify().unwrap();
+    assert_eq!(table.count(), 2999);
+
+    // Buckets and the directory are sealed like B-tree nodes.
+    let mut sealed = options.clone();
+    sealed.encryption_key([9u8; 32]);
+    let mut table = sealed.open(dir.path().join("sealed.dat")).unwrap();
+    for i in 0..500 {
+        table.add_record(&key(i), b"v").unwrap();
+    }
+    drop(table);
+    let mut table = sealed.open(dir.path().join("sealed.dat")).unwrap();
+    table.verify().unwrap();
+    assert_eq!(table.search_record(&key(499)).unwrap(), Some(padded(b"v", 8)));
+    let bytes = std::fs::read(dir.path().join("sealed.dat.ndx")).unwrap();
+    assert!(!bytes.windows(5).any(|w| w == b"key00"));
+
+    let mut counted = options.clone();
+    counted.order_statistics(true);
+    assert!(matches!(counted.open(dir.path().join("counted.dat")), Err(Error::InvalidOptions(_))));
+}
+
+#[test]
+fn lsm_tables_match_a_model_through_flushes_and_compactions() {
+    let key = |i: u64| format!("k{:06}", i).into_bytes();
+    let mut options = TableOptions::new();
+    // 17-byte entries, so the memtable holds 40 of them.
+    options.key_size(7).record_size(9).engine(Engine::Lsm).memtable_size(17 * 40);
+    let mut table = options.open_in_memory().unwrap();
+    assert_eq!(table.engine(), Engine::Lsm);
+
+    let mut model: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+    let mut seed = 7u64;
+    for step in 0..6000u64 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let k = key((seed >> 33) % 1500);
+        let record = padded(format!("r{}", step).as_bytes(), 9);
+        match (seed >> 20) % 6 {
+            0 | 1 => match table.add_record(&k, &record) {
+                Ok(()) => assert!(model.insert(k, record).is_none()),
+                Err(Error::DuplicateKey) => assert!(model.contains_key(&k)),
+                Err(err) => panic!("{}", err),
+            },
+            2 => match table.update_record(&k, &record) {
+                Ok(()) => assert!(model.insert(k, record).is_some()),
+                Err(Error::KeyNotFound) => assert!(!model.contains_key(&k)),
+                Err(err) => panic!("{}", err),
+            },
+            3 => match table.delete_record(&k) {
+                Ok(()) => assert!(model.remove(&k).is_some()),
+                Err(Error::KeyNotFound) => assert!(!model.contains_key(&k)),
+                Err(err) => panic!("{}", err),
+            },
+            4 => {
+                let expected = if model.contains_key(&k) { WriteOutcome::Replaced } else { WriteOutcome::Inserted };
+                assert_eq!(table.upsert(&k, &record).unwrap(), expected);
+                model.insert(k, record);
+            }
+            _ => {
+                let other = key((seed >> 40) % 1500);
+                let mut batch = WriteBatch::new();
+                batch.put(&k, &record).put(&other, b"batched");
+                table.write_batch(&batch).unwrap();
+                model.insert(k, record);
+                model.insert(other, padded(b"batched", 9));
+            }
+        }
+    }
+
+    table.verify().unwrap();
+    assert_eq!(table.count(), model.len() as u64);
+    let stats = table.stats().unwrap();
+    assert_eq!((stats.engine, stats.records), (Engine::Lsm, model.len() as u64));
+    assert!(stats.runs.len() > 2, "runs per level: {:?}", stats.runs);
+
+    let expected: Vec<_> = model.iter().map(|(k, r)| (k.clone(), r.clone())).collect();
+    let scanned: Vec<_> = table.scan().unwrap().map(Result::unwrap).collect();
+    assert_eq!(scanned, expected);
+    let reversed: Vec<_> = table.scan().unwrap().rev().map(Result::unwrap).collect();
+    assert_eq!(reversed, expected.iter().rev().cloned().collect::<Vec<_>>());
+
+    let in_range: Vec<_> = table.scan_range(&b"k000200"[..]..&b"k000300"[..]).unwrap().map(Result::unwrap).collect();
+    let expected_range: Vec<_> =
+        expected.iter().filter(|(k, _)| k.as_slice() >= b"k000200" && k.as_slice() < b"k000300").cloned().collect();
+    assert_eq!(in_range, expected_range);
+    let prefixed: Vec<_> = table.scan_prefix(b"k0012").unwrap().map(|e| e.unwrap().0).collect();
+    let expected_prefix: Vec<_> = model.keys().filter(|k| k.starts_with(b"k0012")).cloned().collect();
+    assert_eq!(prefixed, expected_prefix);
+
+    // Both ends of a scan meet in the middle without repeating an entry.
+    let mut scan = table.scan().unwrap();
+    let mut seen = 0;
+    while let Some(entry) = if seen % 2 == 0 { scan.next() } else { scan.next_back() } {
+        entry.unwrap();
+        seen += 1;
+    }
+    drop(scan);
+    assert_eq!(seen, model.len());
+
+    assert_eq!(table.min_key().unwrap().as_ref(), model.keys().next());
+    assert_eq!(table.max_key().unwrap().as_ref(), model.keys().next_back());
+    let probes = [key(3), key(700), key(1499), key(9999)];
+    let probe_refs: Vec<&[u8]> = probes.iter().map(Vec::as_slice).collect();
+    let records = table.multi_get(&probe_refs).unwrap();
+    assert_eq!(records, probes.iter().map(|k| model.get(k).cloned()).collect::<Vec<_>>());
+    // Every key, in reverse and twice over, spread across the memtable and every run.
+    let probes: Vec<Vec<u8>> = (0..1600).rev().chain(0..1600).map(key).collect();
+    let probe_refs: Vec<&[u8]> = probes.iter().map(Vec::as_slice).collect();
+    let records = table.multi_get(&probe_refs).unwrap();
+    assert_eq!(records, probes.iter().map(|k| model.get(k).cloned()).collect::<Vec<_>>());
+
+    assert!(matches!(table.rank(&key(1)), Err(Error::Unsupported(_))));
+    assert!(matches!(table.record_at(0), Err(Error::Unsupported(_))));
+    assert!(table.hash_index().is_none());
+    assert!(table.index().is_none());
+}
+
+#[test]
+fn lsm_tables_replay_their_wal_and_reopen_their_runs() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("events.lsm");
+    let key = |i: u32| format!("ev{:06}", i).into_bytes();
+    let mut options = TableOptions::new();
+    options.key_size(8).record_size(8).engine(Engine::Lsm).memtable_size(17 * 100);
+
+    let mut table = options.open(&path).unwrap();
+    for i in 0..1050 {
+        table.add_record(&key(i), b"v").unwrap();
+    }
+    table.delete_record(&key(3)).unwrap();
+    table.update_record(&key(1040), b"changed").unwrap();
+    assert!(table.stats().unwrap().runs.iter().sum::<usize>() > 0);
+    // Skips the sync on drop; the writes since the last flush are only in the WAL.
+    std::mem::forget(table);
+
+    // A run that a crash left unnamed in the manifest is cleaned up.
+    let orphan = dir.path().join("events.lsm.999.run");
+    std::fs::write(&orphan, b"partial").unwrap();
+
+    // The engine comes from the manifest at the table's path.
+    let mut table = Table::open(&path).unwrap();
+    assert!(!orphan.exists());
+    assert_eq!(table.engine(), Engine::Lsm);
+    assert_eq!((table.keysize(), table.recordsize()), (8, 8));
+    assert_eq!(table.count(), 1049);
+    assert_eq!(table.search_record(&key(3)).unwrap(), None);
+    assert_eq!(table.search_record(&key(1040)).unwrap(), Some(padded(b"changed", 8)));
+    assert_eq!(table.search_record(&key(1049)).unwrap(), Some(padded(b"v", 8)));
+    table.verify().unwrap();
+
+    for i in (0..500).filter(|&i| i != 3) {
+        table.delete_record(&key(i)).unwrap();
+    }
+    drop(table);
+    let mut table = Table::open(&path).unwrap();
+    assert_eq!(table.count(), 550);
+    assert_eq!(table.min_key().unwrap(), Some(key(500)));
+    table.verify().unwrap();
+    drop(table);
+
+    let mut wrong = TableOptions::new();
+    wrong.key_size(4);
+    assert!(matches!(wrong.open(&path), Err(Error::InvalidOptions(_))));
+    let mut compressed = options.clone();
+    compressed.compression(Compression::Lz4);
+    assert!(matches!(compressed.open(dir.path().join("lz4.lsm")), Err(Error::InvalidOptions(_))));
+    let storage = || Box::new(rustdb::MemStorage::new());
+    assert!(matches!(options.open_with_storage(storage(), storage()), Err(Error::InvalidOptions(_))));
+}
+
+#[test]
+fn lsm_tables_replay_a_batch_whole_or_not_at_all() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("batches.lsm");
+    let wal = dir.path().join("batches.lsm.wal");
+    let mut options = TableOptions::new();
+    options.key_size(4).record_size(8).engine(Engine::Lsm);
+
+    let write = |keys: [&[u8]; 3]| {
+        let mut table = Table::open(&path).unwrap();
+        let mut batch = WriteBatch::new();
+        for key in keys {
+            batch.put(key, b"batched");
+        }
+        table.write_batch(&batch).unwrap();
+        // Leaves the WAL as the crash did.
+        std::mem::forget(table);
+    };
+
+    let mut table = options.open(&path).unwrap();
+    table.add_record(b"solo", b"single").unwrap();
+    drop(table);
+
+    // An append cut short by a crash loses the whole batch, not just its tail.
+    write([b"aaaa", b"bbbb", b"cccc"]);
+    let len = std::fs::metadata(&wal).unwrap().len();
+    std::fs::OpenOptions::new().write(true).open(&wal).unwrap().set_len(len - 5).unwrap();
+    let mut table = Table::open(&path).unwrap();
+    assert_eq!(table.count(), 1);
+    assert_eq!(table.search_record(b"solo").unwrap(), Some(padded(b"single", 8)));
+    assert_eq!(table.search_record(b"aaaa").unwrap(), None);
+    drop(table);
+
+    // So does one whose bytes did not all reach the disk.
+    write([b"dddd", b"eeee", b"ffff"]);
+    let mut log = std::fs::read(&wal).unwrap();
+    let len = log.len();
+    log[len - 20] ^= 0xff;
+    std::fs::write(&wal, &log).unwrap();
+    let mut table = Table::open(&path).unwrap();
+    assert_eq!(table.count(), 1);
+    assert_eq!(table.search_record(b"dddd").unwrap(), None);
+
+    // The log was cut back to the last whole batch, so new writes replay after it.
+    table.add_record(b"next", b"after").unwrap();
+    std::mem::forget(table);
+    let mut table = Table::open(&path).unwrap();
+    assert_eq!(table.count(), 2);
+    assert_eq!(table.search_record(b"next").unwrap(), Some(padded(b"after", 8)));
+    table.verify().unwrap();
+}